@@ -0,0 +1,248 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix_web::{web, HttpResponse, Error, HttpRequest};
+use actix_multipart::Multipart;
+use futures_util::{StreamExt, TryStreamExt};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::env;
+use serde::{Deserialize, Serialize};
+use bytes::BytesMut;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use uuid::Uuid;
+
+use crate::services::{NotificationManager, ClipboardService};
+use crate::notifications::ConfirmationNotification;
+use crate::utils::auth::{AuthIdentity, Scope};
+use crate::utils::network::ClientIp;
+use crate::utils::config::ServerConfig;
+use crate::utils::constants::NOTIFICATION_ASSETS_DIR;
+
+#[derive(Default)]
+struct ClipboardUpload {
+    text: Option<String>,
+    html: Option<String>,
+    image_path: Option<String>,
+    file_paths: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ClipboardQuery {
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ClipboardContent {
+    text: Option<String>,
+    /// Base64 encoded PNG
+    image: Option<String>,
+    files: Option<Vec<String>>,
+}
+
+pub(super) async fn read_text_field(field: &mut actix_multipart::Field, name: &str) -> Result<String, Error> {
+    let mut content = Vec::new();
+    while let Ok(Some(chunk)) = field.try_next().await {
+        content.extend_from_slice(&chunk);
+    }
+    String::from_utf8(content).map_err(|e| {
+        log::error!("Invalid UTF-8 in {}: {}", name, e);
+        actix_web::error::ErrorBadRequest(format!("Invalid {} encoding", name))
+    })
+}
+
+pub(super) async fn save_file_field(field: &mut actix_multipart::Field, file_path: &PathBuf) -> Result<(), Error> {
+    let mut file = fs::File::create(file_path)
+        .map_err(|e| {
+            log::error!("Failed to create file: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to create file")
+        })?;
+
+    while let Ok(Some(chunk)) = field.try_next().await {
+        file.write_all(&chunk)
+            .map_err(|e| {
+                log::error!("Failed to write file chunk: {}", e);
+                actix_web::error::ErrorInternalServerError("Failed to save file")
+            })?;
+    }
+    Ok(())
+}
+
+async fn handle_multipart(mut payload: Multipart, temp_dir: PathBuf) -> Result<ClipboardUpload, Error> {
+    let mut upload = ClipboardUpload::default();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_disposition = field.content_disposition().clone();
+        let name = content_disposition.get_name().unwrap_or("");
+
+        match name {
+            "text" => upload.text = Some(read_text_field(&mut field, "text").await?),
+            "html" => upload.html = Some(read_text_field(&mut field, "html").await?),
+            "image" => {
+                if let Some(filename) = content_disposition.get_filename() {
+                    let file_ext = PathBuf::from(filename).extension()
+                        .and_then(|ext| ext.to_str())
+                        .unwrap_or("png")
+                        .to_string();
+                    let file_path = temp_dir.join(format!("image.{}", file_ext));
+                    save_file_field(&mut field, &file_path).await?;
+                    upload.image_path = Some(file_path.to_string_lossy().into_owned());
+                }
+            },
+            "files" => {
+                if let Some(filename) = content_disposition.get_filename() {
+                    // Only the final component is kept so uploads cannot escape the clipboard dir
+                    let filename = Path::new(filename)
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .filter(|name| !name.is_empty())
+                        .ok_or_else(|| actix_web::error::ErrorBadRequest("Invalid filename"))?;
+                    let file_path = temp_dir.join(filename);
+                    save_file_field(&mut field, &file_path).await?;
+                    upload.file_paths.push(file_path.to_string_lossy().into_owned());
+                }
+            },
+            _ => {
+                log::warn!("Unexpected field: {}", name);
+            }
+        }
+    }
+
+    Ok(upload)
+}
+
+pub async fn set_clipboard(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    config: web::Data<ServerConfig>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Clipboard)?;
+    if !config.clipboard.allow_write {
+        return Ok(HttpResponse::Forbidden().body("Remote clipboard writes are disabled"));
+    }
+
+    // A dir per request, so concurrent writes cannot remove each other's files
+    let temp_dir = env::temp_dir().join(NOTIFICATION_ASSETS_DIR).join(format!("clipboard_{}", Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)
+        .map_err(|e| {
+            log::error!("Failed to create temp directory: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to create temp directory")
+        })?;
+
+    let content_type = req.headers()
+        .get("content-type")
+        .and_then(|ct| ct.to_str().ok())
+        .unwrap_or("");
+
+    let upload = if content_type.starts_with("multipart/form-data") {
+        handle_multipart(Multipart::new(req.headers(), payload), temp_dir).await?
+    } else {
+        // Any other body is treated as plain text
+        let mut body = BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            body.extend_from_slice(&chunk?);
+        }
+        let text = String::from_utf8(body.to_vec())
+            .map_err(|_| actix_web::error::ErrorBadRequest("Invalid text encoding"))?;
+        ClipboardUpload { text: Some(text), ..Default::default() }
+    };
+
+    // Only one kind of content is placed on the clipboard, richest first
+    let result = if !upload.file_paths.is_empty() {
+        ClipboardService::set_files(&upload.file_paths)
+    } else if let Some(image_path) = &upload.image_path {
+        ClipboardService::set_image(image_path)
+    } else if let Some(html) = &upload.html {
+        ClipboardService::set_html(html, upload.text.as_deref().unwrap_or(html))
+    } else if let Some(text) = &upload.text {
+        ClipboardService::set_text(text)
+    } else {
+        return Ok(HttpResponse::BadRequest().body("No clipboard content provided"));
+    };
+
+    match result {
+        Ok(_) => Ok(HttpResponse::Ok().body("Clipboard updated successfully")),
+        Err(e) => {
+            log::error!("Failed to set clipboard: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to set clipboard: {}", e)))
+        }
+    }
+}
+
+pub async fn get_clipboard(
+    req: HttpRequest,
+    query: web::Query<ClipboardQuery>,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Clipboard)?;
+    if !config.clipboard.allow_read {
+        return Ok(HttpResponse::Forbidden().body("Remote clipboard reads are disabled"));
+    }
+
+    // Never hand out the clipboard to anonymous remote clients
+    if identity.is_anonymous() {
+        return Ok(HttpResponse::Forbidden().body("Remote clipboard reads require authentication"));
+    }
+
+    if config.clipboard.confirm_read {
+        let remote = ClientIp::from_request(&req)
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let confirmation = ConfirmationNotification {
+            title: "Clipboard access requested".to_string(),
+            message: format!("{} ({}) wants to read your clipboard", identity.name, remote),
+        };
+
+        // The lock must not be held while waiting for the user
+        let receiver = {
+            let mut manager = manager.lock().unwrap();
+            manager.request_confirmation(&confirmation)
+        };
+        let receiver = match receiver {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                log::error!("Failed to show confirmation: {}", e);
+                return Ok(HttpResponse::InternalServerError().body(format!("Failed to show confirmation: {}", e)));
+            }
+        };
+
+        let timeout = Duration::from_secs(config.clipboard.confirm_timeout_secs);
+        let allowed = matches!(tokio::time::timeout(timeout, receiver).await, Ok(Ok(true)));
+        if !allowed {
+            log::warn!("Clipboard read from {} was not confirmed", remote);
+            return Ok(HttpResponse::Forbidden().body("Clipboard read was not confirmed"));
+        }
+    }
+
+    let to_error = |e: anyhow::Error| {
+        log::error!("Failed to read clipboard: {}", e);
+        actix_web::error::ErrorInternalServerError(format!("Failed to read clipboard: {}", e))
+    };
+
+    match query.format.as_deref() {
+        Some("text") => match ClipboardService::get_text().map_err(to_error)? {
+            Some(text) => Ok(HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(text)),
+            None => Ok(HttpResponse::NoContent().finish()),
+        },
+        Some("image") => match ClipboardService::get_image_png().map_err(to_error)? {
+            Some(png) => Ok(HttpResponse::Ok().content_type("image/png").body(png)),
+            None => Ok(HttpResponse::NoContent().finish()),
+        },
+        Some("files") => match ClipboardService::get_files().map_err(to_error)? {
+            Some(files) => Ok(HttpResponse::Ok().json(files)),
+            None => Ok(HttpResponse::NoContent().finish()),
+        },
+        Some(other) => Ok(HttpResponse::BadRequest().body(format!("Unknown clipboard format: {}", other))),
+        None => {
+            let content = ClipboardContent {
+                text: ClipboardService::get_text().map_err(to_error)?,
+                image: ClipboardService::get_image_png().map_err(to_error)?.map(|png| STANDARD.encode(png)),
+                files: ClipboardService::get_files().map_err(to_error)?,
+            };
+            Ok(HttpResponse::Ok().json(content))
+        }
+    }
+}
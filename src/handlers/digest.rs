@@ -0,0 +1,78 @@
+use std::sync::{Arc, Mutex};
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+
+use crate::services::NotificationManager;
+use crate::services::digest::DigestBatch;
+use crate::utils::network::ClientIp;
+
+const PAGE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body { font-family: "Segoe UI", sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 0.4em 0.8em; border-bottom: 1px solid #ddd; vertical-align: top; }
+td.message { white-space: pre-wrap; }
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<table>
+<tr><th>Received</th><th>Priority</th><th>Sender</th><th>Title</th><th>Message</th></tr>
+{rows}
+</table>
+{overflow}</body>
+</html>"#;
+
+fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn render(batch: &DigestBatch) -> String {
+    let rows: String = batch.items.iter()
+        .map(|item| format!(
+            "<tr><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td><td class=\"message\">{}</td></tr>\n",
+            item.received.format("%Y-%m-%d %H:%M:%S"),
+            item.priority,
+            escape_html(item.sender.as_deref().unwrap_or("")),
+            escape_html(&item.title),
+            escape_html(&item.message),
+        ))
+        .collect();
+    let overflow = match batch.overflow {
+        0 => String::new(),
+        count => format!("<p>{} more notifications were not kept.</p>\n", count),
+    };
+    let title = format!("{} ({} notifications)", batch.name, batch.total());
+
+    PAGE_TEMPLATE
+        .replace("{title}", &escape_html(&title))
+        .replace("{rows}", &rows)
+        .replace("{overflow}", &overflow)
+}
+
+/// The "view all" page linked from a digest summary toast. The browser opening it sends
+/// no credentials, so it is only served to this machine and found by the batch's random id.
+pub async fn get_digest(
+    req: HttpRequest,
+    path: web::Path<String>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+) -> Result<HttpResponse, Error> {
+    if !ClientIp::from_request(&req).is_some_and(|ip| ip.is_loopback()) {
+        return Ok(HttpResponse::Forbidden().body("Digest pages are only served to this machine"));
+    }
+
+    let manager = manager.lock().unwrap();
+    match manager.digest_batch(&path) {
+        Some(batch) => Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(render(batch))),
+        None => Ok(HttpResponse::NotFound().body("Digest not found or expired")),
+    }
+}
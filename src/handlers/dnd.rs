@@ -0,0 +1,72 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix_web::{web, HttpResponse, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::services::NotificationManager;
+use crate::utils::auth::{AuthIdentity, Scope};
+
+/// Longest timed Do Not Disturb, a year
+const MAX_DND_MINUTES: u64 = 366 * 24 * 60;
+
+#[derive(Deserialize)]
+pub struct DndForm {
+    enabled: bool,
+    /// Switch Do Not Disturb off again after this many minutes
+    #[serde(default)]
+    minutes: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct DndStatus {
+    dnd: bool,
+    /// Local time a timed Do Not Disturb ends
+    dnd_until: Option<String>,
+    quiet_hours: bool,
+    queued: usize,
+}
+
+fn status(manager: &NotificationManager) -> DndStatus {
+    let quiet_hours = manager.quiet_hours();
+    DndStatus {
+        dnd: quiet_hours.is_dnd_active(),
+        dnd_until: quiet_hours.dnd_until().map(|until| until.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        quiet_hours: quiet_hours.is_scheduled_quiet(),
+        queued: manager.queued_count(),
+    }
+}
+
+pub async fn get_dnd(
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Admin)?;
+    let manager = manager.lock().unwrap();
+    Ok(HttpResponse::Ok().json(status(&manager)))
+}
+
+pub async fn set_dnd(
+    form: web::Form<DndForm>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Admin)?;
+
+    let duration = match form.minutes {
+        Some(minutes) if minutes > MAX_DND_MINUTES => {
+            return Ok(HttpResponse::BadRequest().body(format!("minutes must be at most {}", MAX_DND_MINUTES)));
+        }
+        minutes => minutes.map(|minutes| Duration::from_secs(minutes * 60)),
+    };
+    log::info!("{} switched Do Not Disturb {} (minutes: {:?})",
+        identity.name, if form.enabled { "on" } else { "off" }, form.minutes);
+
+    let mut manager = manager.lock().unwrap();
+    if let Err(e) = manager.quiet_hours_mut().set_dnd(form.enabled, duration) {
+        return Ok(HttpResponse::BadRequest().body(e.to_string()));
+    }
+    if let Err(e) = manager.flush_quiet_queue().await {
+        log::error!("Failed to deliver queued notifications: {}", e);
+    }
+    Ok(HttpResponse::Ok().json(status(&manager)))
+}
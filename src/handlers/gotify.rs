@@ -0,0 +1,229 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use actix_web::{web, HttpResponse, Error, HttpRequest};
+use actix_multipart::Multipart;
+use futures_util::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use bytes::BytesMut;
+
+use crate::services::NotificationManager;
+use crate::notifications::{NotificationRequest, DefaultAction, Priority};
+use crate::utils::auth::{AuthIdentity, Scope};
+use super::clipboard::read_text_field;
+
+/// Ids handed out in responses, Gotify clients expect every message to have one
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A message as posted to Gotify's `POST /message`
+#[derive(Debug, Default, Deserialize)]
+pub struct GotifyMessage {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub priority: Option<i64>,
+    #[serde(default)]
+    pub extras: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct GotifyResponse {
+    id: u64,
+    appid: u64,
+    title: String,
+    message: String,
+    priority: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<Value>,
+    date: String,
+}
+
+/// Gotify priorities run from 0 to 10, with 8 and up meant to interrupt
+pub fn priority_from_gotify(priority: i64) -> Priority {
+    match priority {
+        i64::MIN..=0 => Priority::Min,
+        1..=3 => Priority::Low,
+        4..=7 => Priority::Normal,
+        8..=9 => Priority::High,
+        _ => Priority::Urgent,
+    }
+}
+
+impl GotifyMessage {
+    pub fn into_request(self, default_title: &str) -> NotificationRequest {
+        let title = self.title
+            .filter(|title| !title.trim().is_empty())
+            .unwrap_or_else(|| default_title.to_string());
+        let mut request = NotificationRequest::new(&title, &self.message);
+        if let Some(priority) = self.priority {
+            request.priority = priority_from_gotify(priority);
+        }
+
+        let click_url = self.extras.as_ref()
+            .and_then(|extras| extras.pointer("/client::notification/click/url"))
+            .and_then(Value::as_str);
+        if let Some(url) = click_url {
+            request.url = Some(url.to_string());
+            request.default_action = Some(vec![DefaultAction::OpenUrl]);
+        }
+        request
+    }
+}
+
+async fn read_form(req: &HttpRequest, mut payload: web::Payload) -> Result<GotifyMessage, Error> {
+    let content_type = req.headers()
+        .get("content-type")
+        .and_then(|ct| ct.to_str().ok())
+        .unwrap_or("");
+
+    if content_type.starts_with("multipart/form-data") {
+        let mut message = GotifyMessage::default();
+        let mut multipart = Multipart::new(req.headers(), payload);
+        while let Ok(Some(mut field)) = multipart.try_next().await {
+            let name = field.content_disposition().get_name().unwrap_or("").to_string();
+            let value = read_text_field(&mut field, &name).await?;
+            match name.as_str() {
+                "title" => message.title = Some(value),
+                "message" => message.message = value,
+                "priority" => message.priority = value.trim().parse().ok(),
+                _ => log::warn!("Unexpected field: {}", name),
+            }
+        }
+        return Ok(message);
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        body.extend_from_slice(&chunk);
+    }
+
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        #[derive(Deserialize)]
+        struct GotifyForm {
+            title: Option<String>,
+            #[serde(default)]
+            message: String,
+            priority: Option<String>,
+        }
+        let form: GotifyForm = serde_urlencoded::from_bytes(&body)
+            .map_err(|_| actix_web::error::ErrorBadRequest("Invalid form data"))?;
+        return Ok(GotifyMessage {
+            title: form.title,
+            message: form.message,
+            priority: form.priority.and_then(|priority| priority.trim().parse().ok()),
+            extras: None,
+        });
+    }
+
+    serde_json::from_slice(&body).map_err(|e| {
+        log::error!("Invalid Gotify message: {}", e);
+        actix_web::error::ErrorBadRequest(format!("Invalid JSON: {}", e))
+    })
+}
+
+/// Gotify-compatible `POST /message`, so tools that push to Gotify can notify this desktop
+pub async fn gotify_message(
+    req: HttpRequest,
+    payload: web::Payload,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+
+    let message = read_form(&req, payload).await?;
+    if message.message.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("message is required"));
+    }
+
+    let default_title = identity.sender.clone().unwrap_or_else(|| identity.name.clone());
+    let response = GotifyResponse {
+        id: NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed),
+        appid: 1,
+        title: message.title.clone().unwrap_or_else(|| default_title.clone()),
+        message: message.message.clone(),
+        priority: message.priority.unwrap_or(0),
+        extras: message.extras.clone(),
+        date: chrono::Local::now().to_rfc3339(),
+    };
+
+    let mut request = message.into_request(&default_title);
+    request.sender = identity.sender.clone();
+
+    let mut manager = manager.lock().unwrap();
+    match manager.send_notification(request).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            log::error!("Failed to send notification: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to send notification: {}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> GotifyMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn priorities_follow_gotify_ranges() {
+        let expected = [
+            (-1, Priority::Min),
+            (0, Priority::Min),
+            (1, Priority::Low),
+            (3, Priority::Low),
+            (4, Priority::Normal),
+            (7, Priority::Normal),
+            (8, Priority::High),
+            (9, Priority::High),
+            (10, Priority::Urgent),
+            (100, Priority::Urgent),
+        ];
+        for (gotify, priority) in expected {
+            assert_eq!(priority_from_gotify(gotify), priority, "gotify priority {}", gotify);
+        }
+    }
+
+    #[test]
+    fn uptime_kuma_alert() {
+        let request = fixture(include_str!("../../tests/fixtures/gotify/uptime_kuma_down.json"))
+            .into_request("monitoring");
+
+        assert_eq!(request.title, "Uptime-Kuma");
+        assert_eq!(request.message, "[My Website] [🔴 Down] connect ECONNREFUSED 127.0.0.1:8080");
+        assert_eq!(request.priority, Priority::High);
+        assert!(request.url.is_none());
+        assert!(request.default_action.is_none());
+    }
+
+    #[test]
+    fn click_url_extra_opens_the_url() {
+        let request = fixture(include_str!("../../tests/fixtures/gotify/home_assistant_click.json"))
+            .into_request("home-assistant");
+
+        assert_eq!(request.title, "Front door");
+        assert_eq!(request.priority, Priority::Normal);
+        assert_eq!(request.url.as_deref(), Some("https://homeassistant.local:8123/lovelace/security"));
+        assert!(matches!(request.default_action.as_deref(), Some([DefaultAction::OpenUrl])));
+        // Toasts are plain text, so markdown content is shown as sent
+        assert_eq!(request.message, "**Front door** was opened at 18:02");
+    }
+
+    #[test]
+    fn display_extra_alone_changes_nothing() {
+        let request = fixture(include_str!("../../tests/fixtures/gotify/watchtower_update.json"))
+            .into_request("watchtower");
+
+        // An empty title falls back to the sender
+        assert_eq!(request.title, "watchtower");
+        assert!(request.message.starts_with("Found new image for nginx:latest"));
+        assert_eq!(request.priority, Priority::Min);
+        assert!(request.url.is_none());
+        assert!(request.default_action.is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use actix_web::{web, HttpResponse, Error};
+use serde::Deserialize;
+
+use crate::services::NotificationManager;
+use crate::notifications::{NotificationRequest, NotificationLink, DefaultAction, Priority};
+use crate::utils::auth::{AuthIdentity, Scope};
+use crate::utils::config::{AlertmanagerConfig, ServerConfig};
+
+/// Alerts listed in the toast body
+const LISTED_ALERTS: usize = 5;
+
+/// The version 4 webhook payload sent by Alertmanager's `webhook_config`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertmanagerWebhook {
+    #[serde(default)]
+    pub version: String,
+    pub group_key: String,
+    #[serde(default)]
+    pub truncated_alerts: usize,
+    pub status: String,
+    #[serde(default)]
+    pub receiver: String,
+    #[serde(default)]
+    pub group_labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub common_labels: BTreeMap<String, String>,
+    #[serde(default, rename = "externalURL")]
+    pub external_url: Option<String>,
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Alert {
+    pub status: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default, rename = "generatorURL")]
+    pub generator_url: Option<String>,
+}
+
+impl Alert {
+    fn is_firing(&self) -> bool {
+        self.status == "firing"
+    }
+
+    /// One line for the toast body, preferring the summary annotation
+    fn describe(&self) -> String {
+        let text = ["summary", "description", "message"].iter()
+            .find_map(|name| self.annotations.get(*name))
+            .or_else(|| self.labels.get("alertname"))
+            .cloned()
+            .unwrap_or_else(|| "Alert".to_string());
+        let text = match self.labels.get("instance") {
+            Some(instance) => format!("{} ({})", text, instance),
+            None => text,
+        };
+        if self.is_firing() { text } else { format!("Resolved: {}", text) }
+    }
+}
+
+impl AlertmanagerWebhook {
+    fn is_firing(&self) -> bool {
+        self.status == "firing"
+    }
+
+    /// The highest priority among the firing alerts' severities, or the resolved priority
+    fn priority(&self, config: &AlertmanagerConfig) -> Priority {
+        if !self.is_firing() {
+            return config.resolved_priority;
+        }
+        self.alerts.iter()
+            .filter(|alert| alert.is_firing())
+            .map(|alert| alert.labels.get(&config.severity_label)
+                .or_else(|| self.common_labels.get(&config.severity_label))
+                .and_then(|severity| config.severities.get(&severity.to_lowercase()).copied())
+                .unwrap_or_default())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Titles like Alertmanager's default template, `[FIRING:2] HighLatency api`
+    fn title(&self) -> String {
+        let name = if self.group_labels.is_empty() {
+            self.common_labels.get("alertname").cloned().unwrap_or_else(|| self.receiver.clone())
+        } else {
+            self.group_labels.values().cloned().collect::<Vec<_>>().join(" ")
+        };
+        if self.is_firing() {
+            let firing = self.alerts.iter().filter(|alert| alert.is_firing()).count();
+            format!("[FIRING:{}] {}", firing, name)
+        } else {
+            format!("[RESOLVED] {}", name)
+        }
+    }
+
+    pub fn into_request(self, config: &AlertmanagerConfig) -> NotificationRequest {
+        let mut lines: Vec<String> = self.alerts.iter()
+            .take(LISTED_ALERTS)
+            .map(Alert::describe)
+            .collect();
+        let more = self.alerts.len().saturating_sub(LISTED_ALERTS) + self.truncated_alerts;
+        if more > 0 {
+            lines.push(format!("and {} more", more));
+        }
+
+        let mut request = NotificationRequest::new(&self.title(), &lines.join("\n"));
+        request.priority = self.priority(config);
+        // Every update of a group, including its resolution, replaces the group's toast
+        request.replace_key = Some(format!("alertmanager:{}", self.group_key));
+
+        if let Some(external_url) = self.external_url.filter(|url| !url.is_empty()) {
+            request.url = Some(external_url.clone());
+            request.default_action = Some(vec![DefaultAction::OpenUrl]);
+            request.links.push(NotificationLink { label: "Alertmanager".to_string(), url: external_url });
+        }
+        if let Some(generator_url) = self.alerts.iter().find_map(|alert| alert.generator_url.clone()) {
+            request.links.push(NotificationLink { label: "Source".to_string(), url: generator_url });
+        }
+        request
+    }
+}
+
+/// Receiver for Alertmanager's `webhook_config`, showing one toast per alert group
+pub async fn alertmanager_webhook(
+    body: web::Json<AlertmanagerWebhook>,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+
+    let webhook = body.into_inner();
+    if webhook.version != "4" {
+        log::warn!("Unexpected Alertmanager webhook version {:?}, parsing it as version 4", webhook.version);
+    }
+    log::info!("Alertmanager group {} is {} with {} alert(s)", webhook.group_key, webhook.status, webhook.alerts.len());
+
+    let mut request = webhook.into_request(&config.alertmanager);
+    request.sender = Some(identity.sender.clone().unwrap_or_else(|| "Alertmanager".to_string()));
+
+    let mut manager = manager.lock().unwrap();
+    match manager.send_notification(request).await {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(e) => {
+            log::error!("Failed to send notification: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to send notification: {}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> AlertmanagerWebhook {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn firing() -> AlertmanagerWebhook {
+        fixture(include_str!("../../../tests/fixtures/alertmanager/firing.json"))
+    }
+
+    fn resolved() -> AlertmanagerWebhook {
+        fixture(include_str!("../../../tests/fixtures/alertmanager/resolved.json"))
+    }
+
+    fn alert(status: &str, summary: &str) -> Alert {
+        Alert {
+            status: status.to_string(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::from([("summary".to_string(), summary.to_string())]),
+            generator_url: None,
+        }
+    }
+
+    #[test]
+    fn firing_group() {
+        let request = firing().into_request(&AlertmanagerConfig::default());
+
+        // BTreeMap orders the group labels by name
+        assert_eq!(request.title, "[FIRING:2] HighLatency api");
+        assert_eq!(request.message, [
+            "p99 latency above 500ms (api-1:9100)",
+            "p99 latency above 2s for 10 minutes (api-2:9100)",
+            "Resolved: HighLatency (api-3:9100)",
+        ].join("\n"));
+        // The resolved critical alert does not count
+        assert_eq!(request.priority, Priority::Urgent);
+        assert_eq!(request.url.as_deref(), Some("http://alertmanager.lan:9093"));
+        assert!(matches!(request.default_action.as_deref(), Some([DefaultAction::OpenUrl])));
+        let links: Vec<(&str, &str)> = request.links.iter().map(|link| (link.label.as_str(), link.url.as_str())).collect();
+        assert_eq!(links, [
+            ("Alertmanager", "http://alertmanager.lan:9093"),
+            ("Source", "http://prometheus.lan:9090/graph?g0.expr=latency_p99+%3E+0.5"),
+        ]);
+    }
+
+    #[test]
+    fn resolution_replaces_the_group_toast() {
+        let config = AlertmanagerConfig::default();
+        let firing = firing().into_request(&config);
+        let resolved = resolved().into_request(&config);
+
+        assert_eq!(firing.replace_key.as_deref(), Some(r#"alertmanager:{}:{alertname="HighLatency", job="api"}"#));
+        assert_eq!(resolved.replace_key, firing.replace_key);
+        assert_eq!(resolved.title, "[RESOLVED] HighLatency api");
+        assert_eq!(resolved.message, [
+            "Resolved: p99 latency above 500ms (api-1:9100)",
+            "Resolved: p99 latency above 2s for 10 minutes (api-2:9100)",
+        ].join("\n"));
+        assert_eq!(resolved.priority, Priority::Normal);
+
+        let quiet = AlertmanagerConfig { resolved_priority: Priority::Min, ..AlertmanagerConfig::default() };
+        assert_eq!(self::resolved().into_request(&quiet).priority, Priority::Min);
+    }
+
+    #[test]
+    fn maps_severities() {
+        let mut webhook = firing();
+        for alert in &mut webhook.alerts {
+            alert.labels.remove("severity");
+        }
+        webhook.common_labels.insert("severity".to_string(), "Error".to_string());
+        assert_eq!(webhook.priority(&AlertmanagerConfig::default()), Priority::High);
+
+        webhook.common_labels.insert("severity".to_string(), "unheard-of".to_string());
+        assert_eq!(webhook.priority(&AlertmanagerConfig::default()), Priority::Normal);
+
+        let config = AlertmanagerConfig { severity_label: "job".to_string(), ..AlertmanagerConfig::default() };
+        let severities = std::collections::HashMap::from([("api".to_string(), Priority::Low)]);
+        assert_eq!(firing().priority(&AlertmanagerConfig { severities, ..config }), Priority::Low);
+    }
+
+    #[test]
+    fn lists_a_limited_number_of_alerts() {
+        let mut webhook = firing();
+        webhook.alerts = (1..=7).map(|n| alert("firing", &format!("Alert {}", n))).collect();
+        webhook.truncated_alerts = 3;
+        let request = webhook.into_request(&AlertmanagerConfig::default());
+
+        assert_eq!(request.title, "[FIRING:7] HighLatency api");
+        let lines: Vec<&str> = request.message.lines().collect();
+        assert_eq!(lines.len(), LISTED_ALERTS + 1);
+        assert_eq!(lines[0], "Alert 1");
+        assert_eq!(lines[LISTED_ALERTS], "and 5 more");
+    }
+
+    #[test]
+    fn titles_without_group_labels() {
+        let mut webhook = firing();
+        webhook.group_labels.clear();
+        assert_eq!(webhook.title(), "[FIRING:2] HighLatency");
+
+        webhook.common_labels.clear();
+        assert_eq!(webhook.title(), "[FIRING:2] desktop");
+
+        webhook.external_url = Some(String::new());
+        webhook.alerts.iter_mut().for_each(|alert| alert.generator_url = None);
+        let request = webhook.into_request(&AlertmanagerConfig::default());
+        assert_eq!(request.url, None);
+        assert!(request.links.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use std::sync::{Arc, Mutex};
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorUnauthorized};
+use serde_json::Value;
+
+use crate::services::NotificationManager;
+use crate::notifications::Priority;
+use crate::utils::auth::{AuthIdentity, Scope};
+use crate::utils::config::{ForgeWebhookConfig, ServerConfig};
+use crate::utils::signature::verify_body_signature;
+use super::{ci_toast, excerpt, is_event_enabled, is_mentioned, is_user, number, parse_payload, send_event, text, EventToast};
+
+const EVENT_HEADER: &str = "x-github-event";
+const SIGNATURE_HEADER: &str = "x-hub-signature-256";
+/// Events shown when `events` is not configured
+const DEFAULT_EVENTS: &[&str] = &[
+    "pull_request",
+    "pull_request_review",
+    "pull_request_review_comment",
+    "issue_comment",
+    "issues",
+    "workflow_run",
+    "release",
+];
+/// Commits listed for a push
+const LISTED_COMMITS: usize = 5;
+
+/// Receiver for GitHub webhooks, verified with the `[github]` secret
+pub async fn github_webhook(
+    req: HttpRequest,
+    body: web::Bytes,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    let github = &config.github;
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+
+    let secret = github.secret().map_err(|e| {
+        log::error!("GitHub webhook secret is unavailable: {}", e);
+        ErrorInternalServerError("Webhook secret is unavailable")
+    })?;
+    match secret {
+        Some(secret) => {
+            let signature = header(SIGNATURE_HEADER).ok_or_else(|| ErrorUnauthorized("Missing X-Hub-Signature-256 header"))?;
+            if !verify_body_signature(secret.as_bytes(), signature, &body) {
+                log::warn!("Rejected GitHub webhook with an invalid signature");
+                return Err(ErrorUnauthorized("Invalid signature"));
+            }
+        }
+        None => identity.require(Scope::Notify)?,
+    }
+
+    let event = header(EVENT_HEADER).ok_or_else(|| ErrorBadRequest("Missing X-GitHub-Event header"))?;
+    if event == "ping" {
+        return Ok(HttpResponse::Ok().body("pong"));
+    }
+    if !is_event_enabled(github, DEFAULT_EVENTS, event) {
+        log::debug!("Ignoring GitHub {} event", event);
+        return Ok(HttpResponse::Ok().body("Event ignored"));
+    }
+
+    let payload = parse_payload(&req, &body)?;
+    let toast = match render(event, &payload, github) {
+        Some(toast) => toast,
+        None => return Ok(HttpResponse::Ok().body("Event ignored")),
+    };
+    log::info!("GitHub {} event: {}", event, toast.title);
+
+    let sender = identity.sender.clone().unwrap_or_else(|| "GitHub".to_string());
+    send_event(&manager, toast, sender).await
+}
+
+/// The toast for an event, `None` for actions that are not worth one
+fn render(event: &str, payload: &Value, config: &ForgeWebhookConfig) -> Option<EventToast> {
+    let repo = text(payload, "/repository/full_name");
+    let actor = text(payload, "/sender/login");
+    let action = text(payload, "/action");
+
+    match event {
+        "workflow_run" => {
+            if action != "completed" {
+                return None;
+            }
+            let message = format!("{}: {}", repo, excerpt(text(payload, "/workflow_run/head_commit/message")));
+            let mut toast = ci_toast(
+                config,
+                text(payload, "/workflow_run/conclusion"),
+                text(payload, "/workflow_run/head_branch"),
+                text(payload, "/workflow_run/name"),
+                message,
+                Some(text(payload, "/workflow_run/html_url").to_string()),
+            )?;
+            toast.replace_key = Some(format!("github:workflow_run:{}", number(payload, "/workflow_run/id")));
+            Some(toast)
+        }
+        "check_run" => {
+            if action != "completed" {
+                return None;
+            }
+            ci_toast(
+                config,
+                text(payload, "/check_run/conclusion"),
+                text(payload, "/check_run/check_suite/head_branch"),
+                text(payload, "/check_run/name"),
+                repo.to_string(),
+                Some(text(payload, "/check_run/html_url").to_string()),
+            )
+        }
+        "pull_request" => {
+            let verb = match action {
+                "opened" => "opened",
+                "reopened" => "reopened",
+                "ready_for_review" => "marked ready for review",
+                "closed" if payload.pointer("/pull_request/merged") == Some(&Value::Bool(true)) => "merged",
+                "closed" => "closed",
+                "review_requested" => "requested a review on",
+                _ => return None,
+            };
+            let mut toast = EventToast::new(
+                format!("{} {} #{}: {}", actor, verb, number(payload, "/pull_request/number"), text(payload, "/pull_request/title")),
+                repo.to_string(),
+                Some(text(payload, "/pull_request/html_url").to_string()),
+            );
+            // With `mention` set, only review requests for that user are shown
+            if action == "review_requested" && config.mention.is_some() {
+                if !is_user(config, text(payload, "/requested_reviewer/login")) {
+                    return None;
+                }
+                toast.priority = Priority::High;
+            }
+            Some(toast)
+        }
+        "pull_request_review" => {
+            if action != "submitted" {
+                return None;
+            }
+            let verb = match text(payload, "/review/state").to_lowercase().as_str() {
+                "approved" => "approved",
+                "changes_requested" => "requested changes on",
+                _ => "reviewed",
+            };
+            let body = text(payload, "/review/body");
+            let mut toast = EventToast::new(
+                format!("{} {} #{}: {}", actor, verb, number(payload, "/pull_request/number"), text(payload, "/pull_request/title")),
+                if body.is_empty() { repo.to_string() } else { excerpt(body) },
+                Some(text(payload, "/review/html_url").to_string()),
+            );
+            if is_mentioned(config, body) || is_user(config, text(payload, "/pull_request/user/login")) {
+                toast.priority = Priority::High;
+            }
+            Some(toast)
+        }
+        "issue_comment" | "pull_request_review_comment" | "commit_comment" => {
+            if action != "created" {
+                return None;
+            }
+            let body = text(payload, "/comment/body");
+            let subject = match payload.get("issue").or_else(|| payload.get("pull_request")) {
+                Some(issue) => format!("#{}: {}", number(issue, "/number"), text(issue, "/title")),
+                None => format!("commit {}", text(payload, "/comment/commit_id").chars().take(7).collect::<String>()),
+            };
+            let mentioned = is_mentioned(config, body);
+            let verb = if mentioned { "mentioned you on" } else { "commented on" };
+            let mut toast = EventToast::new(
+                format!("{} {} {}", actor, verb, subject),
+                excerpt(body),
+                Some(text(payload, "/comment/html_url").to_string()),
+            );
+            if mentioned {
+                toast.priority = Priority::High;
+            }
+            Some(toast)
+        }
+        "issues" => {
+            if !matches!(action, "opened" | "closed" | "reopened") {
+                return None;
+            }
+            Some(EventToast::new(
+                format!("{} {} issue #{}: {}", actor, action, number(payload, "/issue/number"), text(payload, "/issue/title")),
+                repo.to_string(),
+                Some(text(payload, "/issue/html_url").to_string()),
+            ))
+        }
+        "push" => {
+            let commits = payload.get("commits").and_then(Value::as_array).cloned().unwrap_or_default();
+            if commits.is_empty() {
+                return None;
+            }
+            let branch = text(payload, "/ref").trim_start_matches("refs/heads/");
+            let plural = if commits.len() == 1 { "" } else { "s" };
+            let mut lines: Vec<String> = commits.iter()
+                .take(LISTED_COMMITS)
+                .map(|commit| excerpt(text(commit, "/message")))
+                .collect();
+            if commits.len() > LISTED_COMMITS {
+                lines.push(format!("and {} more", commits.len() - LISTED_COMMITS));
+            }
+            Some(EventToast::new(
+                format!("{} pushed {} commit{} to {}", text(payload, "/pusher/name"), commits.len(), plural, branch),
+                lines.join("\n"),
+                Some(text(payload, "/compare").to_string()),
+            ))
+        }
+        "release" => {
+            if action != "published" {
+                return None;
+            }
+            Some(EventToast::new(
+                format!("Release {} published", text(payload, "/release/tag_name")),
+                format!("{}: {}", repo, text(payload, "/release/name")),
+                Some(text(payload, "/release/html_url").to_string()),
+            ))
+        }
+        _ => {
+            let title = if action.is_empty() {
+                format!("GitHub {} event", event)
+            } else {
+                format!("GitHub {} event: {}", event, action)
+            };
+            Some(EventToast::new(title, repo.to_string(), Some(text(payload, "/repository/html_url").to_string())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn config(mention: Option<&str>, ci_success: bool) -> ForgeWebhookConfig {
+        ForgeWebhookConfig {
+            mention: mention.map(str::to_string),
+            ci_success,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn failed_workflow_run() {
+        let payload = fixture(include_str!("../../../tests/fixtures/github/workflow_run_failure.json"));
+        let toast = render("workflow_run", &payload, &config(None, false)).unwrap();
+
+        assert_eq!(toast.title, "Build failed on main: CI");
+        assert_eq!(toast.message, "octo-org/hello-world: Fix flaky login test");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url.as_deref(), Some("https://github.com/octo-org/hello-world/actions/runs/9284751023"));
+        assert_eq!(toast.replace_key.as_deref(), Some("github:workflow_run:9284751023"));
+    }
+
+    #[test]
+    fn successful_workflow_run_only_with_ci_success() {
+        let payload = fixture(include_str!("../../../tests/fixtures/github/workflow_run_success.json"));
+        assert!(render("workflow_run", &payload, &config(None, false)).is_none());
+
+        let toast = render("workflow_run", &payload, &config(None, true)).unwrap();
+        assert_eq!(toast.title, "Build passed on feature/login: CI");
+        assert_eq!(toast.priority, Priority::Low);
+    }
+
+    #[test]
+    fn comment_mentioning_the_user() {
+        let payload = fixture(include_str!("../../../tests/fixtures/github/issue_comment_mention.json"));
+
+        let toast = render("issue_comment", &payload, &config(Some("mona-lisa"), false)).unwrap();
+        assert_eq!(toast.title, "octocat mentioned you on #1347: Login page times out on slow connections");
+        assert_eq!(toast.message, "Thanks for the report!");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url.as_deref(), Some("https://github.com/octo-org/hello-world/issues/1347#issuecomment-1234567"));
+
+        let toast = render("issue_comment", &payload, &config(Some("mona"), false)).unwrap();
+        assert_eq!(toast.title, "octocat commented on #1347: Login page times out on slow connections");
+        assert_eq!(toast.priority, Priority::Normal);
+    }
+
+    #[test]
+    fn review_requests_for_others_are_skipped_with_mention() {
+        let payload = fixture(include_str!("../../../tests/fixtures/github/pull_request_review_requested.json"));
+
+        let toast = render("pull_request", &payload, &config(Some("Mona-Lisa"), false)).unwrap();
+        assert_eq!(toast.title, "octocat requested a review on #42: Retry login redirects");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url.as_deref(), Some("https://github.com/octo-org/hello-world/pull/42"));
+
+        assert!(render("pull_request", &payload, &config(Some("hubot"), false)).is_none());
+        let toast = render("pull_request", &payload, &config(None, false)).unwrap();
+        assert_eq!(toast.priority, Priority::Normal);
+    }
+}
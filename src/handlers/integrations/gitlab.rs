@@ -0,0 +1,254 @@
+use std::sync::{Arc, Mutex};
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorUnauthorized};
+use serde_json::Value;
+
+use crate::services::NotificationManager;
+use crate::notifications::Priority;
+use crate::utils::auth::{constant_time_eq, AuthIdentity, Scope};
+use crate::utils::config::{ForgeWebhookConfig, ServerConfig};
+use super::{ci_toast, excerpt, is_event_enabled, is_mentioned, is_user, number, send_event, text, EventToast};
+
+const TOKEN_HEADER: &str = "x-gitlab-token";
+/// Events shown when `events` is not configured, by the payload's `object_kind`
+const DEFAULT_EVENTS: &[&str] = &["merge_request", "note", "issue", "pipeline", "release"];
+/// Commits listed for a push
+const LISTED_COMMITS: usize = 5;
+
+/// Receiver for GitLab webhooks, verified with the `[gitlab]` secret token
+pub async fn gitlab_webhook(
+    req: HttpRequest,
+    body: web::Bytes,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    let gitlab = &config.gitlab;
+
+    let secret = gitlab.secret().map_err(|e| {
+        log::error!("GitLab webhook secret is unavailable: {}", e);
+        ErrorInternalServerError("Webhook secret is unavailable")
+    })?;
+    match secret {
+        Some(secret) => {
+            let token = req.headers().get(TOKEN_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| ErrorUnauthorized("Missing X-Gitlab-Token header"))?;
+            if !constant_time_eq(token, &secret) {
+                log::warn!("Rejected GitLab webhook with an invalid token");
+                return Err(ErrorUnauthorized("Invalid token"));
+            }
+        }
+        None => identity.require(Scope::Notify)?,
+    }
+
+    let payload: Value = serde_json::from_slice(&body)
+        .map_err(|e| ErrorBadRequest(format!("Invalid payload: {}", e)))?;
+    let event = text(&payload, "/object_kind");
+    if !is_event_enabled(gitlab, DEFAULT_EVENTS, event) {
+        log::debug!("Ignoring GitLab {} event", event);
+        return Ok(HttpResponse::Ok().body("Event ignored"));
+    }
+
+    let toast = match render(event, &payload, gitlab) {
+        Some(toast) => toast,
+        None => return Ok(HttpResponse::Ok().body("Event ignored")),
+    };
+    log::info!("GitLab {} event: {}", event, toast.title);
+
+    let sender = identity.sender.clone().unwrap_or_else(|| "GitLab".to_string());
+    send_event(&manager, toast, sender).await
+}
+
+/// The toast for an event, `None` for actions that are not worth one
+fn render(event: &str, payload: &Value, config: &ForgeWebhookConfig) -> Option<EventToast> {
+    let project = text(payload, "/project/path_with_namespace");
+    let actor = text(payload, "/user/username");
+    let action = text(payload, "/object_attributes/action");
+
+    match event {
+        "pipeline" => {
+            let failed_jobs: Vec<&str> = payload.get("builds").and_then(Value::as_array)
+                .map(|builds| builds.iter()
+                    .filter(|build| text(build, "/status") == "failed")
+                    .map(|build| text(build, "/name"))
+                    .collect())
+                .unwrap_or_default();
+            let name = if failed_jobs.is_empty() { project.to_string() } else { failed_jobs.join(", ") };
+            let id = number(payload, "/object_attributes/id");
+            let url = match text(payload, "/object_attributes/url") {
+                "" => format!("{}/-/pipelines/{}", text(payload, "/project/web_url"), id),
+                url => url.to_string(),
+            };
+            let mut toast = ci_toast(
+                config,
+                text(payload, "/object_attributes/status"),
+                text(payload, "/object_attributes/ref"),
+                &name,
+                format!("{}: {}", project, excerpt(text(payload, "/commit/message"))),
+                Some(url),
+            )?;
+            toast.replace_key = Some(format!("gitlab:pipeline:{}", id));
+            Some(toast)
+        }
+        "build" => ci_toast(
+            config,
+            text(payload, "/build_status"),
+            text(payload, "/ref"),
+            text(payload, "/build_name"),
+            text(payload, "/project_name").to_string(),
+            Some(format!("{}/-/jobs/{}", text(payload, "/repository/homepage"), number(payload, "/build_id"))),
+        ),
+        "merge_request" => {
+            let verb = match action {
+                "open" => "opened",
+                "reopen" => "reopened",
+                "close" => "closed",
+                "merge" => "merged",
+                "approved" => "approved",
+                "unapproved" => "unapproved",
+                _ => return None,
+            };
+            let mut toast = EventToast::new(
+                format!("{} {} !{}: {}", actor, verb, number(payload, "/object_attributes/iid"), text(payload, "/object_attributes/title")),
+                project.to_string(),
+                Some(text(payload, "/object_attributes/url").to_string()),
+            );
+            let involves_user = ["reviewers", "assignees"].iter()
+                .filter_map(|field| payload.get(*field).and_then(Value::as_array))
+                .flatten()
+                .any(|user| is_user(config, text(user, "/username")));
+            if action == "open" && involves_user {
+                toast.priority = Priority::High;
+            }
+            Some(toast)
+        }
+        "note" => {
+            let body = text(payload, "/object_attributes/note");
+            let subject = match text(payload, "/object_attributes/noteable_type") {
+                "MergeRequest" => format!("!{}: {}", number(payload, "/merge_request/iid"), text(payload, "/merge_request/title")),
+                "Issue" => format!("#{}: {}", number(payload, "/issue/iid"), text(payload, "/issue/title")),
+                "Commit" => format!("commit {}", text(payload, "/commit/id").chars().take(8).collect::<String>()),
+                _ => project.to_string(),
+            };
+            let mentioned = is_mentioned(config, body);
+            let verb = if mentioned { "mentioned you on" } else { "commented on" };
+            let mut toast = EventToast::new(
+                format!("{} {} {}", actor, verb, subject),
+                excerpt(body),
+                Some(text(payload, "/object_attributes/url").to_string()),
+            );
+            if mentioned {
+                toast.priority = Priority::High;
+            }
+            Some(toast)
+        }
+        "issue" => {
+            let verb = match action {
+                "open" => "opened",
+                "reopen" => "reopened",
+                "close" => "closed",
+                _ => return None,
+            };
+            Some(EventToast::new(
+                format!("{} {} issue #{}: {}", actor, verb, number(payload, "/object_attributes/iid"), text(payload, "/object_attributes/title")),
+                project.to_string(),
+                Some(text(payload, "/object_attributes/url").to_string()),
+            ))
+        }
+        "push" => {
+            let commits = payload.get("commits").and_then(Value::as_array).cloned().unwrap_or_default();
+            let total = payload.get("total_commits_count").and_then(Value::as_u64).unwrap_or(commits.len() as u64);
+            if total == 0 {
+                return None;
+            }
+            let branch = text(payload, "/ref").trim_start_matches("refs/heads/");
+            let plural = if total == 1 { "" } else { "s" };
+            let mut lines: Vec<String> = commits.iter()
+                .take(LISTED_COMMITS)
+                .map(|commit| excerpt(text(commit, "/message")))
+                .collect();
+            let listed = lines.len() as u64;
+            if total > listed {
+                lines.push(format!("and {} more", total - listed));
+            }
+            Some(EventToast::new(
+                format!("{} pushed {} commit{} to {}", text(payload, "/user_username"), total, plural, branch),
+                lines.join("\n"),
+                Some(format!("{}/-/commits/{}", text(payload, "/project/web_url"), branch)),
+            ))
+        }
+        "release" => {
+            if text(payload, "/action") != "create" {
+                return None;
+            }
+            Some(EventToast::new(
+                format!("Release {} published", text(payload, "/tag")),
+                format!("{}: {}", project, text(payload, "/name")),
+                Some(text(payload, "/url").to_string()),
+            ))
+        }
+        _ => Some(EventToast::new(
+            format!("GitLab {} event", event),
+            project.to_string(),
+            Some(text(payload, "/project/web_url").to_string()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn config(mention: Option<&str>) -> ForgeWebhookConfig {
+        ForgeWebhookConfig {
+            mention: mention.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn failed_pipeline_lists_failed_jobs() {
+        let payload = fixture(include_str!("../../../tests/fixtures/gitlab/pipeline_failed.json"));
+        let toast = render("pipeline", &payload, &config(None)).unwrap();
+
+        assert_eq!(toast.title, "Build failed on main: unit-tests, integration-tests");
+        assert_eq!(toast.message, "group/api: Bump database driver");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url.as_deref(), Some("https://gitlab.example.com/group/api/-/pipelines/31"));
+        assert_eq!(toast.replace_key.as_deref(), Some("gitlab:pipeline:31"));
+    }
+
+    #[test]
+    fn note_mentioning_the_user() {
+        let payload = fixture(include_str!("../../../tests/fixtures/gitlab/note_mention.json"));
+
+        let toast = render("note", &payload, &config(Some("alice"))).unwrap();
+        assert_eq!(toast.title, "reviewer-bot mentioned you on !7: Add last_login column");
+        assert_eq!(toast.message, "@alice this migration locks the users table, can we batch it?");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url.as_deref(), Some("https://gitlab.example.com/group/api/-/merge_requests/7#note_1244"));
+
+        let toast = render("note", &payload, &config(None)).unwrap();
+        assert_eq!(toast.title, "reviewer-bot commented on !7: Add last_login column");
+        assert_eq!(toast.priority, Priority::Normal);
+    }
+
+    #[test]
+    fn merge_request_for_the_user_is_high_priority() {
+        let payload = fixture(include_str!("../../../tests/fixtures/gitlab/merge_request_open.json"));
+
+        let toast = render("merge_request", &payload, &config(Some("alice"))).unwrap();
+        assert_eq!(toast.title, "bob opened !8: Cache session lookups");
+        assert_eq!(toast.message, "group/api");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url.as_deref(), Some("https://gitlab.example.com/group/api/-/merge_requests/8"));
+
+        let toast = render("merge_request", &payload, &config(Some("carol"))).unwrap();
+        assert_eq!(toast.priority, Priority::Normal);
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+use actix_web::error::ErrorNotFound;
+
+use crate::services::NotificationManager;
+use crate::services::hooks::{Hooks, HookRoute, MappedHook};
+use crate::utils::auth::{AuthIdentity, Scope};
+use super::{download_image, parse_payload};
+
+/// Largest image downloaded for a hook's `image_url`
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+fn find_route<'a>(hooks: &'a Hooks, name: &str) -> Result<&'a HookRoute, Error> {
+    hooks.get(name).ok_or_else(|| {
+        log::warn!("Request for unknown hook {}", name);
+        ErrorNotFound("Unknown hook")
+    })
+}
+
+/// Maps an arbitrary JSON body onto a notification with the `[[hooks]]` route `name`
+pub async fn hook_webhook(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Bytes,
+    hooks: web::Data<Hooks>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+    let route = find_route(&hooks, &name)?;
+    let payload = parse_payload(&req, &body)?;
+
+    let MappedHook { mut request, image_url } = route.apply(&payload, identity.sender.as_deref());
+    match image_url {
+        Some(image_url) if identity.has_scope(Scope::Images) => {
+            match download_image(&image_url, MAX_IMAGE_BYTES).await {
+                Ok(path) => request.image_path = Some(path),
+                Err(e) => log::warn!("Failed to download image {} for hook {}: {}", image_url, name, e),
+            }
+        }
+        Some(_) => log::debug!("Not showing hook {}'s image, {} lacks the images scope", name, identity.name),
+        None => {}
+    }
+
+    let mut manager = manager.lock().unwrap();
+    match manager.send_notification(request).await {
+        Ok(_) => Ok(HttpResponse::Ok().body("Notification sent successfully")),
+        Err(e) => {
+            log::error!("Failed to send notification: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to send notification: {}", e)))
+        }
+    }
+}
+
+/// Shows the notification `POST /hooks/{name}` would send for a body, without sending it
+pub async fn hook_dry_run(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Bytes,
+    hooks: web::Data<Hooks>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+    let route = find_route(&hooks, &name)?;
+    let payload = parse_payload(&req, &body)?;
+    Ok(HttpResponse::Ok().json(route.apply(&payload, identity.sender.as_deref())))
+}
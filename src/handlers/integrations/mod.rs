@@ -0,0 +1,232 @@
+mod alertmanager;
+mod github;
+mod gitlab;
+mod hooks;
+mod slack;
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use actix_web::{HttpRequest, HttpResponse, Error};
+use actix_web::error::ErrorBadRequest;
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::services::NotificationManager;
+use crate::notifications::{NotificationRequest, NotificationLink, DefaultAction, Priority};
+use crate::utils::config::ForgeWebhookConfig;
+use crate::utils::network::is_public_ip;
+use super::ntfy::save_attachment;
+
+pub use alertmanager::alertmanager_webhook;
+pub use github::github_webhook;
+pub use gitlab::gitlab_webhook;
+pub use hooks::{hook_webhook, hook_dry_run};
+pub use slack::slack_webhook;
+
+/// Longest comment or commit message shown in a toast
+const MAX_EXCERPT_CHARS: usize = 200;
+const IMAGE_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A forge webhook event rendered for a toast
+struct EventToast {
+    title: String,
+    message: String,
+    url: Option<String>,
+    priority: Priority,
+    /// Replaces the toast of an earlier event with the same key, such as a rerun pipeline
+    replace_key: Option<String>,
+}
+
+impl EventToast {
+    fn new(title: String, message: String, url: Option<String>) -> Self {
+        Self { title, message, url, priority: Priority::Normal, replace_key: None }
+    }
+
+    fn into_request(self, sender: String) -> NotificationRequest {
+        let mut request = NotificationRequest::new(&self.title, &self.message);
+        request.priority = self.priority;
+        request.replace_key = self.replace_key;
+        request.sender = Some(sender);
+        if let Some(url) = self.url.filter(|url| !url.is_empty()) {
+            request.url = Some(url.clone());
+            request.default_action = Some(vec![DefaultAction::OpenUrl]);
+            request.links.push(NotificationLink { label: "Open in browser".to_string(), url });
+        }
+        request
+    }
+}
+
+/// Whether `event` is shown, given the configured events or the receiver's defaults
+fn is_event_enabled(config: &ForgeWebhookConfig, defaults: &[&str], event: &str) -> bool {
+    if config.events.is_empty() {
+        defaults.contains(&event)
+    } else {
+        config.events.iter().any(|enabled| enabled == "*" || enabled.eq_ignore_ascii_case(event))
+    }
+}
+
+/// The string at a JSON pointer such as `/repository/full_name`, empty if missing
+fn text<'a>(payload: &'a Value, pointer: &str) -> &'a str {
+    payload.pointer(pointer).and_then(Value::as_str).unwrap_or("")
+}
+
+/// The number at a JSON pointer, printed for titles such as `#42`
+fn number(payload: &Value, pointer: &str) -> String {
+    payload.pointer(pointer).and_then(Value::as_u64).map(|number| number.to_string()).unwrap_or_default()
+}
+
+/// The first non-empty line of `text`, shortened to `MAX_EXCERPT_CHARS`
+fn excerpt(text: &str) -> String {
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("");
+    if line.chars().count() > MAX_EXCERPT_CHARS {
+        format!("{}…", line.chars().take(MAX_EXCERPT_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Whether `text` mentions `@user`, ignoring case and not matching longer usernames
+fn mentions(text: &str, user: &str) -> bool {
+    let needle = format!("@{}", user.to_lowercase());
+    let text = text.to_lowercase();
+    text.match_indices(&needle).any(|(index, _)| {
+        !text[index + needle.len()..].chars().next()
+            .is_some_and(|next| next.is_alphanumeric() || next == '-' || next == '_')
+    })
+}
+
+fn is_mentioned(config: &ForgeWebhookConfig, text: &str) -> bool {
+    config.mention.as_deref().is_some_and(|user| mentions(text, user))
+}
+
+fn is_user(config: &ForgeWebhookConfig, username: &str) -> bool {
+    config.mention.as_deref().is_some_and(|user| user.eq_ignore_ascii_case(username))
+}
+
+/// A toast for a finished CI run, such as "Build failed on main: tests", or `None` for results
+/// that are not shown
+fn ci_toast(config: &ForgeWebhookConfig, status: &str, branch: &str, name: &str, message: String, url: Option<String>) -> Option<EventToast> {
+    let (outcome, priority) = match status {
+        "failure" | "failed" | "timed_out" | "startup_failure" => ("failed", Priority::High),
+        "success" if config.ci_success => ("passed", Priority::Low),
+        _ => return None,
+    };
+    let mut toast = EventToast::new(format!("Build {} on {}: {}", outcome, branch, name), message, url);
+    toast.priority = priority;
+    Some(toast)
+}
+
+/// JSON sent either as the body or in the `payload` field of a form, as GitHub and Slack accept
+fn parse_payload(req: &HttpRequest, body: &[u8]) -> Result<Value, Error> {
+    let is_form = req.headers().get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+    let json = if is_form {
+        serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+            .ok()
+            .and_then(|fields| fields.into_iter().find(|(name, _)| name == "payload"))
+            .map(|(_, payload)| payload)
+            .ok_or_else(|| ErrorBadRequest("Missing payload field"))?
+    } else {
+        String::from_utf8(body.to_vec()).map_err(|_| ErrorBadRequest("Payload is not UTF-8"))?
+    };
+    serde_json::from_str(&json).map_err(|e| ErrorBadRequest(format!("Invalid payload: {}", e)))
+}
+
+/// Downloads an image into the asset directory so the toast can show it. The URL comes from
+/// the payload, so only public addresses are contacted and redirects are not followed.
+async fn download_image(url: &str, max_bytes: usize) -> anyhow::Result<String> {
+    let parsed = reqwest::Url::parse(url)?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(anyhow!("Unsupported image URL scheme: {}", parsed.scheme()));
+    }
+    let host = parsed.host_str().ok_or_else(|| anyhow!("Image URL has no host"))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    // The checked addresses are the ones connected to, so a second lookup cannot point elsewhere
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port)).await?.collect();
+    if addresses.is_empty() {
+        return Err(anyhow!("{} did not resolve to an address", host));
+    }
+    if let Some(address) = addresses.iter().find(|address| !is_public_ip(address.ip())) {
+        return Err(anyhow!("Refusing to download from non-public address {}", address.ip()));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(IMAGE_DOWNLOAD_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(host, &addresses)
+        .build()?;
+    let mut response = client.get(parsed).send().await?.error_for_status()?;
+    if response.content_length().is_some_and(|length| length > max_bytes as u64) {
+        return Err(anyhow!("Image is larger than {} bytes", max_bytes));
+    }
+    let mut content = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        content.extend_from_slice(&chunk);
+        if content.len() > max_bytes {
+            return Err(anyhow!("Image is larger than {} bytes", max_bytes));
+        }
+    }
+
+    let format = image::guess_format(&content).map_err(|_| anyhow!("Downloaded file is not an image"))?;
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    save_attachment(&format!("image.{}", extension), &content).map_err(|e| anyhow!("{}", e))
+}
+
+async fn send_event(manager: &Mutex<NotificationManager>, toast: EventToast, sender: String) -> Result<HttpResponse, Error> {
+    let mut manager = manager.lock().unwrap();
+    match manager.send_notification(toast.into_request(sender)).await {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(e) => {
+            log::error!("Failed to send notification: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to send notification: {}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_match_whole_usernames_ignoring_case() {
+        assert!(mentions("cc @Alice", "alice"));
+        assert!(mentions("@alice: please review", "alice"));
+        assert!(mentions("thanks @alice.", "alice"));
+        assert!(!mentions("cc @alice-bot", "alice"));
+        assert!(!mentions("cc @alice_2", "alice"));
+        assert!(!mentions("cc @alicesmith", "alice"));
+        assert!(!mentions("mail alice@example.com", "alice"));
+        assert!(mentions("@alicesmith and @alice", "alice"));
+    }
+
+    #[actix_web::test]
+    async fn download_image_refuses_internal_addresses() {
+        for url in ["http://127.0.0.1:8080/image.png", "http://localhost/image.png", "http://[::1]/image.png", "http://169.254.169.254/latest"] {
+            let error = download_image(url, 1024).await.unwrap_err();
+            assert!(error.to_string().contains("non-public address"), "{}: {}", url, error);
+        }
+        assert!(download_image("file:///C:/Windows/win.ini", 1024).await.is_err());
+    }
+
+    #[test]
+    fn ci_toast_shows_failures_and_optionally_successes() {
+        let config = ForgeWebhookConfig::default();
+        let url = Some("https://ci.example.com/1".to_string());
+
+        let toast = ci_toast(&config, "timed_out", "main", "build", "repo".to_string(), url.clone()).unwrap();
+        assert_eq!(toast.title, "Build failed on main: build");
+        assert_eq!(toast.priority, Priority::High);
+        assert_eq!(toast.url, url);
+
+        assert!(ci_toast(&config, "success", "main", "build", String::new(), None).is_none());
+        assert!(ci_toast(&config, "cancelled", "main", "build", String::new(), None).is_none());
+
+        let config = ForgeWebhookConfig { ci_success: true, ..Default::default() };
+        let toast = ci_toast(&config, "success", "main", "build", String::new(), None).unwrap();
+        assert_eq!(toast.title, "Build passed on main: build");
+        assert_eq!(toast.priority, Priority::Low);
+    }
+}
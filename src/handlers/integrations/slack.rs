@@ -0,0 +1,363 @@
+use std::sync::{Arc, Mutex, OnceLock};
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+use crate::services::NotificationManager;
+use crate::notifications::{NotificationRequest, NotificationLink, DefaultAction, is_web_url};
+use crate::utils::auth::{AuthIdentity, Scope};
+use crate::utils::config::{ServerConfig, SlackConfig};
+use super::{download_image, parse_payload, text};
+
+const MAX_CHANNEL_LENGTH: usize = 80;
+
+/// A Slack incoming-webhook message reduced to what a toast can show
+#[derive(Debug, Default)]
+struct SlackMessage {
+    title: Option<String>,
+    lines: Vec<String>,
+    image_url: Option<String>,
+    buttons: Vec<NotificationLink>,
+    click_url: Option<String>,
+}
+
+impl SlackMessage {
+    fn parse(payload: &Value) -> Self {
+        let mut message = Self::default();
+        let blocks = payload.get("blocks").and_then(Value::as_array);
+
+        // Slack shows the blocks when there are any and uses `text` as their fallback
+        match blocks.filter(|blocks| !blocks.is_empty()) {
+            Some(blocks) => message.add_blocks(blocks),
+            None => message.add_text(text(payload, "/text")),
+        }
+
+        for attachment in payload.get("attachments").and_then(Value::as_array).into_iter().flatten() {
+            message.add_attachment(attachment);
+        }
+        message
+    }
+
+    fn add_text(&mut self, mrkdwn: &str) {
+        let flattened = flatten_mrkdwn(mrkdwn);
+        if !flattened.trim().is_empty() {
+            self.lines.push(flattened.trim().to_string());
+        }
+    }
+
+    fn add_button(&mut self, label: &str, url: &str) {
+        if is_web_url(url) {
+            self.buttons.push(NotificationLink { label: flatten_mrkdwn(label), url: url.to_string() });
+        }
+    }
+
+    fn add_image(&mut self, url: &str) {
+        if self.image_url.is_none() && !url.is_empty() {
+            self.image_url = Some(url.to_string());
+        }
+    }
+
+    fn add_blocks(&mut self, blocks: &[Value]) {
+        for block in blocks {
+            match text(block, "/type") {
+                "header" => {
+                    let header = flatten_mrkdwn(text(block, "/text/text"));
+                    if self.title.is_none() {
+                        self.title = Some(header);
+                    } else {
+                        self.lines.push(header);
+                    }
+                }
+                "section" => {
+                    self.add_text(text(block, "/text/text"));
+                    for field in block.get("fields").and_then(Value::as_array).into_iter().flatten() {
+                        self.add_text(text(field, "/text"));
+                    }
+                    if let Some(accessory) = block.get("accessory") {
+                        self.add_element(accessory);
+                    }
+                }
+                "context" => {
+                    let parts: Vec<String> = block.get("elements").and_then(Value::as_array).into_iter().flatten()
+                        .filter(|element| text(element, "/type") != "image")
+                        .map(|element| flatten_mrkdwn(text(element, "/text")))
+                        .collect();
+                    self.add_text(&parts.join(" "));
+                }
+                "image" => self.add_image(text(block, "/image_url")),
+                "actions" => {
+                    for element in block.get("elements").and_then(Value::as_array).into_iter().flatten() {
+                        self.add_element(element);
+                    }
+                }
+                "rich_text" => self.add_text(&rich_text(block)),
+                _ => {}
+            }
+        }
+    }
+
+    fn add_element(&mut self, element: &Value) {
+        match text(element, "/type") {
+            "button" => self.add_button(text(element, "/text/text"), text(element, "/url")),
+            "image" => self.add_image(text(element, "/image_url")),
+            _ => {}
+        }
+    }
+
+    fn add_attachment(&mut self, attachment: &Value) {
+        let title = text(attachment, "/title");
+        let title_link = text(attachment, "/title_link");
+        if !title.is_empty() {
+            if self.title.is_none() {
+                self.title = Some(flatten_mrkdwn(title));
+            } else {
+                self.add_text(title);
+            }
+        }
+        if is_web_url(title_link) && self.click_url.is_none() {
+            self.click_url = Some(title_link.to_string());
+        }
+
+        let lines_before = self.lines.len();
+        self.add_text(text(attachment, "/pretext"));
+        self.add_text(text(attachment, "/text"));
+        for field in attachment.get("fields").and_then(Value::as_array).into_iter().flatten() {
+            let (title, value) = (text(field, "/title"), text(field, "/value"));
+            if title.is_empty() {
+                self.add_text(value);
+            } else {
+                self.add_text(&format!("{}: {}", title, value));
+            }
+        }
+        if let Some(blocks) = attachment.get("blocks").and_then(Value::as_array) {
+            self.add_blocks(blocks);
+        }
+        // `fallback` is the plain text version for clients that show nothing else
+        if self.lines.len() == lines_before && title.is_empty() {
+            self.add_text(text(attachment, "/fallback"));
+        }
+        self.add_text(text(attachment, "/footer"));
+
+        self.add_image(text(attachment, "/image_url"));
+        self.add_image(text(attachment, "/thumb_url"));
+        for action in attachment.get("actions").and_then(Value::as_array).into_iter().flatten() {
+            if text(action, "/type") == "button" {
+                self.add_button(text(action, "/text"), text(action, "/url"));
+            }
+        }
+    }
+}
+
+/// The text of a `rich_text` block, whose sections hold runs of text, links and mentions
+fn rich_text(block: &Value) -> String {
+    let sections = block.get("elements").and_then(Value::as_array).into_iter().flatten();
+    sections
+        .map(|section| {
+            section.get("elements").and_then(Value::as_array).into_iter().flatten()
+                .map(|element| match text(element, "/type") {
+                    "link" if text(element, "/text").is_empty() => text(element, "/url").to_string(),
+                    "user" => format!("@{}", text(element, "/user_id")),
+                    "channel" => format!("#{}", text(element, "/channel_id")),
+                    "emoji" => format!(":{}:", text(element, "/name")),
+                    _ => text(element, "/text").to_string(),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("Invalid built-in pattern"))
+}
+
+/// Reduces Slack's mrkdwn to plain text: links become their label, mentions their name and
+/// bold, italic, strikethrough and code markers are removed
+fn flatten_mrkdwn(mrkdwn: &str) -> String {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    static CODE_BLOCK: OnceLock<Regex> = OnceLock::new();
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    static BOLD: OnceLock<Regex> = OnceLock::new();
+    static ITALIC: OnceLock<Regex> = OnceLock::new();
+    static STRIKE: OnceLock<Regex> = OnceLock::new();
+
+    let text = regex(&LINK, r"<([^<>|]+)(?:\|([^<>]+))?>").replace_all(mrkdwn, |caps: &Captures| {
+        let target = &caps[1];
+        match (target.chars().next(), caps.get(2)) {
+            (Some('@'), Some(label)) => format!("@{}", label.as_str().trim_start_matches('@')),
+            (Some('#'), Some(label)) => format!("#{}", label.as_str()),
+            (_, Some(label)) => label.as_str().to_string(),
+            (Some('!'), None) => format!("@{}", target.trim_start_matches('!').split('^').next().unwrap_or("")),
+            (_, None) => target.to_string(),
+        }
+    });
+    let text = regex(&CODE_BLOCK, r"(?s)```(.*?)```").replace_all(&text, "$1");
+    let text = regex(&CODE, r"`([^`\n]+)`").replace_all(&text, "$1");
+    let text = regex(&BOLD, r"(^|\W)\*(\S[^*\n]*?)\*").replace_all(&text, "$1$2");
+    let text = regex(&ITALIC, r"(^|\W)_(\S[^_\n]*?)_").replace_all(&text, "$1$2");
+    let text = regex(&STRIKE, r"(^|\W)~(\S[^~\n]*?)~").replace_all(&text, "$1$2");
+
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+fn is_valid_channel(channel: &str) -> bool {
+    !channel.is_empty()
+        && channel.len() <= MAX_CHANNEL_LENGTH
+        && channel.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Accepts what tools post to a Slack incoming-webhook URL: `text`, `attachments` and `blocks`,
+/// as JSON or in a form's `payload` field. Answers `ok` like Slack does.
+pub async fn slack_webhook(
+    req: HttpRequest,
+    channel: web::Path<String>,
+    body: web::Bytes,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+
+    let channel = channel.into_inner();
+    if !is_valid_channel(&channel) {
+        return Ok(HttpResponse::BadRequest().body("invalid_channel"));
+    }
+    let payload = match parse_payload(&req, &body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::warn!("Invalid Slack payload: {}", e);
+            return Ok(HttpResponse::BadRequest().body("invalid_payload"));
+        }
+    };
+
+    let message = SlackMessage::parse(&payload);
+    if message.lines.is_empty() && message.title.is_none() {
+        return Ok(HttpResponse::BadRequest().body("no_text"));
+    }
+    let request = into_request(message, &channel, &payload, &config.slack, &identity).await;
+
+    let mut manager = manager.lock().unwrap();
+    match manager.send_notification(request).await {
+        Ok(_) => Ok(HttpResponse::Ok().content_type("text/plain").body("ok")),
+        Err(e) => {
+            log::error!("Failed to send notification: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to send notification: {}", e)))
+        }
+    }
+}
+
+async fn into_request(message: SlackMessage, channel: &str, payload: &Value, config: &SlackConfig, identity: &AuthIdentity) -> NotificationRequest {
+    let channel_name = format!("#{}", channel);
+    let title = message.title.unwrap_or_else(|| channel_name.clone());
+    let mut request = NotificationRequest::new(&title, &message.lines.join("\n"));
+
+    let username = text(payload, "/username");
+    request.sender = identity.sender.clone()
+        .or_else(|| (!username.is_empty()).then(|| username.to_string()))
+        .or(Some(channel_name));
+    request.links = message.buttons;
+    if let Some(url) = message.click_url {
+        request.url = Some(url);
+        request.default_action = Some(vec![DefaultAction::OpenUrl]);
+    }
+
+    if let Some(image_url) = message.image_url {
+        if !config.download_images {
+            request.links.push(NotificationLink { label: "Open image".to_string(), url: image_url });
+        } else if !identity.has_scope(Scope::Images) {
+            log::debug!("Not showing the Slack message's image, {} lacks the images scope", identity.name);
+        } else {
+            match download_image(&image_url, config.max_image_bytes).await {
+                Ok(path) => request.image_path = Some(path),
+                Err(e) => log::warn!("Failed to download image {}: {}", image_url, e),
+            }
+        }
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn flattens_links_and_mentions() {
+        assert_eq!(flatten_mrkdwn("See <https://example.com|the docs>"), "See the docs");
+        assert_eq!(flatten_mrkdwn("See <https://example.com>"), "See https://example.com");
+        assert_eq!(flatten_mrkdwn("<@U024BE7LH|jane> and <@U024BE7LH>"), "@jane and @U024BE7LH");
+        assert_eq!(flatten_mrkdwn("<#C0123ABCD|deploys>"), "#deploys");
+        assert_eq!(flatten_mrkdwn("<!here>, <!channel> and <!subteam^S012ABC|@oncall>"), "@here, @channel and @oncall");
+        assert_eq!(flatten_mrkdwn("<!date^1392734382^{date_short}|Feb 18, 2014>"), "Feb 18, 2014");
+    }
+
+    #[test]
+    fn flattens_formatting() {
+        assert_eq!(flatten_mrkdwn("*bold* _italic_ ~struck~ `code`"), "bold italic struck code");
+        assert_eq!(flatten_mrkdwn("```let x = 1;\nlet y = 2;```"), "let x = 1;\nlet y = 2;");
+        assert_eq!(flatten_mrkdwn("5 * 3 * 2 and snake_case_name"), "5 * 3 * 2 and snake_case_name");
+        assert_eq!(flatten_mrkdwn("a &lt;b&gt; &amp; c"), "a <b> & c");
+    }
+
+    #[test]
+    fn block_kit_message() {
+        let message = SlackMessage::parse(&fixture(include_str!("../../../tests/fixtures/slack/block_kit_deploy.json")));
+
+        assert_eq!(message.title.as_deref(), Some("Deploy finished"));
+        assert_eq!(message.lines, [
+            "api v2.4.1 is live on production :rocket:\n@here please watch the dashboard",
+            "Author:\n@jane",
+            "Channel:\n#deploys",
+            "Triggered by @oncall took 3m 12s",
+        ]);
+        assert_eq!(message.image_url.as_deref(), Some("https://cdn.example.com/logos/api.png"));
+        // The file:// button and the one without a URL are dropped
+        let buttons: Vec<(&str, &str)> = message.buttons.iter().map(|link| (link.label.as_str(), link.url.as_str())).collect();
+        assert_eq!(buttons, [("View release", "https://github.com/example/api/releases/tag/v2.4.1")]);
+        assert_eq!(message.click_url, None);
+    }
+
+    #[test]
+    fn blocks_replace_the_fallback_text() {
+        let payload = serde_json::json!({
+            "text": "fallback",
+            "blocks": [{ "type": "section", "text": { "type": "mrkdwn", "text": "shown" } }],
+        });
+        assert_eq!(SlackMessage::parse(&payload).lines, ["shown"]);
+
+        let payload = serde_json::json!({ "text": "Hello <@U1|bob>", "blocks": [] });
+        assert_eq!(SlackMessage::parse(&payload).lines, ["Hello @bob"]);
+    }
+
+    #[test]
+    fn legacy_attachment() {
+        let message = SlackMessage::parse(&fixture(include_str!("../../../tests/fixtures/slack/legacy_attachment.json")));
+
+        assert_eq!(message.title.as_deref(), Some("Build #142 failed"));
+        assert_eq!(message.lines, [
+            "api build failed",
+            "Build status changed for api",
+            "test_login failed with timeout & 1 2 retries",
+            "Branch: main",
+            "mailto:dev@example.com",
+            "Jenkins",
+        ]);
+        assert_eq!(message.click_url.as_deref(), Some("https://ci.example.com/job/api/142/"));
+        assert_eq!(message.image_url.as_deref(), Some("https://ci.example.com/static/jenkins.png"));
+        assert_eq!(message.buttons.len(), 1);
+        assert_eq!(message.buttons[0].label, "Console");
+    }
+
+    #[test]
+    fn attachment_fallback_is_used_alone() {
+        let payload = serde_json::json!({
+            "attachments": [{ "fallback": "Disk *full* on nas", "title_link": "javascript:alert(1)" }],
+        });
+        let message = SlackMessage::parse(&payload);
+        assert_eq!(message.lines, ["Disk full on nas"]);
+        assert_eq!(message.click_url, None);
+    }
+}
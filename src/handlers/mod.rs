@@ -1,3 +1,16 @@
 mod web;
+mod clipboard;
+mod dnd;
+mod digest;
+mod gotify;
+mod ntfy;
+mod relay;
+pub mod integrations;
 
 pub use web::send_notification;
+pub use clipboard::{set_clipboard, get_clipboard};
+pub use dnd::{set_dnd, get_dnd};
+pub use digest::get_digest;
+pub use gotify::gotify_message;
+pub use ntfy::{ntfy_publish, ntfy_publish_json};
+pub use relay::relay_activation;
@@ -0,0 +1,456 @@
+use std::sync::{Arc, Mutex};
+use std::fs;
+use std::env;
+use actix_web::{web, HttpResponse, Error, HttpRequest};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use bytes::BytesMut;
+use uuid::Uuid;
+
+use crate::services::NotificationManager;
+use crate::notifications::{NotificationRequest, NotificationLink, DefaultAction, Priority};
+use crate::utils::auth::{AuthIdentity, Scope};
+use crate::utils::config::{NtfyTopicConfig, ServerConfig};
+use crate::utils::constants::NOTIFICATION_ASSETS_DIR;
+
+const MAX_TOPIC_LENGTH: usize = 64;
+/// Action options ntfy knows besides `action`, `label` and `url`. Other `key=value` parts are
+/// positional, so labels and URLs may contain `=`.
+const ACTION_OPTIONS: [&str; 5] = ["clear", "method", "body", "intent", "value"];
+const ACTION_OPTION_PREFIXES: [&str; 2] = ["headers.", "extras."];
+
+/// Tags ntfy shows as emoji in front of the title instead of listing them
+const TAG_EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("warning", "⚠️"),
+    ("rotating_light", "🚨"),
+    ("white_check_mark", "✅"),
+    ("heavy_check_mark", "✔️"),
+    ("x", "❌"),
+    ("no_entry", "⛔"),
+    ("skull", "💀"),
+    ("tada", "🎉"),
+    ("partying_face", "🥳"),
+    ("loudspeaker", "📢"),
+    ("computer", "💻"),
+    ("floppy_disk", "💾"),
+    ("calendar", "📅"),
+];
+
+/// A message as published to ntfy with JSON, or assembled from headers and query parameters
+#[derive(Debug, Default, Deserialize)]
+pub struct NtfyMessage {
+    #[serde(default)]
+    pub topic: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 1 (min) to 5 (max)
+    #[serde(default)]
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub click: Option<String>,
+    #[serde(default)]
+    pub attach: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<NtfyAction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfyAction {
+    pub action: String,
+    pub label: String,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NtfyResponse {
+    id: String,
+    time: i64,
+    event: &'static str,
+    topic: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u8>,
+}
+
+fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty()
+        && topic.len() <= MAX_TOPIC_LENGTH
+        && topic.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Parses `1`-`5` as well as ntfy's names such as `max` or `urgent`
+pub fn parse_ntfy_priority(value: &str) -> Option<u8> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "min" => Some(1),
+        "2" | "low" => Some(2),
+        "3" | "default" => Some(3),
+        "4" | "high" => Some(4),
+        "5" | "max" | "urgent" => Some(5),
+        _ => None,
+    }
+}
+
+fn priority_from_ntfy(priority: u8) -> Priority {
+    match priority {
+        0 | 1 => Priority::Min,
+        2 => Priority::Low,
+        3 => Priority::Normal,
+        4 => Priority::High,
+        _ => Priority::Urgent,
+    }
+}
+
+/// Splits on `separator` outside of single or double quotes. The quotes are removed unless
+/// `keep_quotes` is set, for parts that are split again.
+fn split_quoted(input: &str, separator: char, keep_quotes: bool) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    for c in input.chars() {
+        match quote {
+            Some(q) if c == q => {
+                quote = None;
+                if keep_quotes {
+                    current.push(c);
+                }
+            }
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                if keep_quotes {
+                    current.push(c);
+                }
+            }
+            None if c == separator => parts.push(std::mem::take(&mut current)),
+            None => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts.into_iter().map(|part| part.trim().to_string()).collect()
+}
+
+/// Parses the header form of actions, such as
+/// `view, Open portal, https://example.com, clear=true; http, Close door, https://api.example.com/close`
+pub fn parse_ntfy_actions(value: &str) -> Vec<NtfyAction> {
+    split_quoted(value, ';', true).iter()
+        .filter(|definition| !definition.is_empty())
+        .filter_map(|definition| {
+            let mut action = None;
+            let mut label = None;
+            let mut url = None;
+            for (index, part) in split_quoted(definition, ',', false).into_iter().enumerate() {
+                match part.split_once('=') {
+                    Some(("action", value)) => action = Some(value.to_string()),
+                    Some(("label", value)) => label = Some(value.to_string()),
+                    Some(("url", value)) => url = Some(value.to_string()),
+                    // Other options such as clear or method do not apply to toasts
+                    Some((key, _)) if ACTION_OPTIONS.contains(&key)
+                        || ACTION_OPTION_PREFIXES.iter().any(|prefix| key.starts_with(prefix)) => {}
+                    _ => match index {
+                        0 => action = Some(part),
+                        1 => label = Some(part),
+                        2 => url = Some(part),
+                        _ => {}
+                    },
+                }
+            }
+            Some(NtfyAction { action: action?, label: label?, url })
+        })
+        .collect()
+}
+
+impl NtfyMessage {
+    /// Collects the ntfy headers, or the query parameters of the same names
+    fn from_request(req: &HttpRequest, topic: &str, body: Option<String>) -> Self {
+        let query: Vec<(String, String)> = serde_urlencoded::from_str(req.query_string()).unwrap_or_default();
+        let param = |names: &[&str]| -> Option<String> {
+            names.iter()
+                .find_map(|name| req.headers().get(*name).and_then(|value| value.to_str().ok()).map(str::to_string))
+                .or_else(|| query.iter()
+                    .find(|(key, _)| names.iter().any(|name| key.eq_ignore_ascii_case(name)))
+                    .map(|(_, value)| value.clone()))
+        };
+
+        Self {
+            topic: topic.to_string(),
+            message: param(&["x-message", "message", "m"]).or(body),
+            title: param(&["x-title", "title", "ti", "t"]),
+            tags: param(&["x-tags", "tags", "tag", "ta"])
+                .map(|tags| tags.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect())
+                .unwrap_or_default(),
+            priority: param(&["x-priority", "priority", "prio", "p"]).and_then(|p| parse_ntfy_priority(&p)),
+            click: param(&["x-click", "click"]),
+            attach: param(&["x-attach", "attach", "a"]),
+            actions: param(&["x-actions", "actions", "action"])
+                .map(|actions| parse_ntfy_actions(&actions))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn into_request(self, topic: Option<&NtfyTopicConfig>) -> NotificationRequest {
+        let mut emoji = Vec::new();
+        let mut other_tags = Vec::new();
+        for tag in &self.tags {
+            match TAG_EMOJI.iter().find(|(name, _)| name == tag) {
+                Some((_, symbol)) => emoji.push(*symbol),
+                None => other_tags.push(tag.as_str()),
+            }
+        }
+
+        let title = self.title
+            .filter(|title| !title.trim().is_empty())
+            .or_else(|| topic.and_then(|topic| topic.title.clone()))
+            .unwrap_or_else(|| self.topic.clone());
+        let title = if emoji.is_empty() { title } else { format!("{} {}", emoji.join(" "), title) };
+
+        let mut message = self.message.unwrap_or_default();
+        if !other_tags.is_empty() {
+            message = format!("{}\nTags: {}", message, other_tags.join(", "));
+        }
+
+        let mut request = NotificationRequest::new(&title, message.trim());
+        request.priority = match self.priority {
+            Some(priority) => priority_from_ntfy(priority),
+            None => topic.and_then(|topic| topic.priority).unwrap_or_default(),
+        };
+        request.digest = topic.and_then(|topic| topic.digest.clone());
+
+        if let Some(click) = self.click {
+            request.url = Some(click);
+            request.default_action = Some(vec![DefaultAction::OpenUrl]);
+        }
+        for action in self.actions {
+            match (action.action.as_str(), action.url) {
+                ("view", Some(url)) => request.links.push(NotificationLink { label: action.label, url }),
+                (other, _) => log::warn!("Ignoring unsupported ntfy action {} ({})", other, action.label),
+            }
+        }
+        if let Some(attach) = self.attach {
+            request.links.push(NotificationLink { label: "Open attachment".to_string(), url: attach });
+        }
+        request
+    }
+}
+
+async fn read_body(mut payload: web::Payload, max_bytes: usize) -> Result<BytesMut, Error> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > max_bytes {
+            return Err(actix_web::error::ErrorPayloadTooLarge("Message body is too large"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Stores a published file the way ntfy does when a `Filename` header is sent
+pub(super) fn save_attachment(filename: &str, content: &[u8]) -> Result<String, Error> {
+    let filename = std::path::Path::new(filename)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Invalid filename"))?;
+
+    let temp_dir = env::temp_dir().join(NOTIFICATION_ASSETS_DIR).join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&temp_dir).map_err(|e| {
+        log::error!("Failed to create temp directory: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to create temp directory")
+    })?;
+    let file_path = temp_dir.join(filename);
+    fs::write(&file_path, content).map_err(|e| {
+        log::error!("Failed to save attachment: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to save file")
+    })?;
+    Ok(file_path.to_string_lossy().into_owned())
+}
+
+async fn publish(
+    message: NtfyMessage,
+    file_path: Option<String>,
+    config: &ServerConfig,
+    manager: &Mutex<NotificationManager>,
+    identity: &AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    if !is_valid_topic(&message.topic) {
+        return Ok(HttpResponse::BadRequest().body("Invalid topic"));
+    }
+    let topic = config.ntfy.topics.iter().find(|topic| topic.name == message.topic);
+    if topic.is_none() && !config.ntfy.allow_unlisted_topics {
+        log::warn!("Rejecting message for unlisted ntfy topic {}", message.topic);
+        return Ok(HttpResponse::Forbidden().body("Topic is not configured"));
+    }
+
+    let response = NtfyResponse {
+        id: Uuid::new_v4().simple().to_string()[..12].to_string(),
+        time: chrono::Utc::now().timestamp(),
+        event: "message",
+        topic: message.topic.clone(),
+        message: message.message.clone().unwrap_or_default(),
+        title: message.title.clone(),
+        tags: message.tags.clone(),
+        priority: message.priority,
+    };
+
+    if topic.is_some_and(|topic| topic.mute) {
+        log::info!("Muted ntfy topic {}, not showing: {:?}", message.topic, message.title);
+        return Ok(HttpResponse::Ok().json(response));
+    }
+
+    let mut request = message.into_request(topic);
+    if request.message.is_empty() {
+        request.message = match &file_path {
+            Some(file_path) => format!("You received a file: {}", file_path.rsplit(['\\', '/']).next().unwrap_or_default()),
+            None => "triggered".to_string(),
+        };
+    }
+    if let Some(file_path) = file_path {
+        request.file_paths = Some(vec![file_path]);
+    }
+    request.sender = identity.sender.clone();
+
+    let mut manager = manager.lock().unwrap();
+    match manager.send_notification(request).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            log::error!("Failed to send notification: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to send notification: {}", e)))
+        }
+    }
+}
+
+/// ntfy-compatible `PUT/POST /{topic}`. The body is the message, everything else comes from
+/// headers such as `Title`, `Priority`, `Tags`, `Click`, `Attach` and `Actions`.
+pub async fn ntfy_publish(
+    req: HttpRequest,
+    path: web::Path<String>,
+    payload: web::Payload,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+
+    let body = read_body(payload, config.ntfy.max_body_bytes).await?;
+    let filename = ["x-filename", "filename", "file", "f"].iter()
+        .find_map(|name| req.headers().get(*name).and_then(|value| value.to_str().ok()));
+
+    // Like ntfy, a file name or a body that is not text turns the body into an attachment,
+    // which is only written to disk for identities allowed to send files
+    let (text, attachment) = match (filename, std::str::from_utf8(&body)) {
+        (None, Ok(text)) => (Some(text.trim().to_string()).filter(|text| !text.is_empty()), None),
+        (Some(filename), _) => (None, Some(filename)),
+        (None, Err(_)) => (None, Some("attachment.bin")),
+    };
+    let file_path = match attachment {
+        Some(filename) => {
+            identity.require(Scope::Files)?;
+            Some(save_attachment(filename, &body)?)
+        }
+        None => None,
+    };
+
+    let message = NtfyMessage::from_request(&req, &path, text);
+    publish(message, file_path, &config, &manager, &identity).await
+}
+
+/// ntfy's JSON publishing, `POST /` with the topic in the body
+pub async fn ntfy_publish_json(
+    body: web::Json<NtfyMessage>,
+    config: web::Data<ServerConfig>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+    publish(body.into_inner(), None, &config, &manager, &identity).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(action: &str, label: &str, url: Option<&str>) -> (String, String, Option<String>) {
+        (action.to_string(), label.to_string(), url.map(str::to_string))
+    }
+
+    fn parse(value: &str) -> Vec<(String, String, Option<String>)> {
+        parse_ntfy_actions(value).into_iter().map(|a| (a.action, a.label, a.url)).collect()
+    }
+
+    #[test]
+    fn parses_priority_numbers_and_names() {
+        for (value, priority) in [("1", 1), ("min", 1), ("low", 2), ("3", 3), ("default", 3), ("HIGH", 4), (" 5 ", 5), ("max", 5), ("urgent", 5)] {
+            assert_eq!(parse_ntfy_priority(value), Some(priority), "{}", value);
+        }
+        for value in ["0", "6", "", "critical"] {
+            assert_eq!(parse_ntfy_priority(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn splits_outside_of_quotes() {
+        assert_eq!(split_quoted("a, 'b, c', \"d; e\"", ',', false), ["a", "b, c", "d; e"]);
+        assert_eq!(split_quoted("a, 'b; c'; d", ';', true), ["a, 'b; c'", "d"]);
+        assert_eq!(split_quoted("one; two;", ';', false), ["one", "two", ""]);
+    }
+
+    // The examples from https://docs.ntfy.sh/publish/#action-buttons
+    #[test]
+    fn parses_the_short_format() {
+        assert_eq!(
+            parse("view, Open portal, https://home.nest.com/, clear=true; http, Turn down, https://api.nest.com/, body='{\"temperature\": 65}'"),
+            [
+                action("view", "Open portal", Some("https://home.nest.com/")),
+                action("http", "Turn down", Some("https://api.nest.com/")),
+            ],
+        );
+        assert_eq!(
+            parse("http, Close door, https://api.mygarage.lan/, method=PUT, headers.Authorization=Bearer zAzsx1sk.., body='{\"action\": \"close\"}'"),
+            [action("http", "Close door", Some("https://api.mygarage.lan/"))],
+        );
+        assert_eq!(
+            parse("broadcast, Take picture, extras.cmd=pic, extras.camera=front"),
+            [action("broadcast", "Take picture", None)],
+        );
+    }
+
+    #[test]
+    fn parses_the_long_format() {
+        assert_eq!(
+            parse("action=view, label=Open portal, url=https://home.nest.com/, clear=true"),
+            [action("view", "Open portal", Some("https://home.nest.com/"))],
+        );
+        assert_eq!(
+            parse("label=\"Open, now\", action=view, url=https://example.com"),
+            [action("view", "Open, now", Some("https://example.com"))],
+        );
+    }
+
+    #[test]
+    fn keeps_equals_signs_in_positional_parts() {
+        assert_eq!(
+            parse("view, Search, https://example.com/search?q=ntfy&page=2"),
+            [action("view", "Search", Some("https://example.com/search?q=ntfy&page=2"))],
+        );
+        assert_eq!(parse("view, a=b, https://example.com"), [action("view", "a=b", Some("https://example.com"))]);
+        assert_eq!(parse("view, 'x = y', https://example.com")[0].1, "x = y");
+    }
+
+    #[test]
+    fn skips_incomplete_actions() {
+        assert!(parse("").is_empty());
+        assert!(parse("view").is_empty());
+        assert_eq!(parse("view; view, Open, https://example.com").len(), 1);
+    }
+}
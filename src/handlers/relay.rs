@@ -0,0 +1,40 @@
+use std::sync::{Arc, Mutex};
+use actix_web::{web, HttpResponse, Error};
+use serde::Deserialize;
+
+use crate::services::NotificationManager;
+use crate::utils::auth::{AuthIdentity, Scope};
+
+#[derive(Deserialize)]
+pub struct RelayActivation {
+    /// The `relay_id` the notification was forwarded with
+    id: String,
+}
+
+/// Called by relay targets when a notification forwarded to them is clicked. The click is
+/// propagated to whatever input the notification came from, such as an MQTT reply topic.
+pub async fn relay_activation(
+    activation: web::Json<RelayActivation>,
+    manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    identity: AuthIdentity,
+) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+
+    let manager = manager.lock().unwrap();
+    let relay = match manager.relay() {
+        Some(relay) => relay,
+        None => return Ok(HttpResponse::NotFound().body("Relaying is not configured")),
+    };
+    // Only the targets the notification was forwarded to may report its clicks
+    let forward = match relay.activated(&activation.id) {
+        Some(forward) if forward.may_report(&identity.name) => forward,
+        Some(_) => return Ok(HttpResponse::Forbidden().body("Not forwarded to this identity")),
+        None => return Ok(HttpResponse::NotFound().body("Unknown notification")),
+    };
+
+    log::info!("{} clicked forwarded notification: {}", identity.name, forward.title);
+    if let Some(origin) = forward.origin {
+        manager.emit_activation(origin);
+    }
+    Ok(HttpResponse::Ok().body("ok"))
+}
@@ -3,7 +3,6 @@ use actix_web::{web, HttpResponse, Error, HttpRequest};
 use std::time::Instant;
 use actix_multipart::Multipart;
 use futures_util::TryStreamExt;
-use std::io::Write;
 use std::path::PathBuf;
 use std::fs;
 use uuid::Uuid;
@@ -12,10 +11,13 @@ use serde::Deserialize;
 use bytes::BytesMut;
 use futures_util::StreamExt;
 
-use crate::services::NotificationManager;
-use crate::notifications::{NotificationRequest, ImagePosition};
-
-const NOTIFICATION_ASSETS_DIR: &str = "notification_server_assets";
+use crate::services::{NotificationManager, Delivery};
+use crate::services::relay::relay_origin;
+use crate::notifications::{NotificationRequest, NotificationLink, ImagePosition, DefaultAction, Priority};
+use crate::utils::auth::{AuthIdentity, Scope};
+use crate::utils::config::ServerConfig;
+use crate::utils::constants::NOTIFICATION_ASSETS_DIR;
+use super::clipboard::{read_text_field, save_file_field};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -24,11 +26,54 @@ struct FormData {
     message: Option<String>,
     #[serde(default)]
     image_position: Option<ImagePosition>,
+    #[serde(default, alias = "default_action")]
+    default_action: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default, alias = "dedup_key")]
+    dedup_key: Option<String>,
+    #[serde(default)]
+    priority: Option<String>,
+    #[serde(default)]
+    digest: Option<String>,
+    #[serde(default, alias = "replace_key")]
+    replace_key: Option<String>,
+    /// JSON array of `{"label": ..., "url": ...}` buttons
+    #[serde(default)]
+    links: Option<String>,
+    /// Only honored from the hubs listed in `relay.hubs`
+    #[serde(default)]
+    sender: Option<String>,
+    /// Set by a hub forwarding the notification, see `[relay]`
+    #[serde(default, alias = "relay_id")]
+    relay_id: Option<String>,
+}
+
+fn parse_priority(value: &str) -> Result<Priority, Error> {
+    value.parse::<Priority>().map_err(|e| {
+        log::error!("Invalid priority: {}", e);
+        actix_web::error::ErrorBadRequest(format!("Invalid priority: {}", e))
+    })
+}
+
+fn parse_links(value: &str) -> Result<Vec<NotificationLink>, Error> {
+    serde_json::from_str::<Vec<NotificationLink>>(value).map_err(|e| {
+        log::error!("Invalid links: {}", e);
+        actix_web::error::ErrorBadRequest(format!("Invalid links: {}", e))
+    })
+}
+
+fn parse_default_action(value: &str) -> Result<Vec<DefaultAction>, Error> {
+    DefaultAction::parse_list(value).map_err(|e| {
+        log::error!("Invalid default_action: {}", e);
+        actix_web::error::ErrorBadRequest(format!("Invalid default_action: {}", e))
+    })
 }
 
 async fn handle_multipart(
     mut payload: Multipart,
     temp_dir: PathBuf,
+    identity: &AuthIdentity,
 ) -> Result<NotificationRequest, Error> {
     let mut title = String::new();
     let mut message = String::new();
@@ -36,106 +81,62 @@ async fn handle_multipart(
     let mut image_position = None;
     let mut file_paths = Vec::new();
     let mut callback_command = None;
+    let mut default_action = None;
+    let mut url = None;
+    let mut dedup_key = None;
+    let mut priority = Priority::default();
+    let mut digest = None;
+    let mut replace_key = None;
+    let mut links = Vec::new();
+    let mut sender = None;
+    let mut relay_id = None;
 
     while let Ok(Some(mut field)) = payload.try_next().await {
         let content_disposition = field.content_disposition();
         let name = content_disposition.get_name().unwrap_or("");
 
         match name {
-            "title" => {
-                let mut content = Vec::new();
-                while let Ok(Some(chunk)) = field.try_next().await {
-                    content.extend_from_slice(&chunk);
-                }
-                title = String::from_utf8(content)
-                    .map_err(|e| {
-                        log::error!("Invalid UTF-8 in title: {}", e);
-                        actix_web::error::ErrorBadRequest("Invalid title encoding")
-                    })?;
-            },
-            "message" => {
-                let mut content = Vec::new();
-                while let Ok(Some(chunk)) = field.try_next().await {
-                    content.extend_from_slice(&chunk);
-                }
-                message = String::from_utf8(content)
-                    .map_err(|e| {
-                        log::error!("Invalid UTF-8 in message: {}", e);
-                        actix_web::error::ErrorBadRequest("Invalid message encoding")
-                    })?;
-            },
+            "title" => title = read_text_field(&mut field, "title").await?,
+            "message" => message = read_text_field(&mut field, "message").await?,
             "image_position" => {
-                let mut content = Vec::new();
-                while let Ok(Some(chunk)) = field.try_next().await {
-                    content.extend_from_slice(&chunk);
-                }
-                let pos = String::from_utf8(content)
-                    .map_err(|e| {
-                        log::error!("Invalid UTF-8 in image_position: {}", e);
-                        actix_web::error::ErrorBadRequest("Invalid image_position encoding")
-                    })?;
+                let pos = read_text_field(&mut field, "image_position").await?;
                 match pos.to_lowercase().as_str() {
                     "hero" => image_position = Some(ImagePosition::Hero),
                     "logo" => image_position = Some(ImagePosition::AppLogoOverride),
                     _ => log::warn!("Invalid image position value: {}", pos),
                 }
             },
-            "callback_command" => {
-                let mut content = Vec::new();
-                while let Ok(Some(chunk)) = field.try_next().await {
-                    content.extend_from_slice(&chunk);
-                }
-                let cmd = String::from_utf8(content)
-                    .map_err(|e| {
-                        log::error!("Invalid UTF-8 in callback_command: {}", e);
-                        actix_web::error::ErrorBadRequest("Invalid callback_command encoding")
-                    })?;
-                callback_command = Some(cmd);
+            "callback_command" => callback_command = Some(read_text_field(&mut field, "callback_command").await?),
+            "default_action" => {
+                let value = read_text_field(&mut field, "default_action").await?;
+                default_action = Some(parse_default_action(&value)?);
             },
+            "url" => url = Some(read_text_field(&mut field, "url").await?),
+            "dedup_key" => dedup_key = Some(read_text_field(&mut field, "dedup_key").await?),
+            "priority" => priority = parse_priority(&read_text_field(&mut field, "priority").await?)?,
+            "digest" => digest = Some(read_text_field(&mut field, "digest").await?),
+            "replace_key" => replace_key = Some(read_text_field(&mut field, "replace_key").await?),
+            "links" => links = parse_links(&read_text_field(&mut field, "links").await?)?,
+            "sender" => sender = Some(read_text_field(&mut field, "sender").await?),
+            "relay_id" => relay_id = Some(read_text_field(&mut field, "relay_id").await?),
+            // Uploads are checked against the token's scopes before anything is written to disk
             "image" => {
+                identity.require(Scope::Images)?;
                 if let Some(filename) = content_disposition.get_filename() {
-                    let input_path = PathBuf::from(filename);
-                    let file_ext = input_path.extension()
+                    let file_ext = PathBuf::from(filename).extension()
                         .and_then(|ext| ext.to_str())
                         .unwrap_or("jpg")
                         .to_string();
-                    
-                    let image_filename = format!("image.{}", file_ext);
-                    let file_path = temp_dir.join(&image_filename);
-                    
-                    let mut file = fs::File::create(&file_path)
-                        .map_err(|e| {
-                            log::error!("Failed to create image file: {}", e);
-                            actix_web::error::ErrorInternalServerError("Failed to create image file")
-                        })?;
-
-                    while let Ok(Some(chunk)) = field.try_next().await {
-                        file.write_all(&chunk)
-                            .map_err(|e| {
-                                log::error!("Failed to write image chunk: {}", e);
-                                actix_web::error::ErrorInternalServerError("Failed to save image")
-                            })?;
-                    }
+                    let file_path = temp_dir.join(format!("image.{}", file_ext));
+                    save_file_field(&mut field, &file_path).await?;
                     image_path = Some(file_path.to_string_lossy().into_owned());
                 }
             },
             "files" => {
+                identity.require(Scope::Files)?;
                 if let Some(filename) = content_disposition.get_filename() {
                     let file_path = temp_dir.join(filename);
-                    
-                    let mut file = fs::File::create(&file_path)
-                        .map_err(|e| {
-                            log::error!("Failed to create file: {}", e);
-                            actix_web::error::ErrorInternalServerError("Failed to create file")
-                        })?;
-
-                    while let Ok(Some(chunk)) = field.try_next().await {
-                        file.write_all(&chunk)
-                            .map_err(|e| {
-                                log::error!("Failed to write file chunk: {}", e);
-                                actix_web::error::ErrorInternalServerError("Failed to save file")
-                            })?;
-                    }
+                    save_file_field(&mut field, &file_path).await?;
                     file_paths.push(file_path.to_string_lossy().into_owned());
                 }
             },
@@ -153,6 +154,15 @@ async fn handle_multipart(
         image_position,
         file_paths: if file_paths.is_empty() { None } else { Some(file_paths) },
         callback_command,
+        default_action,
+        url,
+        sender,
+        dedup_key,
+        replace_key,
+        priority,
+        digest,
+        links,
+        origin: relay_id.as_deref().and_then(relay_origin),
     })
 }
 
@@ -160,7 +170,11 @@ pub async fn send_notification(
     req: HttpRequest,
     mut payload: web::Payload,
     manager: web::Data<Arc<Mutex<NotificationManager>>>,
+    config: web::Data<ServerConfig>,
+    identity: AuthIdentity,
 ) -> Result<HttpResponse, Error> {
+    identity.require(Scope::Notify)?;
+
     let start = Instant::now();
     log::info!("Received notification request at {:?}", start);
     
@@ -182,7 +196,7 @@ pub async fn send_notification(
 
     // Handle request based on content type
     let request = if content_type.starts_with("multipart/form-data") {
-        handle_multipart(Multipart::new(req.headers(), payload), temp_dir).await?
+        handle_multipart(Multipart::new(req.headers(), payload), temp_dir, &identity).await?
     } else {
         // Handle URL-encoded form data
         let mut body = BytesMut::new();
@@ -202,15 +216,59 @@ pub async fn send_notification(
             image_position: form_data.image_position,
             file_paths: None,
             callback_command: None,
+            default_action: form_data.default_action
+                .as_deref()
+                .map(parse_default_action)
+                .transpose()?,
+            url: form_data.url,
+            sender: form_data.sender,
+            dedup_key: form_data.dedup_key,
+            replace_key: form_data.replace_key,
+            priority: form_data.priority
+                .as_deref()
+                .map(parse_priority)
+                .transpose()?
+                .unwrap_or_default(),
+            digest: form_data.digest,
+            links: form_data.links
+                .as_deref()
+                .map(parse_links)
+                .transpose()?
+                .unwrap_or_default(),
+            origin: form_data.relay_id.as_deref().and_then(relay_origin),
+        }
+    };
+
+    // Opening an uploaded file launches whatever program handles it, much like a callback
+    let opens_file = request.default_action.as_ref().is_some_and(|actions| actions.contains(&DefaultAction::OpenFile));
+    if opens_file || request.callback_command.as_deref().is_some_and(|cmd| !cmd.trim().is_empty()) {
+        identity.require(Scope::Callbacks)?;
+    }
+    // Only a hub forwarding its own notifications may name their sender and relay id
+    let request = if config.relay.is_hub(&identity.name) {
+        NotificationRequest {
+            sender: request.sender.or_else(|| identity.sender.clone()),
+            ..request
+        }
+    } else {
+        NotificationRequest {
+            sender: identity.sender.clone(),
+            origin: None,
+            ..request
         }
     };
 
     // Send notification
     let mut manager = manager.lock().unwrap();
     match manager.send_notification(request).await {
-        Ok(_) => {
+        Ok(delivery) => {
             log::info!("Request completed successfully in {:?}", start.elapsed());
-            Ok(HttpResponse::Ok().body("Notification sent successfully"))
+            Ok(match delivery {
+                Delivery::Shown => HttpResponse::Ok().body("Notification sent successfully"),
+                Delivery::Queued => HttpResponse::Accepted().body("Notification queued until quiet hours end"),
+                Delivery::Dropped => HttpResponse::Ok().body("Notification dropped during quiet hours"),
+                Delivery::Batched => HttpResponse::Accepted().body("Notification added to digest"),
+            })
         },
         Err(e) => {
             log::error!("Failed to send notification: {}", e);
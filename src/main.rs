@@ -1,5 +1,8 @@
 use actix_web::{web, App, HttpServer};
 use anyhow::{Context, Result};
+use std::fs;
+use std::io::BufRead;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use clap::Parser;
 
@@ -9,8 +12,15 @@ mod handlers;
 mod utils;
 
 use services::NotificationManager;
+use services::hooks::Hooks;
+use services::mqtt::MqttInput;
+use services::smtp::SmtpReceiver;
+use services::syslog::SyslogListener;
 use utils::constants::{APP_ID, APP_DISPLAY_NAME};
-use utils::auth::{AuthConfig, AuthMiddleware};
+use utils::auth::{AuthConfig, AuthMiddleware, generate_token, hash_token, hash_password, validate_password_hash};
+use utils::config::ServerConfig;
+use utils::rate_limit::{RateLimiter, RateLimitMiddleware};
+use utils::tls;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Notification server for sending Windows notifications")]
@@ -27,20 +37,145 @@ struct Args {
     #[arg(short, long)]
     username: Option<String>,
 
-    /// Optional password for basic authentication
-    #[arg(short = 'w', long)]
+    /// Optional password for basic authentication. Visible in the process list, prefer --password-hash or --password-file
+    #[arg(short = 'w', long, env = "NOTIFICATION_SERVER_PASSWORD", hide_env_values = true)]
     password: Option<String>,
+
+    /// Argon2 or bcrypt hash of the basic authentication password
+    #[arg(long, env = "NOTIFICATION_SERVER_PASSWORD_HASH", hide_env_values = true, conflicts_with_all = ["password", "password_file"])]
+    password_hash: Option<String>,
+
+    /// File containing the basic authentication password
+    #[arg(long, conflicts_with = "password")]
+    password_file: Option<PathBuf>,
+
+    /// Optional path to a TOML configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Print a new API token and the hash to put in the config file, then exit
+    #[arg(long)]
+    generate_token: bool,
+
+    /// Read a password from stdin, print its argon2 hash for --password-hash, then exit
+    #[arg(long)]
+    hash_password: bool,
+
+    /// PEM certificate chain to serve HTTPS with
+    #[arg(long, requires = "tls_key", conflicts_with = "tls_self_signed")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Serve HTTPS with a generated self-signed certificate that is kept across restarts
+    #[arg(long)]
+    tls_self_signed: bool,
+
+    /// Directory the self-signed certificate is stored in [default: %LOCALAPPDATA%\NotificationServer\tls]
+    #[arg(long)]
+    tls_dir: Option<PathBuf>,
+
+    /// Additional host name or address for the self-signed certificate, can be repeated
+    #[arg(long)]
+    tls_san: Vec<String>,
+}
+
+/// Names the self-signed certificate is valid for
+fn self_signed_names(args: &Args) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    if let Ok(hostname) = std::env::var("COMPUTERNAME") {
+        names.push(hostname.to_lowercase());
+    }
+    if args.address != "0.0.0.0" && args.address != "::" {
+        names.push(args.address.clone());
+    }
+    names.extend(args.tls_san.iter().cloned());
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn load_tls_config(args: &Args, config: &ServerConfig) -> Result<Option<rustls::ServerConfig>> {
+    let (cert_path, key_path) = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert_path), Some(key_path)) => (cert_path.clone(), key_path.clone()),
+        _ if args.tls_self_signed => {
+            let dir = args.tls_dir.clone().unwrap_or_else(tls::default_tls_dir);
+            tls::ensure_self_signed(&dir, self_signed_names(args))?
+        }
+        _ => {
+            // Mapped clients would make authentication mandatory without a way to present a certificate
+            if !config.mtls.clients.is_empty() {
+                anyhow::bail!("mtls.clients requires TLS, enable it with --tls-cert/--tls-key or --tls-self-signed");
+            }
+            if config.mtls.ca_bundle.is_some() {
+                log::warn!("mtls.ca_bundle is ignored because TLS is not enabled");
+            }
+            return Ok(None);
+        }
+    };
+    if !config.mtls.clients.is_empty() && config.mtls.ca_bundle.is_none() {
+        anyhow::bail!("mtls.clients requires mtls.ca_bundle, otherwise no client certificate is requested");
+    }
+
+    println!("TLS certificate: {}", cert_path.display());
+    println!("TLS certificate fingerprint (SHA-256): {}", tls::fingerprint(&cert_path)?);
+    if let Some(ca_bundle) = &config.mtls.ca_bundle {
+        println!("Client certificates are verified against {}", ca_bundle.display());
+    }
+    tls::load_server_config(&cert_path, &key_path, config.mtls.ca_bundle.as_deref()).map(Some)
+}
+
+/// Resolves the configured password source into an argon2 or bcrypt hash
+fn resolve_password_hash(args: &Args) -> Result<Option<String>> {
+    if let Some(hash) = &args.password_hash {
+        validate_password_hash(hash)?;
+        return Ok(Some(hash.clone()));
+    }
+
+    if let Some(path) = &args.password_file {
+        let password = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read password file {}", path.display()))?;
+        return hash_password(password.trim_end_matches(['\r', '\n'])).map(Some);
+    }
+
+    match &args.password {
+        Some(password) => {
+            if std::env::var_os("NOTIFICATION_SERVER_PASSWORD").is_none() {
+                log::warn!("--password is visible in the process list, consider --password-hash or --password-file");
+            }
+            hash_password(password).map(Some)
+        }
+        None => Ok(None),
+    }
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+
+    if args.generate_token {
+        let token = generate_token();
+        println!("Token: {}", token);
+        println!("Hash:  {}", hash_token(&token));
+        return Ok(());
+    }
     
+    if args.hash_password {
+        let mut password = String::new();
+        std::io::stdin().lock().read_line(&mut password)?;
+        println!("{}", hash_password(password.trim_end_matches(['\r', '\n']))?);
+        return Ok(());
+    }
+
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("debug"));
+
+    let config = ServerConfig::load(args.config.as_deref())?;
     
     log::info!("Initializing notification manager...");
     let manager = Arc::new(Mutex::new(
-        NotificationManager::new(APP_ID, APP_DISPLAY_NAME)
+        NotificationManager::new(APP_ID, APP_DISPLAY_NAME, &config)
             .await
             .context("Failed to create notification manager")?
     ));
@@ -50,26 +185,117 @@ async fn main() -> Result<()> {
     let is_localhost = args.address == "127.0.0.1" || args.address == "localhost" || args.address == "::1";
     
     // Initialize auth config
-    let auth_config = AuthConfig::new(args.username, args.password);
-    if auth_config.is_auth_required() {
+    let password_hash = resolve_password_hash(&args)?;
+    let auth_config = AuthConfig::new(args.username.clone(), password_hash, &config)?;
+    if auth_config.is_basic_auth_enabled() {
         println!("Basic authentication enabled");
     }
+    if !auth_config.tokens.is_empty() {
+        println!("Token authentication enabled with {} token(s)", auth_config.tokens.len());
+    }
+    if !config.network.localhost_bypass {
+        println!("Localhost requests must authenticate");
+    }
     
-    let mut server = HttpServer::new(move || {
+    let rate_limiter = RateLimiter::new(&config.rate_limit).with_token_limits(&config.tokens);
+    let hooks = web::Data::new(Hooks::new(&config.hooks).context("Invalid [[hooks]] configuration")?);
+
+    let tls_config = load_tls_config(&args, &config)?;
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+
+    manager.lock().unwrap().set_base_url(format!("{}://localhost:{}", scheme, args.port));
+    services::quiet_hours::start_flush_task(manager.clone());
+    services::digest::start_digest_task(manager.clone());
+    services::relay::start(&config.relay, manager.clone()).context("Failed to start the relay")?;
+    MqttInput::new(&config.mqtt, manager.clone(), hooks.clone().into_inner())
+        .and_then(MqttInput::start)
+        .context("Failed to start the MQTT input")?;
+    SmtpReceiver::new(&config.smtp, manager.clone())
+        .and_then(SmtpReceiver::start)
+        .context("Failed to start the SMTP receiver")?;
+    SyslogListener::new(&config.syslog, manager.clone())
+        .and_then(SyslogListener::start)
+        .context("Failed to start the syslog listener")?;
+
+    let config = web::Data::new(config);
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(manager.clone()))
+            .app_data(config.clone())
+            .app_data(hooks.clone())
             .wrap(AuthMiddleware::new(auth_config.clone()))
-            .route("/notify", web::post().to(handlers::send_notification))
+            .service(
+                web::resource("/notify")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::send_notification))
+            )
+            .service(
+                web::resource("/message")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::gotify_message))
+            )
+            .route("/clipboard", web::post().to(handlers::set_clipboard))
+            .route("/clipboard", web::get().to(handlers::get_clipboard))
+            .route("/dnd", web::post().to(handlers::set_dnd))
+            .route("/dnd", web::get().to(handlers::get_dnd))
+            .route("/digests/{id}", web::get().to(handlers::get_digest))
+            .route("/relay/activations", web::post().to(handlers::relay_activation))
+            .service(
+                web::resource("/integrations/alertmanager")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::integrations::alertmanager_webhook))
+            )
+            .service(
+                web::resource("/integrations/github")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::integrations::github_webhook))
+            )
+            .service(
+                web::resource("/integrations/gitlab")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::integrations::gitlab_webhook))
+            )
+            .service(
+                web::resource("/integrations/slack/{channel}")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::integrations::slack_webhook))
+            )
+            .service(
+                web::resource("/hooks/{name}")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::integrations::hook_webhook))
+            )
+            .route("/hooks/{name}/dry-run", web::post().to(handlers::integrations::hook_dry_run))
+            // ntfy topics match any single path segment, so they have to come last
+            .service(
+                web::resource("/")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::ntfy_publish_json))
+            )
+            .service(
+                web::resource("/{topic}")
+                    .wrap(RateLimitMiddleware::new(rate_limiter.clone()))
+                    .route(web::post().to(handlers::ntfy_publish))
+                    .route(web::put().to(handlers::ntfy_publish))
+            )
     })
-    .bind(&bind_addr)?;
+    .on_connect(tls::extract_peer_certificate);
+
+    let mut server = match &tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(&bind_addr, tls_config.clone())?,
+        None => server.bind(&bind_addr)?,
+    };
 
     // Only bind to localhost if we're not already bound to it
     if !is_localhost {
         let localhost_addr = format!("127.0.0.1:{}", args.port);
-        server = server.bind(&localhost_addr)?;
-        println!("Starting notification server on http://{} and http://{}", bind_addr, localhost_addr);
+        server = match &tls_config {
+            Some(tls_config) => server.bind_rustls_0_23(&localhost_addr, tls_config.clone())?,
+            None => server.bind(&localhost_addr)?,
+        };
+        println!("Starting notification server on {}://{} and {}://{}", scheme, bind_addr, scheme, localhost_addr);
     } else {
-        println!("Starting notification server on http://{}", bind_addr);
+        println!("Starting notification server on {}://{}", scheme, bind_addr);
     }
     
     server.workers(4).run().await?;
@@ -5,7 +5,10 @@ use windows::{
     Data::Xml::Dom::*,
 };
 use std::path::Path;
-use super::types::{NotificationType, NotificationData, ImagePosition};
+use super::types::{NotificationType, NotificationData, ImagePosition, DefaultAction, Priority, NotificationLink, is_web_url};
+
+/// Windows shows at most five buttons on a toast and rejects toasts with more
+const MAX_ACTIONS: usize = 5;
 
 pub struct BasicNotification {
     pub title: String,
@@ -14,17 +17,31 @@ pub struct BasicNotification {
     pub image_position: Option<ImagePosition>,
     pub file_paths: Option<Vec<String>>,
     pub callback_command: Option<String>,
+    pub default_action: Option<Vec<DefaultAction>>,
+    pub url: Option<String>,
+    pub sender: Option<String>,
+    pub priority: Priority,
+    pub links: Vec<NotificationLink>,
+    /// How often this notification was received within the dedup window, shown as "×N" above 1
+    pub repeat_count: u32,
+    /// Show without sound regardless of priority, used during quiet hours
+    pub silent: bool,
+    /// Go straight to Action Center regardless of priority
+    pub suppress_popup: bool,
+    pub origin: Option<String>,
 }
 
-const TOAST_TEMPLATE: &str = r#"<toast launch="action=mainContent&amp;tag={tag}" activationType="foreground" duration="long">
+const TOAST_TEMPLATE: &str = r#"<toast launch="action=mainContent&amp;tag={tag}" activationType="foreground" duration="{duration}"{scenario}>
     <visual>
         <binding template="ToastGeneric">
             {image}
             <text>{title}</text>
             <text>{message}</text>
+            {attribution}
         </binding>
     </visual>
-    <audio src="ms-winsoundevent:Notification.Default"/>
+    {actions}
+    {audio}
 </toast>"#;
 
 impl NotificationType for BasicNotification {
@@ -51,11 +68,47 @@ impl NotificationType for BasicNotification {
 
         log::debug!("Generated image XML: {}", image_xml);
 
+        let attribution_xml = match &self.sender {
+            Some(sender) => format!("<text placement=\"attribution\">via {}</text>", escape_xml(sender)),
+            None => String::new(),
+        };
+
+        let title = if self.repeat_count > 1 {
+            format!("{} ×{}", self.title, self.repeat_count)
+        } else {
+            self.title.clone()
+        };
+
+        let audio_xml = match self.priority.audio() {
+            Some(audio) if !self.silent => format!("<audio src=\"{}\"/>", audio),
+            _ => r#"<audio silent="true"/>"#.to_string(),
+        };
+        let scenario = match self.priority.scenario() {
+            Some(scenario) => format!(" scenario=\"{}\"", scenario),
+            None => String::new(),
+        };
+
+        let actions_xml = if self.links.is_empty() {
+            String::new()
+        } else {
+            let actions: String = self.links.iter()
+                .take(MAX_ACTIONS)
+                .map(|link| format!("<action content=\"{}\" arguments=\"{}\" activationType=\"protocol\"/>",
+                    escape_xml(&link.label), escape_xml(&link.url)))
+                .collect();
+            format!("<actions>{}</actions>", actions)
+        };
+
         let toast_xml = TOAST_TEMPLATE
             .replace("{tag}", &tag)
-            .replace("{title}", &escape_xml(&self.title))
+            .replace("{duration}", self.priority.duration())
+            .replace("{scenario}", &scenario)
+            .replace("{title}", &escape_xml(&title))
             .replace("{message}", &escape_xml(&self.message))
-            .replace("{image}", &image_xml);
+            .replace("{image}", &image_xml)
+            .replace("{attribution}", &attribution_xml)
+            .replace("{actions}", &actions_xml)
+            .replace("{audio}", &audio_xml);
 
         log::debug!("Generated toast XML: {}", toast_xml);
         Ok(toast_xml)
@@ -71,6 +124,13 @@ impl NotificationType for BasicNotification {
         let tag = format!("notification_{}", uuid::Uuid::new_v4());
         notification.SetTag(&HSTRING::from(tag))?;
 
+        if self.suppress_popup || self.priority.suppress_popup() {
+            notification.SetSuppressPopup(true)?;
+        }
+        if self.priority >= Priority::High {
+            notification.SetPriority(ToastNotificationPriority::High)?;
+        }
+
         Ok(notification)
     }
 
@@ -80,6 +140,9 @@ impl NotificationType for BasicNotification {
             message: self.message.clone(),
             image_path: self.image_path.clone(),
             file_paths: self.file_paths.clone(),
+            default_action: self.default_action.clone(),
+            url: self.url.clone(),
+            origin: self.origin.clone(),
         }
     }
 }
@@ -93,14 +156,72 @@ impl From<super::types::NotificationRequest> for BasicNotification {
             image_position: request.image_position,
             file_paths: request.file_paths,
             callback_command: request.callback_command,
+            default_action: request.default_action,
+            url: request.url,
+            sender: request.sender,
+            priority: request.priority,
+            // Links come from payloads of every integration, so they are checked here once
+            links: request.links.into_iter()
+                .filter(|link| {
+                    let allowed = is_web_url(&link.url);
+                    if !allowed {
+                        log::warn!("Dropping link {} with a non-http URL: {}", link.label, link.url);
+                    }
+                    allowed
+                })
+                .collect(),
+            repeat_count: 1,
+            silent: false,
+            suppress_popup: false,
+            origin: request.origin,
         }
     }
 }
 
-fn escape_xml(input: &str) -> String {
+pub(super) fn escape_xml(input: &str) -> String {
     input
         .replace('&', "&amp;")
         .replace('"', "&quot;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::NotificationRequest;
+
+    fn link(url: &str) -> NotificationLink {
+        NotificationLink { label: "Open".to_string(), url: url.to_string() }
+    }
+
+    #[test]
+    fn only_web_links_become_buttons() {
+        let mut request = NotificationRequest::new("Build failed", "main is red");
+        request.links = vec![
+            link("https://ci.example.com/run/1"),
+            link("javascript:alert(1)"),
+            link("file:///C:/Windows/System32/calc.exe"),
+            link("ms-settings:privacy"),
+            link("HTTP://example.com"),
+        ];
+
+        let notification = BasicNotification::from(request);
+        let urls: Vec<&str> = notification.links.iter().map(|link| link.url.as_str()).collect();
+        assert_eq!(urls, ["https://ci.example.com/run/1", "HTTP://example.com"]);
+
+        let xml = notification.prepare_xml().unwrap();
+        assert!(xml.contains("arguments=\"https://ci.example.com/run/1\""));
+        assert!(!xml.contains("calc.exe"));
+    }
+
+    #[test]
+    fn web_urls_are_recognized() {
+        assert!(is_web_url("http://example.com"));
+        assert!(is_web_url("HTTPS://example.com"));
+        assert!(!is_web_url("https:example.com"));
+        assert!(!is_web_url("ftp://example.com"));
+        assert!(!is_web_url("shell:startup"));
+        assert!(!is_web_url("é"));
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+use windows::{
+    core::*,
+    UI::Notifications::*,
+    Data::Xml::Dom::*,
+};
+use super::basic::escape_xml;
+use super::types::{NotificationType, NotificationData};
+
+pub const CONFIRM_ALLOW: &str = "allow";
+pub const CONFIRM_DENY: &str = "deny";
+
+/// A toast with Allow / Deny buttons used to confirm sensitive remote operations
+pub struct ConfirmationNotification {
+    pub title: String,
+    pub message: String,
+}
+
+const CONFIRM_TEMPLATE: &str = r#"<toast scenario="reminder" activationType="foreground">
+    <visual>
+        <binding template="ToastGeneric">
+            <text>{title}</text>
+            <text>{message}</text>
+        </binding>
+    </visual>
+    <actions>
+        <action content="Allow" arguments="{allow}" activationType="foreground"/>
+        <action content="Deny" arguments="{deny}" activationType="foreground"/>
+    </actions>
+</toast>"#;
+
+impl NotificationType for ConfirmationNotification {
+    fn prepare_xml(&self) -> Result<String> {
+        let toast_xml = CONFIRM_TEMPLATE
+            .replace("{title}", &escape_xml(&self.title))
+            .replace("{message}", &escape_xml(&self.message))
+            .replace("{allow}", CONFIRM_ALLOW)
+            .replace("{deny}", CONFIRM_DENY);
+
+        log::debug!("Generated confirmation XML: {}", toast_xml);
+        Ok(toast_xml)
+    }
+
+    fn create_notification(&self, xml: &str) -> Result<ToastNotification> {
+        let xml_doc = XmlDocument::new()?;
+        let xml_string: HSTRING = xml.into();
+        xml_doc.LoadXml(&xml_string)?;
+
+        Ok(ToastNotification::CreateToastNotification(&xml_doc)?)
+    }
+
+    fn get_callback_data(&self) -> NotificationData {
+        NotificationData {
+            callback_command: None,
+            message: self.message.clone(),
+            image_path: None,
+            file_paths: None,
+            default_action: Some(Vec::new()),
+            url: None,
+            origin: None,
+        }
+    }
+}
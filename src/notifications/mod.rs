@@ -1,5 +1,7 @@
 mod basic;
+mod confirm;
 mod types;
 
 pub use basic::BasicNotification;
-pub use types::{NotificationRequest, NotificationData, NotificationType, NotificationKind, ImagePosition};
+pub use confirm::{ConfirmationNotification, CONFIRM_ALLOW};
+pub use types::{NotificationRequest, NotificationData, NotificationType, NotificationKind, ImagePosition, DefaultAction, Priority, NotificationLink, is_web_url};
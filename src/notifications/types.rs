@@ -22,6 +22,112 @@ impl Default for ImagePosition {
     }
 }
 
+/// What happens when the body of a toast is clicked and no callback command is set
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DefaultAction {
+    CopyText,
+    CopyImage,
+    CopyFiles,
+    OpenFolder,
+    OpenFile,
+    OpenUrl,
+}
+
+impl DefaultAction {
+    /// Parses a comma separated list such as `copy-text,open-folder`. `none` yields an empty list.
+    pub fn parse_list(input: &str) -> Result<Vec<DefaultAction>> {
+        let mut actions = Vec::new();
+        for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if part.eq_ignore_ascii_case("none") {
+                continue;
+            }
+            let action = part.parse::<DefaultAction>()?;
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        Ok(actions)
+    }
+}
+
+impl std::str::FromStr for DefaultAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "copy-text" => Ok(DefaultAction::CopyText),
+            "copy-image" => Ok(DefaultAction::CopyImage),
+            "copy-files" => Ok(DefaultAction::CopyFiles),
+            "open-folder" => Ok(DefaultAction::OpenFolder),
+            "open-file" => Ok(DefaultAction::OpenFile),
+            "open-url" => Ok(DefaultAction::OpenUrl),
+            other => Err(anyhow::anyhow!("Unknown default action: {}", other)),
+        }
+    }
+}
+
+/// How important a notification is. Decides the toast's sound, duration and whether it pops up.
+/// Only `urgent` breaks through quiet hours and Do Not Disturb.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Min,
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    /// Sound played with the toast, `None` for silent
+    pub fn audio(&self) -> Option<&'static str> {
+        match self {
+            Priority::Min | Priority::Low => None,
+            Priority::Normal => Some("ms-winsoundevent:Notification.Default"),
+            Priority::High => Some("ms-winsoundevent:Notification.Reminder"),
+            Priority::Urgent => Some("ms-winsoundevent:Notification.Looping.Alarm"),
+        }
+    }
+
+    /// How long the popup stays on screen
+    pub fn duration(&self) -> &'static str {
+        match self {
+            Priority::Min | Priority::Low => "short",
+            Priority::Normal | Priority::High | Priority::Urgent => "long",
+        }
+    }
+
+    /// Urgent toasts use the important notification scenario, which can break through Focus Assist
+    pub fn scenario(&self) -> Option<&'static str> {
+        match self {
+            Priority::Urgent => Some("urgent"),
+            _ => None,
+        }
+    }
+
+    /// Low priorities skip the popup and go straight to Action Center
+    pub fn suppress_popup(&self) -> bool {
+        matches!(self, Priority::Min | Priority::Low)
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "min" => Ok(Priority::Min),
+            "low" => Ok(Priority::Low),
+            "normal" | "" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            "urgent" => Ok(Priority::Urgent),
+            other => Err(anyhow::anyhow!("Unknown priority: {}", other)),
+        }
+    }
+}
+
 impl Default for NotificationKind {
     fn default() -> Self {
         NotificationKind::Basic
@@ -42,6 +148,72 @@ pub struct NotificationRequest {
     pub file_paths: Option<Vec<String>>,
     #[serde(default)]
     pub callback_command: Option<String>,
+    #[serde(default)]
+    pub default_action: Option<Vec<DefaultAction>>,
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Label of the authenticated sender, shown as attribution on the toast.
+    /// Never deserialized from a request body, only set from the caller's `AuthIdentity`, input
+    /// config or, for the hubs in `relay.hubs`, the forwarded form.
+    #[serde(skip_deserializing)]
+    pub sender: Option<String>,
+    /// Repeats with the same key from the same sender update one toast instead of showing new ones.
+    /// Defaults to a hash of title, message and sender.
+    #[serde(default)]
+    pub dedup_key: Option<String>,
+    /// Replaces the last toast from the same sender with this key, such as the firing toast
+    /// of an alert group once it resolves. Takes precedence over `dedup_key`.
+    #[serde(default)]
+    pub replace_key: Option<String>,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Collect into the named digest instead of showing right away
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// Buttons opening a URL
+    #[serde(default)]
+    pub links: Vec<NotificationLink>,
+    /// Set by inputs that want to hear about clicks, reported back in `ActivationEvent`s
+    #[serde(default)]
+    pub origin: Option<String>,
+}
+
+/// A toast button that opens `url` in the default browser
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationLink {
+    pub label: String,
+    pub url: String,
+}
+
+/// True for `http` and `https` URLs, the only ones toasts may open. Anything else
+/// could launch an arbitrary protocol handler on this machine.
+pub fn is_web_url(url: &str) -> bool {
+    ["http://", "https://"].iter()
+        .any(|scheme| url.get(..scheme.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme)))
+}
+
+impl NotificationRequest {
+    /// A plain text notification with every optional field unset
+    pub fn new(title: &str, message: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            message: message.to_string(),
+            notification_type: NotificationKind::default(),
+            image_path: None,
+            image_position: None,
+            file_paths: None,
+            callback_command: None,
+            default_action: None,
+            url: None,
+            sender: None,
+            dedup_key: None,
+            replace_key: None,
+            priority: Priority::Normal,
+            digest: None,
+            links: Vec::new(),
+            origin: None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -50,6 +222,9 @@ pub struct NotificationData {
     pub message: String,
     pub image_path: Option<String>,
     pub file_paths: Option<Vec<String>>,
+    pub default_action: Option<Vec<DefaultAction>>,
+    pub url: Option<String>,
+    pub origin: Option<String>,
 }
 
 pub trait NotificationType {
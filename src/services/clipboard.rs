@@ -1,60 +1,432 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow, Context};
+use std::io::Cursor;
+use std::path::Path;
 use windows::{
+    core::w,
     Win32::System::DataExchange::*,
+    Win32::System::Ole::{CF_UNICODETEXT, CF_DIB, CF_HDROP},
     Win32::Foundation::*,
     Win32::System::Memory::*,
 };
 
+const HTML_FRAGMENT_START: &str = "<!--StartFragment-->";
+const HTML_FRAGMENT_END: &str = "<!--EndFragment-->";
+const DROPFILES_SIZE: u32 = 20;
+
 pub struct ClipboardService;
 
 impl ClipboardService {
     pub fn set_text(text: &str) -> Result<()> {
         log::info!("Attempting to copy text to clipboard: {}", text);
-        
+        Self::set_data(&[(CF_UNICODETEXT.0 as u32, encode_utf16(text))])?;
+        log::info!("Text successfully copied to clipboard");
+        Ok(())
+    }
+
+    /// Places an image on the clipboard both as `CF_DIB` and as a registered `PNG` format
+    pub fn set_image(image_path: &str) -> Result<()> {
+        log::info!("Attempting to copy image to clipboard: {}", image_path);
+        let bytes = std::fs::read(image_path)
+            .with_context(|| format!("Failed to read image {}", image_path))?;
+        let image = image::load_from_memory(&bytes)
+            .context("Failed to decode image")?;
+
+        let rgba = image.to_rgba8();
+        let dib = rgba_to_dib(rgba.width(), rgba.height(), rgba.as_raw());
+
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, image::ImageOutputFormat::Png)
+            .context("Failed to encode image as PNG")?;
+
+        let png_format = unsafe { RegisterClipboardFormatW(w!("PNG")) };
+        let mut entries = vec![(CF_DIB.0 as u32, dib)];
+        if png_format != 0 {
+            entries.push((png_format, png.into_inner()));
+        }
+
+        Self::set_data(&entries)?;
+        log::info!("Image successfully copied to clipboard");
+        Ok(())
+    }
+
+    /// Places an HTML fragment on the clipboard as `CF_HTML`, with plain text as a fallback
+    pub fn set_html(html: &str, text: &str) -> Result<()> {
+        log::info!("Attempting to copy HTML to clipboard");
+        let html_format = unsafe { RegisterClipboardFormatW(w!("HTML Format")) };
+        if html_format == 0 {
+            return Err(anyhow!("Failed to register HTML clipboard format"));
+        }
+
+        let mut cf_html = build_cf_html(html).into_bytes();
+        cf_html.push(0);
+
+        Self::set_data(&[
+            (html_format, cf_html),
+            (CF_UNICODETEXT.0 as u32, encode_utf16(text)),
+        ])?;
+        log::info!("HTML successfully copied to clipboard");
+        Ok(())
+    }
+
+    /// Places a list of files on the clipboard as `CF_HDROP`, so they can be pasted in explorer
+    pub fn set_files(file_paths: &[String]) -> Result<()> {
+        log::info!("Attempting to copy {} file(s) to clipboard", file_paths.len());
+        let paths: Vec<String> = file_paths.iter()
+            .filter(|path| Path::new(path).exists())
+            .cloned()
+            .collect();
+        if paths.is_empty() {
+            return Err(anyhow!("None of the files exist"));
+        }
+
+        Self::set_data(&[(CF_HDROP.0 as u32, build_drop_files(&paths))])?;
+        log::info!("Files successfully copied to clipboard");
+        Ok(())
+    }
+
+    pub fn get_text() -> Result<Option<String>> {
+        let data = match Self::get_data(CF_UNICODETEXT.0 as u32)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let units: Vec<u16> = data.chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|unit| *unit != 0)
+            .collect();
+        Ok(Some(String::from_utf16_lossy(&units)))
+    }
+
+    /// Returns the clipboard image encoded as PNG, preferring the registered `PNG` format over `CF_DIB`
+    pub fn get_image_png() -> Result<Option<Vec<u8>>> {
+        let png_format = unsafe { RegisterClipboardFormatW(w!("PNG")) };
+        if png_format != 0 {
+            if let Some(png) = Self::get_data(png_format)? {
+                return Ok(Some(png));
+            }
+        }
+
+        let dib = match Self::get_data(CF_DIB.0 as u32)? {
+            Some(dib) => dib,
+            None => return Ok(None),
+        };
+
+        let (width, height, rgba) = dib_to_rgba(&dib)?;
+        let image = image::RgbaImage::from_raw(width, height, rgba)
+            .ok_or_else(|| anyhow!("Invalid bitmap dimensions"))?;
+        let mut png = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(image)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .context("Failed to encode clipboard image as PNG")?;
+        Ok(Some(png.into_inner()))
+    }
+
+    pub fn get_files() -> Result<Option<Vec<String>>> {
+        match Self::get_data(CF_HDROP.0 as u32)? {
+            Some(data) => Ok(Some(parse_drop_files(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn open() -> Result<()> {
         unsafe {
             // Try to open clipboard once with a short timeout
             if !OpenClipboard(HWND(0)).as_bool() {
                 std::thread::sleep(std::time::Duration::from_millis(50));
                 if !OpenClipboard(HWND(0)).as_bool() {
                     log::error!("Failed to open clipboard");
-                    return Ok(());
+                    return Err(anyhow!("Failed to open clipboard"));
                 }
             }
+        }
+        Ok(())
+    }
+
+    /// Copies the raw contents of a clipboard format, or returns `None` if it is not available
+    fn get_data(format: u32) -> Result<Option<Vec<u8>>> {
+        unsafe {
+            if !IsClipboardFormatAvailable(format).as_bool() {
+                return Ok(None);
+            }
+
+            Self::open()?;
 
+            let result = match GetClipboardData(format) {
+                Ok(handle) => {
+                    let h_mem = HGLOBAL(handle.0);
+                    let p_mem = GlobalLock(h_mem);
+                    if p_mem.is_null() {
+                        Err(anyhow!("Failed to lock clipboard memory"))
+                    } else {
+                        let size = GlobalSize(h_mem);
+                        let data = std::slice::from_raw_parts(p_mem as *const u8, size).to_vec();
+                        GlobalUnlock(h_mem);
+                        Ok(Some(data))
+                    }
+                }
+                Err(e) => Err(anyhow!("Failed to get clipboard data: {}", e)),
+            };
+
+            CloseClipboard();
+            result
+        }
+    }
+
+    fn set_data(entries: &[(u32, Vec<u8>)]) -> Result<()> {
+        Self::open()?;
+
+        unsafe {
             // Clear existing content
             let _ = EmptyClipboard();
 
-            // Convert to UTF-16 and add null terminator
-            let mut text_utf16: Vec<u16> = text.encode_utf16().collect();
-            text_utf16.push(0);
-            let byte_len = text_utf16.len() * 2;
-
-            // Allocate memory in one go
-            let h_mem = GlobalAlloc(GMEM_MOVEABLE, byte_len)?;
-            let p_mem = GlobalLock(h_mem);
-            
-            if !p_mem.is_null() {
-                std::ptr::copy_nonoverlapping(
-                    text_utf16.as_ptr() as *const u8,
-                    p_mem as *mut u8,
-                    byte_len
-                );
-
-                GlobalUnlock(h_mem);
-
-                if SetClipboardData(13u32, HANDLE(h_mem.0)).is_ok() {
-                    log::info!("Text successfully copied to clipboard");
-                } else {
-                    log::error!("Failed to set clipboard data");
-                    let _ = GlobalFree(h_mem);
+            let mut result = Ok(());
+            for (format, data) in entries {
+                if let Err(e) = Self::set_format(*format, data) {
+                    log::error!("Failed to set clipboard format {}: {}", format, e);
+                    result = Err(e);
+                    break;
                 }
-            } else {
-                log::error!("Failed to lock global memory");
-                let _ = GlobalFree(h_mem);
             }
 
             CloseClipboard();
+            result
+        }
+    }
+
+    /// Copies `data` into movable global memory and hands it to the clipboard.
+    /// Must be called while the clipboard is open.
+    unsafe fn set_format(format: u32, data: &[u8]) -> Result<()> {
+        let h_mem = GlobalAlloc(GMEM_MOVEABLE, data.len())?;
+        let p_mem = GlobalLock(h_mem);
+
+        if p_mem.is_null() {
+            let _ = GlobalFree(h_mem);
+            return Err(anyhow!("Failed to lock global memory"));
+        }
+
+        std::ptr::copy_nonoverlapping(data.as_ptr(), p_mem as *mut u8, data.len());
+        GlobalUnlock(h_mem);
+
+        // On success the clipboard owns the memory, so it must only be freed on failure
+        if SetClipboardData(format, HANDLE(h_mem.0)).is_err() {
+            let _ = GlobalFree(h_mem);
+            return Err(anyhow!("Failed to set clipboard data"));
         }
         Ok(())
     }
 }
+
+/// Encodes text as null-terminated UTF-16 bytes as expected by `CF_UNICODETEXT`
+fn encode_utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+/// Wraps an HTML fragment in the `CF_HTML` description header.
+/// All offsets are byte offsets into the UTF-8 encoded result.
+pub fn build_cf_html(fragment: &str) -> String {
+    let header = |start_html: usize, end_html: usize, start_fragment: usize, end_fragment: usize| {
+        // Offsets are zero padded to ten digits so the header length does not depend on them
+        format!(
+            "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n",
+            start_html, end_html, start_fragment, end_fragment
+        )
+    };
+
+    let prefix = format!("<html><body>\r\n{}", HTML_FRAGMENT_START);
+    let suffix = format!("{}\r\n</body></html>", HTML_FRAGMENT_END);
+
+    let start_html = header(0, 0, 0, 0).len();
+    let start_fragment = start_html + prefix.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + suffix.len();
+
+    format!(
+        "{}{}{}{}",
+        header(start_html, end_html, start_fragment, end_fragment), prefix, fragment, suffix
+    )
+}
+
+/// Converts top-down RGBA pixels into a packed 32-bit `CF_DIB`
+/// (a `BITMAPINFOHEADER` followed by bottom-up BGRA rows).
+pub fn rgba_to_dib(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 40;
+    let row_len = width as usize * 4;
+    let image_size = row_len * height as usize;
+
+    let mut dib = Vec::with_capacity(HEADER_SIZE as usize + image_size);
+    dib.extend_from_slice(&HEADER_SIZE.to_le_bytes()); // biSize
+    dib.extend_from_slice(&(width as i32).to_le_bytes()); // biWidth
+    dib.extend_from_slice(&(height as i32).to_le_bytes()); // biHeight, positive means bottom-up
+    dib.extend_from_slice(&1u16.to_le_bytes()); // biPlanes
+    dib.extend_from_slice(&32u16.to_le_bytes()); // biBitCount
+    dib.extend_from_slice(&0u32.to_le_bytes()); // biCompression = BI_RGB
+    dib.extend_from_slice(&(image_size as u32).to_le_bytes()); // biSizeImage
+    dib.extend_from_slice(&0i32.to_le_bytes()); // biXPelsPerMeter
+    dib.extend_from_slice(&0i32.to_le_bytes()); // biYPelsPerMeter
+    dib.extend_from_slice(&0u32.to_le_bytes()); // biClrUsed
+    dib.extend_from_slice(&0u32.to_le_bytes()); // biClrImportant
+
+    if row_len == 0 {
+        return dib;
+    }
+
+    for row in rgba.chunks_exact(row_len).rev() {
+        for pixel in row.chunks_exact(4) {
+            dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+    }
+    dib
+}
+
+/// Builds the `DROPFILES` structure used by `CF_HDROP`: a 20 byte header
+/// followed by a double null-terminated list of UTF-16 paths.
+pub fn build_drop_files(paths: &[String]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&DROPFILES_SIZE.to_le_bytes()); // pFiles
+    data.extend_from_slice(&0i32.to_le_bytes()); // pt.x
+    data.extend_from_slice(&0i32.to_le_bytes()); // pt.y
+    data.extend_from_slice(&0u32.to_le_bytes()); // fNC
+    data.extend_from_slice(&1u32.to_le_bytes()); // fWide
+
+    for path in paths {
+        data.extend(encode_utf16(path));
+    }
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data
+}
+
+/// Parses a `DROPFILES` structure back into the list of paths it contains
+pub fn parse_drop_files(data: &[u8]) -> Result<Vec<String>> {
+    if data.len() < DROPFILES_SIZE as usize {
+        return Err(anyhow!("DROPFILES structure is truncated"));
+    }
+
+    let offset = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let wide = u32::from_le_bytes([data[16], data[17], data[18], data[19]]) != 0;
+    if offset > data.len() {
+        return Err(anyhow!("DROPFILES file list offset is out of bounds"));
+    }
+
+    let list = &data[offset..];
+    let names: Vec<String> = if wide {
+        let units: Vec<u16> = list.chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        units.split(|unit| *unit == 0)
+            .take_while(|name| !name.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect()
+    } else {
+        list.split(|byte| *byte == 0)
+            .take_while(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect()
+    };
+    Ok(names)
+}
+
+/// Converts an uncompressed 24 or 32-bit `CF_DIB` into top-down RGBA pixels
+pub fn dib_to_rgba(dib: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    const BI_RGB: u32 = 0;
+    const BI_BITFIELDS: u32 = 3;
+
+    if dib.len() < 40 {
+        return Err(anyhow!("Bitmap header is truncated"));
+    }
+
+    let read_u32 = |at: usize| u32::from_le_bytes([dib[at], dib[at + 1], dib[at + 2], dib[at + 3]]);
+    let header_size = read_u32(0) as usize;
+    let width = read_u32(4) as i32;
+    let height = read_u32(8) as i32;
+    let bit_count = u16::from_le_bytes([dib[14], dib[15]]);
+    let compression = read_u32(16);
+
+    if width <= 0 || height == 0 {
+        return Err(anyhow!("Invalid bitmap dimensions"));
+    }
+    if !matches!((bit_count, compression), (24, BI_RGB) | (32, BI_RGB) | (32, BI_BITFIELDS)) {
+        return Err(anyhow!("Unsupported bitmap format: {} bpp, compression {}", bit_count, compression));
+    }
+
+    // BI_BITFIELDS with a plain BITMAPINFOHEADER is followed by three color masks
+    let mut pixels_offset = header_size;
+    if compression == BI_BITFIELDS && header_size == 40 {
+        pixels_offset += 12;
+    }
+
+    let width = width as usize;
+    let rows = height.unsigned_abs() as usize;
+    let bytes_per_pixel = bit_count as usize / 8;
+    let stride = (width * bytes_per_pixel + 3) & !3;
+    if dib.len() < pixels_offset + stride * rows {
+        return Err(anyhow!("Bitmap pixel data is truncated"));
+    }
+
+    let mut rgba = Vec::with_capacity(width * rows * 4);
+    for y in 0..rows {
+        // Positive heights are stored bottom-up
+        let source_row = if height > 0 { rows - 1 - y } else { y };
+        let row = &dib[pixels_offset + source_row * stride..][..width * bytes_per_pixel];
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            let alpha = if bytes_per_pixel == 4 { pixel[3] } else { 255 };
+            rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+        }
+    }
+
+    // Many applications leave the alpha channel of 32-bit bitmaps zeroed
+    if bytes_per_pixel == 4 && rgba.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+        rgba.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+    }
+
+    Ok((width as u32, rows as u32, rgba))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the ten digit offset following `key` in a `CF_HTML` header
+    fn header_offset(html: &str, key: &str) -> usize {
+        let start = html.find(key).unwrap() + key.len();
+        html[start..start + 10].parse().unwrap()
+    }
+
+    #[test]
+    fn cf_html_offsets_point_into_the_produced_bytes() {
+        let fragment = "<b>Grüße</b> €";
+        let html = build_cf_html(fragment);
+        let bytes = html.as_bytes();
+
+        let start_html = header_offset(&html, "StartHTML:");
+        let end_html = header_offset(&html, "EndHTML:");
+        let start_fragment = header_offset(&html, "StartFragment:");
+        let end_fragment = header_offset(&html, "EndFragment:");
+
+        assert!(bytes[start_html..].starts_with(b"<html>"));
+        assert_eq!(end_html, bytes.len());
+        assert_eq!(&bytes[start_fragment..end_fragment], fragment.as_bytes());
+        assert!(bytes[..start_fragment].ends_with(HTML_FRAGMENT_START.as_bytes()));
+        assert!(bytes[end_fragment..].starts_with(HTML_FRAGMENT_END.as_bytes()));
+    }
+
+    #[test]
+    fn dib_has_header_and_bottom_up_bgra_rows() {
+        // 1x2 image: a red pixel on top of a translucent blue one
+        let rgba = [255, 0, 0, 255, 0, 0, 255, 128];
+        let dib = rgba_to_dib(1, 2, &rgba);
+
+        assert_eq!(dib.len(), 40 + 8);
+        assert_eq!(u32::from_le_bytes(dib[0..4].try_into().unwrap()), 40);
+        assert_eq!(i32::from_le_bytes(dib[4..8].try_into().unwrap()), 1);
+        assert_eq!(i32::from_le_bytes(dib[8..12].try_into().unwrap()), 2);
+        assert_eq!(u16::from_le_bytes(dib[14..16].try_into().unwrap()), 32);
+        assert_eq!(u32::from_le_bytes(dib[20..24].try_into().unwrap()), 8);
+
+        // The bottom row comes first and channels are swapped to BGRA
+        assert_eq!(&dib[40..44], &[255, 0, 0, 128]);
+        assert_eq!(&dib[44..48], &[0, 0, 255, 255]);
+    }
+}
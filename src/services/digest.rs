@@ -0,0 +1,290 @@
+use chrono::{Local, NaiveDateTime};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::notifications::{NotificationLink, NotificationRequest, Priority};
+use crate::services::NotificationManager;
+use crate::utils::config::DigestConfig;
+
+/// How often batches are checked for their interval having elapsed
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Emitted batches kept for their "view all" page
+const KEPT_BATCHES: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct DigestItem {
+    pub title: String,
+    pub message: String,
+    pub sender: Option<String>,
+    pub priority: Priority,
+    pub received: NaiveDateTime,
+}
+
+impl From<&NotificationRequest> for DigestItem {
+    fn from(request: &NotificationRequest) -> Self {
+        Self {
+            title: request.title.clone(),
+            message: request.message.clone(),
+            sender: request.sender.clone(),
+            priority: request.priority,
+            received: Local::now().naive_local(),
+        }
+    }
+}
+
+/// Notifications collected under one digest name
+#[derive(Debug, Clone)]
+pub struct DigestBatch {
+    /// Random id used in the URL of the batch's page
+    pub id: String,
+    pub name: String,
+    pub items: Vec<DigestItem>,
+    /// Notifications that were counted but not kept because the batch was full
+    pub overflow: usize,
+    started: Instant,
+}
+
+impl DigestBatch {
+    pub fn new(name: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            name: name.to_string(),
+            items: Vec::new(),
+            overflow: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Every notification in the batch, including the overflow
+    pub fn total(&self) -> usize {
+        self.items.len() + self.overflow
+    }
+
+    /// The summary toast listing the first `top_items` titles, with a link to the batch's page
+    pub fn summary(&self, top_items: usize, base_url: Option<&str>) -> NotificationRequest {
+        let mut lines: Vec<String> = self.items.iter()
+            .take(top_items)
+            .map(|item| match &item.sender {
+                Some(sender) => format!("{} ({})", item.title, sender),
+                None => item.title.clone(),
+            })
+            .collect();
+        if self.total() > lines.len() {
+            lines.push(format!("and {} more", self.total() - lines.len()));
+        }
+
+        let plural = if self.total() == 1 { "" } else { "s" };
+        let mut request = NotificationRequest::new(
+            &format!("{}: {} notification{}", self.name, self.total(), plural),
+            &lines.join("\n"),
+        );
+        // The summary is as important as the most important item, short of breaking through quiet hours
+        request.priority = self.items.iter()
+            .map(|item| item.priority)
+            .max()
+            .unwrap_or_default()
+            .min(Priority::High);
+        if let Some(base_url) = base_url {
+            request.links.push(NotificationLink {
+                label: "View all".to_string(),
+                url: format!("{}/digests/{}", base_url, self.id),
+            });
+        }
+        request
+    }
+}
+
+/// Pending batches per digest name and the recently emitted ones
+pub struct Digests {
+    interval: Duration,
+    max_items: usize,
+    top_items: usize,
+    max_queued: usize,
+    pending: HashMap<String, DigestBatch>,
+    emitted: VecDeque<DigestBatch>,
+}
+
+impl Digests {
+    pub fn new(config: &DigestConfig) -> Self {
+        Self {
+            interval: Duration::from_secs(config.interval_secs),
+            max_items: config.max_items.max(1),
+            top_items: config.top_items,
+            max_queued: config.max_queued,
+            pending: HashMap::new(),
+            emitted: VecDeque::new(),
+        }
+    }
+
+    pub fn top_items(&self) -> usize {
+        self.top_items
+    }
+
+    /// Adds `request` to the batch `name`. Returns the batch once it is full.
+    ///
+    /// Once `max_queued` notifications are kept across all batches, later ones are only counted
+    /// in their batch's overflow, and ones that would start yet another batch are dropped.
+    pub fn add(&mut self, name: &str, request: &NotificationRequest) -> Option<DigestBatch> {
+        let is_full = self.pending.values().map(|batch| batch.items.len()).sum::<usize>() >= self.max_queued;
+        if is_full && !self.pending.contains_key(name) && self.pending.len() >= self.max_queued {
+            log::warn!("Dropping notification for digest {}: too many pending digests", name);
+            return None;
+        }
+
+        let batch = self.pending.entry(name.to_string())
+            .or_insert_with(|| DigestBatch::new(name));
+        if is_full {
+            batch.overflow += 1;
+        } else {
+            batch.items.push(DigestItem::from(request));
+        }
+        if batch.total() >= self.max_items {
+            self.pending.remove(name)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the batches that have been collecting for at least the interval
+    pub fn take_due(&mut self) -> Vec<DigestBatch> {
+        let due: Vec<String> = self.pending.iter()
+            .filter(|(_, batch)| batch.started.elapsed() >= self.interval)
+            .map(|(name, _)| name.clone())
+            .collect();
+        due.iter()
+            .filter_map(|name| self.pending.remove(name))
+            .collect()
+    }
+
+    /// Keeps an emitted batch so its page can be served
+    pub fn store(&mut self, batch: DigestBatch) {
+        self.emitted.push_back(batch);
+        while self.emitted.len() > KEPT_BATCHES {
+            self.emitted.pop_front();
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&DigestBatch> {
+        self.emitted.iter().find(|batch| batch.id == id)
+    }
+}
+
+/// Periodically emits digests whose interval has elapsed
+pub fn start_digest_task(manager: Arc<Mutex<NotificationManager>>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut manager = manager.lock().unwrap();
+            if let Err(e) = manager.flush_due_digests().await {
+                log::error!("Failed to send digest: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digests(max_items: usize, max_queued: usize) -> Digests {
+        Digests::new(&DigestConfig { interval_secs: 3600, max_items, top_items: 2, max_queued })
+    }
+
+    fn request(title: &str, sender: Option<&str>, priority: Priority) -> NotificationRequest {
+        let mut request = NotificationRequest::new(title, "message");
+        request.sender = sender.map(str::to_string);
+        request.priority = priority;
+        request
+    }
+
+    #[test]
+    fn batches_by_name_until_max_items() {
+        let mut digests = digests(3, 100);
+        assert!(digests.add("backups", &request("Backup 1", None, Priority::Low)).is_none());
+        assert!(digests.add("builds", &request("Build 1", None, Priority::Low)).is_none());
+        assert!(digests.add("backups", &request("Backup 2", None, Priority::Low)).is_none());
+
+        let batch = digests.add("backups", &request("Backup 3", None, Priority::Low)).unwrap();
+        assert_eq!(batch.name, "backups");
+        let titles: Vec<&str> = batch.items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, ["Backup 1", "Backup 2", "Backup 3"]);
+
+        // The other batch keeps collecting, and a full batch starts over
+        assert!(digests.add("backups", &request("Backup 4", None, Priority::Low)).is_none());
+        assert_eq!(digests.pending["builds"].items.len(), 1);
+        assert_eq!(digests.pending["backups"].items.len(), 1);
+    }
+
+    #[test]
+    fn take_due_waits_for_the_interval() {
+        let mut digests = Digests::new(&DigestConfig { interval_secs: 0, ..DigestConfig::default() });
+        digests.add("backups", &request("Backup 1", None, Priority::Low));
+        assert_eq!(digests.take_due().len(), 1);
+        assert!(digests.take_due().is_empty());
+
+        let mut digests = Digests::new(&DigestConfig::default());
+        digests.add("backups", &request("Backup 1", None, Priority::Low));
+        assert!(digests.take_due().is_empty());
+    }
+
+    #[test]
+    fn summary_lists_the_top_items() {
+        let mut batch = DigestBatch::new("nightly");
+        for (title, sender, priority) in [("Backup done", Some("nas"), Priority::Low), ("Disk low", None, Priority::High), ("Update", None, Priority::Normal)] {
+            batch.items.push(DigestItem::from(&request(title, sender, priority)));
+        }
+
+        let summary = batch.summary(2, Some("http://localhost:3000"));
+        assert_eq!(summary.title, "nightly: 3 notifications");
+        assert_eq!(summary.message, "Backup done (nas)\nDisk low\nand 1 more");
+        assert_eq!(summary.priority, Priority::High);
+        assert_eq!(summary.links.len(), 1);
+        assert_eq!(summary.links[0].url, format!("http://localhost:3000/digests/{}", batch.id));
+
+        assert!(batch.summary(5, None).links.is_empty());
+    }
+
+    #[test]
+    fn summary_never_breaks_through_quiet_hours() {
+        let mut batch = DigestBatch::new("alerts");
+        batch.items.push(DigestItem::from(&request("Down", None, Priority::Urgent)));
+        let summary = batch.summary(5, None);
+        assert_eq!(summary.title, "alerts: 1 notification");
+        assert_eq!(summary.priority, Priority::High);
+    }
+
+    #[test]
+    fn caps_the_pending_notifications() {
+        let mut digests = digests(10, 3);
+        for index in 0..3 {
+            digests.add("noisy", &request(&format!("Spam {}", index), None, Priority::Low));
+        }
+        assert!(digests.add("noisy", &request("Spam 3", None, Priority::Low)).is_none());
+        assert!(digests.add("quiet", &request("Backup", None, Priority::Low)).is_none());
+        assert_eq!(digests.pending["noisy"].items.len(), 3);
+        assert_eq!(digests.pending["noisy"].overflow, 1);
+        assert_eq!(digests.pending["quiet"].items.len(), 0);
+        assert_eq!(digests.pending["quiet"].overflow, 1);
+
+        // Overflow counts towards max_items, so the noisy batch is still emitted
+        let mut batch = None;
+        for index in 4..10 {
+            batch = digests.add("noisy", &request(&format!("Spam {}", index), None, Priority::Low));
+        }
+        let batch = batch.unwrap();
+        assert_eq!((batch.items.len(), batch.overflow, batch.total()), (3, 7, 10));
+        assert_eq!(batch.summary(2, None).message, "Spam 0\nSpam 1\nand 8 more");
+    }
+
+    #[test]
+    fn drops_new_digests_once_full() {
+        let mut digests = digests(10, 2);
+        digests.add("a", &request("1", None, Priority::Low));
+        digests.add("b", &request("2", None, Priority::Low));
+        assert!(digests.add("c", &request("3", None, Priority::Low)).is_none());
+        assert!(!digests.pending.contains_key("c"));
+        assert_eq!(digests.pending.len(), 2);
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::collections::HashMap;
+
+use crate::notifications::{DefaultAction, NotificationRequest, Priority};
+
+/// A generic webhook route as written in `[[hooks]]`. Each field is a JSONPath expression
+/// such as `$.alert.name`, or text with `{$.path}` placeholders.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HookConfig {
+    pub name: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub priority: Option<String>,
+    /// Opened when the toast is clicked
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Priority per extracted priority value, such as `{ critical = "urgent", "5" = "high" }`.
+    /// Values not listed are parsed as priority names.
+    #[serde(default)]
+    pub priorities: HashMap<String, Priority>,
+    /// Shown as attribution instead of the hook's name
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Text(String),
+    Path(JsonPath),
+}
+
+/// A field of a hook, evaluated against the request body
+#[derive(Debug, Clone)]
+struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+impl Template {
+    fn parse(source: &str) -> Result<Self> {
+        let source = source.trim();
+        let parse_path = |path: &str| JsonPath::parse(path)
+            .map(TemplatePart::Path)
+            .map_err(|e| anyhow!("Invalid JSONPath {}: {}", path, e));
+
+        // A field that is an expression on its own keeps non-string values intact
+        if source.starts_with('$') {
+            return Ok(Self { parts: vec![parse_path(source)?] });
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{$") {
+            let end = rest[start..].find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow!("Unclosed placeholder in {}", source))?;
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            parts.push(parse_path(&rest[start + 1..end])?);
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    fn render(&self, payload: &Value) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.clone(),
+                TemplatePart::Path(path) => path.query(payload)
+                    .iter()
+                    .map(|value| value_to_text(value))
+                    .filter(|text| !text.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+}
+
+fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(values) => values.iter().map(value_to_text).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+/// A configured hook with its expressions parsed
+#[derive(Debug, Clone)]
+pub struct HookRoute {
+    name: String,
+    title: Option<Template>,
+    message: Option<Template>,
+    image_url: Option<Template>,
+    priority: Option<Template>,
+    url: Option<Template>,
+    priorities: HashMap<String, Priority>,
+    sender: Option<String>,
+}
+
+/// What a hook made of a request body, as shown by the dry-run endpoint
+#[derive(Debug, Serialize)]
+pub struct MappedHook {
+    pub request: NotificationRequest,
+    /// Downloaded and shown in the toast when the request is sent
+    pub image_url: Option<String>,
+}
+
+impl HookRoute {
+    fn new(config: &HookConfig) -> Result<Self> {
+        let template = |field: &Option<String>| field.as_deref().map(Template::parse).transpose();
+        Ok(Self {
+            name: config.name.clone(),
+            title: template(&config.title).context("Invalid title")?,
+            message: template(&config.message).context("Invalid message")?,
+            image_url: template(&config.image_url).context("Invalid image_url")?,
+            priority: template(&config.priority).context("Invalid priority")?,
+            url: template(&config.url).context("Invalid url")?,
+            priorities: config.priorities.iter()
+                .map(|(value, priority)| (value.to_lowercase(), *priority))
+                .collect(),
+            sender: config.sender.clone(),
+        })
+    }
+
+    /// Maps `payload` onto a notification. The route's own sender wins for the attribution,
+    /// then `token_sender`, then the hook's name.
+    pub fn apply(&self, payload: &Value, token_sender: Option<&str>) -> MappedHook {
+        let render = |template: &Option<Template>| template.as_ref()
+            .map(|template| template.render(payload))
+            .filter(|text| !text.is_empty());
+
+        let title = render(&self.title).unwrap_or_else(|| self.name.clone());
+        let message = render(&self.message).unwrap_or_default();
+        let mut request = NotificationRequest::new(&title, &message);
+        let sender = self.sender.as_deref().or(token_sender).unwrap_or(&self.name);
+        request.sender = Some(sender.to_string());
+
+        if let Some(priority) = render(&self.priority) {
+            request.priority = match self.priorities.get(&priority.to_lowercase()) {
+                Some(priority) => *priority,
+                None => priority.parse().unwrap_or_else(|_| {
+                    log::warn!("Hook {} extracted unknown priority {:?}", self.name, priority);
+                    Priority::default()
+                }),
+            };
+        }
+        if let Some(url) = render(&self.url) {
+            request.url = Some(url);
+            request.default_action = Some(vec![DefaultAction::OpenUrl]);
+        }
+
+        MappedHook { request, image_url: render(&self.image_url) }
+    }
+}
+
+/// The routes served under `/hooks/{name}`
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    routes: HashMap<String, HookRoute>,
+}
+
+impl Hooks {
+    pub fn new(configs: &[HookConfig]) -> Result<Self> {
+        let mut routes = HashMap::new();
+        for config in configs {
+            let route = HookRoute::new(config).with_context(|| format!("Invalid hook {}", config.name))?;
+            if routes.insert(config.name.clone(), route).is_some() {
+                return Err(anyhow!("Hook {} is configured twice", config.name));
+            }
+        }
+        Ok(Self { routes })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HookRoute> {
+        self.routes.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(name: &str) -> HookConfig {
+        HookConfig {
+            name: name.to_string(),
+            title: None,
+            message: None,
+            image_url: None,
+            priority: None,
+            url: None,
+            priorities: HashMap::new(),
+            sender: None,
+        }
+    }
+
+    fn route(config: HookConfig) -> HookRoute {
+        HookRoute::new(&config).unwrap()
+    }
+
+    fn uptime_kuma() -> Value {
+        serde_json::from_str(include_str!("../../tests/fixtures/hooks/uptime_kuma.json")).unwrap()
+    }
+
+    #[test]
+    fn maps_fields_with_paths_and_placeholders() {
+        let route = route(HookConfig {
+            title: Some("{$.monitor.name} is down ({$.heartbeat.retries} retries)".to_string()),
+            message: Some("$.heartbeat.msg".to_string()),
+            url: Some("$.monitor.url".to_string()),
+            ..hook("uptime")
+        });
+        let mapped = route.apply(&uptime_kuma(), None);
+
+        assert_eq!(mapped.request.title, "Website is down (3 retries)");
+        assert_eq!(mapped.request.message, "connect ECONNREFUSED 10.0.0.5:443");
+        assert_eq!(mapped.request.url.as_deref(), Some("https://www.example.com"));
+        assert!(matches!(mapped.request.default_action.as_deref(), Some([DefaultAction::OpenUrl])));
+        assert_eq!(mapped.image_url, None);
+    }
+
+    #[test]
+    fn renders_non_string_values() {
+        let route = route(HookConfig {
+            title: Some("Monitor {$.monitor.id}: {$.heartbeat.ping}".to_string()),
+            message: Some("$.monitor.tags[*].name".to_string()),
+            ..hook("uptime")
+        });
+        let mapped = route.apply(&uptime_kuma(), None);
+
+        // Numbers are written out, null is left empty and several matches are joined
+        assert_eq!(mapped.request.title, "Monitor 7:");
+        assert_eq!(mapped.request.message, "prod, web");
+
+        let objects = HookRoute::new(&HookConfig { message: Some("$.monitor.tags[0]".to_string()), ..hook("raw") }).unwrap();
+        assert_eq!(objects.apply(&uptime_kuma(), None).request.message, r#"{"name":"prod","value":""}"#);
+    }
+
+    #[test]
+    fn missing_paths_fall_back() {
+        let route = route(HookConfig {
+            title: Some("$.missing".to_string()),
+            message: Some("$.also.missing".to_string()),
+            image_url: Some("$.monitor.screenshot".to_string()),
+            url: Some("$.nope".to_string()),
+            priority: Some("$.nope".to_string()),
+            ..hook("uptime")
+        });
+        let mapped = route.apply(&uptime_kuma(), None);
+
+        assert_eq!(mapped.request.title, "uptime");
+        assert_eq!(mapped.request.message, "");
+        assert_eq!(mapped.request.url, None);
+        assert_eq!(mapped.request.priority, Priority::Normal);
+        assert_eq!(mapped.image_url, None);
+    }
+
+    #[test]
+    fn maps_priorities() {
+        let priorities = HashMap::from([("0".to_string(), Priority::High), ("CRITICAL".to_string(), Priority::Urgent)]);
+        let route = route(HookConfig { priority: Some("$.level".to_string()), priorities, ..hook("levels") });
+
+        for (level, priority) in [("0", Priority::High), ("critical", Priority::Urgent), ("low", Priority::Low), ("bogus", Priority::Normal)] {
+            let payload = serde_json::json!({ "level": level });
+            assert_eq!(route.apply(&payload, None).request.priority, priority, "{}", level);
+        }
+        let numeric = serde_json::json!({ "level": 0 });
+        assert_eq!(route.apply(&numeric, None).request.priority, Priority::High);
+    }
+
+    #[test]
+    fn configured_sender_wins_over_the_token() {
+        let payload = uptime_kuma();
+        let named = route(hook("uptime"));
+        assert_eq!(named.apply(&payload, None).request.sender.as_deref(), Some("uptime"));
+        assert_eq!(named.apply(&payload, Some("Monitoring")).request.sender.as_deref(), Some("Monitoring"));
+
+        let configured = route(HookConfig { sender: Some("Uptime Kuma".to_string()), ..hook("uptime") });
+        assert_eq!(configured.apply(&payload, Some("Monitoring")).request.sender.as_deref(), Some("Uptime Kuma"));
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(HookRoute::new(&HookConfig { title: Some("$.[".to_string()), ..hook("bad") }).is_err());
+        assert!(HookRoute::new(&HookConfig { title: Some("Hello {$.name".to_string()), ..hook("bad") }).is_err());
+        assert!(Hooks::new(&[hook("twice"), hook("twice")]).is_err());
+
+        let hooks = Hooks::new(&[hook("one")]).unwrap();
+        assert!(hooks.get("one").is_some());
+        assert!(hooks.get("two").is_none());
+    }
+}
@@ -8,10 +8,142 @@ use windows::{
     Foundation::TypedEventHandler,
 };
 use std::path::Path;
+use std::time::{Duration, Instant};
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, oneshot};
 
-use crate::notifications::{NotificationRequest, NotificationData, NotificationType, BasicNotification, NotificationKind};
+use crate::notifications::{NotificationRequest, NotificationData, NotificationType, BasicNotification, NotificationKind, DefaultAction, ConfirmationNotification, CONFIRM_ALLOW, Priority, is_web_url};
+use crate::utils::config::ServerConfig;
 use super::registry::RegistryService;
 use super::clipboard::ClipboardService;
+use super::quiet_hours::{QuietDecision, QuietHours};
+use super::digest::{DigestBatch, Digests};
+use super::relay::Relay;
+
+/// How long the toast of a `replace_key` is remembered after its last update
+const REPLACE_KEY_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Activation events buffered for subscribers that fall behind
+const ACTIVATION_CAPACITY: usize = 64;
+/// Files `open-file` never launches, since opening them runs code. Their folder is opened instead.
+const EXECUTABLE_EXTENSIONS: [&str; 22] = [
+    "exe", "com", "scr", "pif", "bat", "cmd", "ps1", "psm1", "vbs", "vbe", "js", "jse",
+    "wsf", "wsh", "hta", "msi", "msp", "lnk", "url", "reg", "cpl", "jar",
+];
+
+/// True for files Windows would run rather than open in a viewer
+fn is_executable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXECUTABLE_EXTENSIONS.iter().any(|executable| executable.eq_ignore_ascii_case(extension)))
+}
+
+/// What became of a notification passed to `send_notification`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Shown,
+    /// Held back until quiet hours end
+    Queued,
+    /// Discarded because of quiet hours
+    Dropped,
+    /// Added to a digest
+    Batched,
+}
+
+/// A click on a toast whose request had an `origin`
+#[derive(Debug, Clone)]
+pub struct ActivationEvent {
+    pub origin: String,
+}
+
+/// A toast shown within the dedup window that repeats are coalesced into
+struct RecentNotification {
+    tag: String,
+    count: u32,
+    last_seen: Instant,
+}
+
+/// Where a toast goes: over an earlier one for a `replace_key` or a repeat, or as a new one
+#[derive(Debug, PartialEq, Eq)]
+struct ToastSlot {
+    replace_key: Option<String>,
+    dedup_key: Option<String>,
+    /// Tag of the toast to replace
+    replace_tag: Option<String>,
+    /// The `×N` count when this repeats a recent toast
+    repeat_count: Option<u32>,
+}
+
+/// Remembers recent toasts so repeats are coalesced and `replace_key`s update their toast
+struct ToastTracker {
+    dedup_window: Duration,
+    recent: HashMap<String, RecentNotification>,
+    /// Tag of the toast last shown for each `replace_key`
+    replaced: HashMap<String, (String, Instant)>,
+}
+
+impl ToastTracker {
+    fn new(dedup_window: Duration) -> Self {
+        Self { dedup_window, recent: HashMap::new(), replaced: HashMap::new() }
+    }
+
+    /// Finds the toast `request` replaces, counting it as a repeat when it is one
+    fn prepare(&mut self, request: &NotificationRequest, now: Instant) -> ToastSlot {
+        let replace_key = request.replace_key.as_deref()
+            .map(|key| format!("{}\0{}", request.sender.as_deref().unwrap_or(""), key));
+        if let Some(key) = replace_key {
+            let replace_tag = self.replaced.get(&key).map(|(tag, _)| tag.clone());
+            return ToastSlot { replace_key: Some(key), dedup_key: None, replace_tag, repeat_count: None };
+        }
+
+        let dedup_key = self.dedup_key(request);
+        let repeat = dedup_key.as_deref().and_then(|key| self.register_repeat(key, now));
+        ToastSlot {
+            replace_key: None,
+            dedup_key,
+            replace_tag: repeat.as_ref().map(|(tag, _)| tag.clone()),
+            repeat_count: repeat.map(|(_, count)| count),
+        }
+    }
+
+    /// Remembers the toast shown for `slot` under `tag`
+    fn record(&mut self, slot: ToastSlot, tag: String, now: Instant) {
+        if let Some(key) = slot.replace_key {
+            self.replaced.retain(|_, (_, last_seen)| now.duration_since(*last_seen) <= REPLACE_KEY_LIFETIME);
+            self.replaced.insert(key, (tag, now));
+        } else if let (Some(key), None) = (slot.dedup_key, slot.replace_tag) {
+            self.recent.insert(key, RecentNotification { tag, count: 1, last_seen: now });
+        }
+    }
+
+    /// Key identifying repeats of `request`, scoped to its sender. `None` when dedup is disabled.
+    fn dedup_key(&self, request: &NotificationRequest) -> Option<String> {
+        if self.dedup_window.is_zero() {
+            return None;
+        }
+        let sender = request.sender.as_deref().unwrap_or("");
+        let key = match &request.dedup_key {
+            Some(key) if !key.trim().is_empty() => format!("key:{}", key.trim()),
+            _ => {
+                let digest = Sha256::digest(format!("{}\0{}", request.title, request.message));
+                format!("hash:{}", hex::encode(digest))
+            }
+        };
+        Some(format!("{}\0{}", sender, key))
+    }
+
+    /// If `key` was shown within the dedup window, bumps its counter and returns the tag of
+    /// the toast to replace with the new count
+    fn register_repeat(&mut self, key: &str, now: Instant) -> Option<(String, u32)> {
+        let window = self.dedup_window;
+        self.recent.retain(|_, recent| now.duration_since(recent.last_seen) <= window);
+
+        let recent = self.recent.get_mut(key)?;
+        recent.count += 1;
+        recent.last_seen = now;
+        log::info!("Coalescing repeated notification into {} (×{})", recent.tag, recent.count);
+        Some((recent.tag.clone(), recent.count))
+    }
+}
 
 pub struct NotificationManager {
     is_registered: bool,
@@ -19,10 +151,17 @@ pub struct NotificationManager {
     notifications: Arc<Mutex<HashMap<String, NotificationData>>>,
     _com_initialized: bool,
     registry_service: RegistryService,
+    default_action: Vec<DefaultAction>,
+    toasts: ToastTracker,
+    quiet_hours: QuietHours,
+    digests: Digests,
+    base_url: Option<String>,
+    activations: broadcast::Sender<ActivationEvent>,
+    relay: Option<Arc<Relay>>,
 }
 
 impl NotificationManager {
-    pub async fn new(app_id: &str, display_name: &str) -> Result<Self> {
+    pub async fn new(app_id: &str, display_name: &str, config: &ServerConfig) -> Result<Self> {
         unsafe {
             CoInitializeEx(None, COINIT_MULTITHREADED).ok();
         }
@@ -35,6 +174,13 @@ impl NotificationManager {
             notifications: Arc::new(Mutex::new(HashMap::new())),
             _com_initialized: true,
             registry_service,
+            default_action: config.default_action.clone(),
+            toasts: ToastTracker::new(Duration::from_secs(config.dedup.window_secs)),
+            quiet_hours: QuietHours::new(&config.quiet_hours)?,
+            digests: Digests::new(&config.digest),
+            base_url: None,
+            activations: broadcast::channel(ACTIVATION_CAPACITY).0,
+            relay: None,
         };
         
         manager.ensure_registration()?;
@@ -59,15 +205,129 @@ impl NotificationManager {
         Ok(())
     }
 
-    pub async fn send_notification(&mut self, request: NotificationRequest) -> Result<()> {
+    pub async fn send_notification(&mut self, request: NotificationRequest) -> Result<Delivery> {
         if !self.is_registered {
             return Err(anyhow::anyhow!("Notification system not properly registered"));
         }
+        // Downstream servers apply their own digests and quiet hours
+        if let Some(relay) = &self.relay {
+            relay.forward(&request);
+        }
+
+        // Important notifications are never held back in a digest
+        let digest = request.digest.as_deref().map(str::trim).filter(|name| !name.is_empty());
+        if let Some(name) = digest.filter(|_| request.priority < Priority::High) {
+            log::info!("Adding notification to digest {}: {}", name, request.title);
+            if let Some(batch) = self.digests.add(name, &request) {
+                self.emit_digest(batch).await?;
+            }
+            return Ok(Delivery::Batched);
+        }
+
+        self.deliver(request).await
+    }
+
+    /// Origin the server can be reached at from this machine, used for links to its own pages
+    pub fn set_base_url(&mut self, base_url: String) {
+        self.base_url = Some(base_url);
+    }
 
+    /// Receives the clicks on toasts of requests that set an `origin`
+    pub fn subscribe_activations(&self) -> broadcast::Receiver<ActivationEvent> {
+        self.activations.subscribe()
+    }
+
+    /// Reports a click that happened elsewhere, such as on a server notifications were relayed to
+    pub fn emit_activation(&self, origin: String) {
+        let _ = self.activations.send(ActivationEvent { origin });
+    }
+
+    /// Forwards every notification passed to `send_notification` through `relay`
+    pub fn set_relay(&mut self, relay: Arc<Relay>) {
+        self.relay = Some(relay);
+    }
+
+    pub fn relay(&self) -> Option<Arc<Relay>> {
+        self.relay.clone()
+    }
+
+    /// Shows `request` unless quiet hours hold it back
+    async fn deliver(&mut self, request: NotificationRequest) -> Result<Delivery> {
+        let silent = match self.quiet_hours.decide(request.priority) {
+            QuietDecision::Deliver => false,
+            QuietDecision::Silent => true,
+            QuietDecision::Queue => {
+                log::info!("Queueing notification until quiet hours end: {}", request.title);
+                self.quiet_hours.queue(&request);
+                return Ok(Delivery::Queued);
+            }
+            QuietDecision::Drop => {
+                log::info!("Dropping notification during quiet hours: {}", request.title);
+                return Ok(Delivery::Dropped);
+            }
+        };
+
+        self.show_notification(request, silent).await?;
+        Ok(Delivery::Shown)
+    }
+
+    pub fn quiet_hours(&self) -> &QuietHours {
+        &self.quiet_hours
+    }
+
+    pub fn quiet_hours_mut(&mut self) -> &mut QuietHours {
+        &mut self.quiet_hours
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.quiet_hours.queued_count()
+    }
+
+    /// Shows everything queued during quiet hours as one digest toast, once they are over
+    pub async fn flush_quiet_queue(&mut self) -> Result<()> {
+        let batch = match self.quiet_hours.take_digest() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        log::info!("Quiet hours ended, delivering digest of {} notification(s)", batch.total());
+        self.emit_digest(batch).await
+    }
+
+    /// Emits the digests whose interval has elapsed
+    pub async fn flush_due_digests(&mut self) -> Result<()> {
+        for batch in self.digests.take_due() {
+            self.emit_digest(batch).await?;
+        }
+        Ok(())
+    }
+
+    /// A digest batch that has been emitted, for its "view all" page
+    pub fn digest_batch(&self, id: &str) -> Option<&DigestBatch> {
+        self.digests.get(id)
+    }
+
+    async fn emit_digest(&mut self, batch: DigestBatch) -> Result<()> {
+        log::info!("Sending digest {} with {} notification(s)", batch.name, batch.total());
+        let summary = batch.summary(self.digests.top_items(), self.base_url.as_deref());
+        self.digests.store(batch);
+        self.deliver(summary).await?;
+        Ok(())
+    }
+
+    async fn show_notification(&mut self, request: NotificationRequest, silent: bool) -> Result<()> {
         match request.notification_type {
             NotificationKind::Basic => {
-                let notification = BasicNotification::from(request);
-                self.send_typed_notification(&notification).await?;
+                let now = Instant::now();
+                let slot = self.toasts.prepare(&request, now);
+                let mut notification = BasicNotification::from(request);
+                notification.silent = silent;
+                if let Some(count) = slot.repeat_count {
+                    notification.repeat_count = count;
+                    // Repeats only update the counter, they do not pop up again
+                    notification.suppress_popup = true;
+                }
+                let tag = self.send_typed_notification(&notification, slot.replace_tag.clone()).await?;
+                self.toasts.record(slot, tag, now);
             }
             // Add future notification types here
         }
@@ -75,28 +335,77 @@ impl NotificationManager {
         Ok(())
     }
 
-    async fn send_typed_notification<T: NotificationType>(&mut self, notification_type: &T) -> Result<()> {
+    /// Shows the toast and returns its tag. With `replace_tag` the toast replaces the existing
+    /// one with that tag.
+    async fn send_typed_notification<T: NotificationType>(&mut self, notification_type: &T, replace_tag: Option<String>) -> Result<String> {
         let xml = notification_type.prepare_xml()?;
         let toast = notification_type.create_notification(&xml)?;
-        let notification_data = notification_type.get_callback_data();
+        let mut notification_data = notification_type.get_callback_data();
+        if notification_data.default_action.is_none() {
+            notification_data.default_action = Some(self.default_action.clone());
+        }
         
-        let tag = format!("notification_{}", uuid::Uuid::new_v4());
+        let tag = replace_tag.unwrap_or_else(|| format!("notification_{}", uuid::Uuid::new_v4()));
         toast.SetTag(&HSTRING::from(tag.clone()))?;
 
         self.notifications.lock().unwrap().insert(tag.clone(), notification_data.clone());
-        self.setup_notification_handlers(&toast, tag)?;
+        self.setup_notification_handlers(&toast, tag.clone())?;
 
         if let Some(notifier) = &self.notifier {
             notifier.Show(&toast)?;
             log::info!("Notification sent successfully");
-            Ok(())
+            Ok(tag)
         } else {
             Err(anyhow::anyhow!("Toast notifier not initialized"))
         }
     }
 
+    /// Shows an Allow / Deny toast. The receiver resolves to `true` only if Allow was clicked,
+    /// and is dropped without a value if the toast disappears some other way.
+    pub fn request_confirmation(&mut self, confirmation: &ConfirmationNotification) -> Result<oneshot::Receiver<bool>> {
+        let xml = confirmation.prepare_xml()?;
+        let toast = confirmation.create_notification(&xml)?;
+
+        let (sender, receiver) = oneshot::channel();
+        let sender = Arc::new(Mutex::new(Some(sender)));
+
+        let activated_sender = Arc::clone(&sender);
+        let _token = toast.Activated(&TypedEventHandler::<ToastNotification, IInspectable>::new(move |_: &Option<ToastNotification>, args: &Option<IInspectable>| {
+            let arguments = args.as_ref()
+                .and_then(|args| args.cast::<ToastActivatedEventArgs>().ok())
+                .and_then(|args| args.Arguments().ok())
+                .map(|arguments| arguments.to_string())
+                .unwrap_or_default();
+
+            log::info!("Confirmation toast activated with arguments: {:?}", arguments);
+            if let Some(sender) = activated_sender.lock().unwrap().take() {
+                let _ = sender.send(arguments == CONFIRM_ALLOW);
+            }
+            Ok(())
+        }))?;
+
+        let dismissed_sender = Arc::clone(&sender);
+        let _token = toast.Dismissed(&TypedEventHandler::<ToastNotification, ToastDismissedEventArgs>::new(move |_: &Option<ToastNotification>, _: &Option<ToastDismissedEventArgs>| {
+            log::info!("Confirmation toast dismissed");
+            if let Some(sender) = dismissed_sender.lock().unwrap().take() {
+                let _ = sender.send(false);
+            }
+            Ok(())
+        }))?;
+
+        match &self.notifier {
+            Some(notifier) => {
+                notifier.Show(&toast)?;
+                log::info!("Confirmation notification sent");
+                Ok(receiver)
+            }
+            None => Err(anyhow::anyhow!("Toast notifier not initialized")),
+        }
+    }
+
     fn setup_notification_handlers(&self, notification: &ToastNotification, tag: String) -> Result<()> {
         let notifications = Arc::clone(&self.notifications);
+        let activations = self.activations.clone();
 
         let tag_clone = tag.clone();
         let _token = notification.Activated(&TypedEventHandler::<ToastNotification, IInspectable>::new(move |_: &Option<ToastNotification>, _: &Option<IInspectable>| {
@@ -105,6 +414,11 @@ impl NotificationManager {
             
             if let Ok(notifications_guard) = notifications.lock() {
                 if let Some(data) = notifications_guard.get(&tag) {
+                    if let Some(origin) = &data.origin {
+                        // Nobody listening is not an error
+                        let _ = activations.send(ActivationEvent { origin: origin.clone() });
+                    }
+
                     // Handle callback command if present and not empty
                     if let Some(cmd) = &data.callback_command {
                         if !cmd.trim().is_empty() {
@@ -119,35 +433,7 @@ impl NotificationManager {
                     }
                     
                     // Execute default operations if no callback command or if it's empty
-                    // Copy message to clipboard
-                    if let Err(e) = ClipboardService::set_text(&data.message) {
-                        log::error!("Failed to copy text to clipboard: {}", e);
-                    }
-
-                    // Determine which directory to open
-                    let directory_to_open = if let Some(image_path) = &data.image_path {
-                        // If there's an image, use its directory
-                        Path::new(image_path).parent().map(|p| p.to_path_buf())
-                    } else if let Some(file_paths) = &data.file_paths {
-                        if !file_paths.is_empty() {
-                            // If there are files but no image, use the directory of the first file
-                            Path::new(&file_paths[0]).parent().map(|p| p.to_path_buf())
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-
-                    // Open the directory if one was found
-                    if let Some(dir) = directory_to_open {
-                        log::info!("Opening directory: {}", dir.display());
-                        if let Err(e) = std::process::Command::new("explorer")
-                            .arg(dir.to_str().unwrap_or(""))
-                            .spawn() {
-                            log::error!("Failed to open directory: {}", e);
-                        }
-                    }
+                    Self::run_default_actions(data);
                 }
             }
             Ok(())
@@ -183,6 +469,94 @@ impl NotificationManager {
 
         Ok(())
     }
+
+    fn run_default_actions(data: &NotificationData) {
+        let actions = data.default_action.as_deref().unwrap_or(&[]);
+        if actions.is_empty() {
+            log::info!("No default action configured for this notification");
+            return;
+        }
+
+        // The image takes precedence over attachments, matching how the toast displays them
+        let primary_path = data.image_path.clone().or_else(|| {
+            data.file_paths.as_ref().and_then(|paths| paths.first().cloned())
+        });
+
+        for action in actions {
+            match action {
+                DefaultAction::CopyText => {
+                    if let Err(e) = ClipboardService::set_text(&data.message) {
+                        log::error!("Failed to copy text to clipboard: {}", e);
+                    }
+                }
+                DefaultAction::CopyImage => {
+                    match &data.image_path {
+                        Some(image_path) => {
+                            if let Err(e) = ClipboardService::set_image(image_path) {
+                                log::error!("Failed to copy image to clipboard: {}", e);
+                            }
+                        }
+                        None => log::warn!("copy-image requested but the notification has no image"),
+                    }
+                }
+                DefaultAction::CopyFiles => {
+                    match &data.file_paths {
+                        Some(file_paths) if !file_paths.is_empty() => {
+                            if let Err(e) = ClipboardService::set_files(file_paths) {
+                                log::error!("Failed to copy files to clipboard: {}", e);
+                            }
+                        }
+                        _ => log::warn!("copy-files requested but the notification has no files"),
+                    }
+                }
+                DefaultAction::OpenFolder => {
+                    let directory_to_open = primary_path.as_ref()
+                        .and_then(|path| Path::new(path).parent().map(|p| p.to_path_buf()));
+
+                    if let Some(dir) = directory_to_open {
+                        log::info!("Opening directory: {}", dir.display());
+                        if let Err(e) = std::process::Command::new("explorer")
+                            .arg(dir.to_str().unwrap_or(""))
+                            .spawn() {
+                            log::error!("Failed to open directory: {}", e);
+                        }
+                    }
+                }
+                DefaultAction::OpenFile => {
+                    if let Some(path) = &primary_path {
+                        // Opening an executable would run it, so only show it in its folder
+                        let target = match Path::new(path).parent() {
+                            Some(dir) if is_executable(Path::new(path)) => {
+                                log::warn!("Refusing to run {}, opening its folder instead", path);
+                                dir.to_string_lossy().into_owned()
+                            }
+                            _ => path.clone(),
+                        };
+                        log::info!("Opening file: {}", target);
+                        if let Err(e) = std::process::Command::new("explorer")
+                            .arg(&target)
+                            .spawn() {
+                            log::error!("Failed to open file: {}", e);
+                        }
+                    }
+                }
+                DefaultAction::OpenUrl => {
+                    match &data.url {
+                        Some(url) if is_web_url(url) => {
+                            log::info!("Opening URL: {}", url);
+                            if let Err(e) = std::process::Command::new("explorer")
+                                .arg(url)
+                                .spawn() {
+                                log::error!("Failed to open URL: {}", e);
+                            }
+                        }
+                        Some(url) => log::warn!("Refusing to open non-http URL: {}", url),
+                        None => log::warn!("open-url requested but the notification has no url"),
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl Drop for NotificationManager {
@@ -194,3 +568,131 @@ impl Drop for NotificationManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn request(sender: &str, title: &str) -> NotificationRequest {
+        let mut request = NotificationRequest::new(title, "message");
+        request.sender = Some(sender.to_string());
+        request
+    }
+
+    /// Prepares and records `request` like `show_notification`, returning the slot and tag
+    fn show(tracker: &mut ToastTracker, request: &NotificationRequest, now: Instant) -> (Option<String>, Option<u32>, String) {
+        let slot = tracker.prepare(request, now);
+        let (replace_tag, repeat_count) = (slot.replace_tag.clone(), slot.repeat_count);
+        let tag = replace_tag.clone().unwrap_or_else(|| format!("notification_{}", uuid::Uuid::new_v4()));
+        tracker.record(slot, tag.clone(), now);
+        (replace_tag, repeat_count, tag)
+    }
+
+    #[test]
+    fn repeats_within_the_window_are_counted() {
+        let mut tracker = ToastTracker::new(WINDOW);
+        let start = Instant::now();
+        let disk = request("nas", "Disk full");
+
+        let (replaced, count, tag) = show(&mut tracker, &disk, start);
+        assert_eq!((replaced, count), (None, None));
+        for expected in 2..=5 {
+            let (replaced, count, _) = show(&mut tracker, &disk, start + Duration::from_secs(10 * expected as u64));
+            assert_eq!(replaced.as_deref(), Some(tag.as_str()));
+            assert_eq!(count, Some(expected));
+        }
+    }
+
+    #[test]
+    fn repeats_after_the_window_show_a_new_toast() {
+        let mut tracker = ToastTracker::new(WINDOW);
+        let start = Instant::now();
+        let disk = request("nas", "Disk full");
+
+        let (_, _, first) = show(&mut tracker, &disk, start);
+        // Each repeat extends the window
+        assert_eq!(show(&mut tracker, &disk, start + Duration::from_secs(50)).1, Some(2));
+        assert_eq!(show(&mut tracker, &disk, start + Duration::from_secs(100)).1, Some(3));
+
+        let (replaced, count, second) = show(&mut tracker, &disk, start + Duration::from_secs(161));
+        assert_eq!((replaced, count), (None, None));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn dedup_keys_and_content_identify_repeats() {
+        let mut tracker = ToastTracker::new(WINDOW);
+        let now = Instant::now();
+        show(&mut tracker, &request("nas", "Disk full"), now);
+        assert_eq!(show(&mut tracker, &request("nas", "Disk almost full"), now).1, None);
+
+        let mut keyed = request("nas", "Backup 1 failed");
+        keyed.dedup_key = Some("backup".to_string());
+        show(&mut tracker, &keyed, now);
+        keyed.title = "Backup 2 failed".to_string();
+        assert_eq!(show(&mut tracker, &keyed, now).1, Some(2));
+    }
+
+    #[test]
+    fn keys_are_scoped_per_sender() {
+        let mut tracker = ToastTracker::new(WINDOW);
+        let now = Instant::now();
+        show(&mut tracker, &request("nas", "Disk full"), now);
+        assert_eq!(show(&mut tracker, &request("router", "Disk full"), now).1, None);
+        assert_eq!(show(&mut tracker, &request("nas", "Disk full"), now).1, Some(2));
+
+        let mut firing = request("alertmanager", "Firing");
+        firing.replace_key = Some("group".to_string());
+        let (_, _, tag) = show(&mut tracker, &firing, now);
+        let mut other = request("grafana", "Firing");
+        other.replace_key = Some("group".to_string());
+        assert_eq!(show(&mut tracker, &other, now).0, None);
+
+        let mut resolved = request("alertmanager", "Resolved");
+        resolved.replace_key = Some("group".to_string());
+        assert_eq!(show(&mut tracker, &resolved, now).0, Some(tag));
+    }
+
+    #[test]
+    fn replace_key_takes_precedence_over_dedup() {
+        let mut tracker = ToastTracker::new(WINDOW);
+        let now = Instant::now();
+        let mut firing = request("alertmanager", "Firing");
+        firing.replace_key = Some("group".to_string());
+        firing.dedup_key = Some("alert".to_string());
+
+        let (replaced, _, tag) = show(&mut tracker, &firing, now);
+        assert_eq!(replaced, None);
+        let slot = tracker.prepare(&firing, now);
+        assert_eq!(slot.replace_tag, Some(tag));
+        assert_eq!(slot.dedup_key, None);
+        // Replacing is not a repeat, so the toast pops up again without a count
+        assert_eq!(slot.repeat_count, None);
+        assert!(tracker.recent.is_empty());
+    }
+
+    #[test]
+    fn dedup_is_disabled_without_a_window() {
+        let mut tracker = ToastTracker::new(Duration::ZERO);
+        let now = Instant::now();
+        show(&mut tracker, &request("nas", "Disk full"), now);
+        assert_eq!(tracker.prepare(&request("nas", "Disk full"), now), ToastSlot {
+            replace_key: None,
+            dedup_key: None,
+            replace_tag: None,
+            repeat_count: None,
+        });
+    }
+
+    #[test]
+    fn recognizes_executables() {
+        for path in ["setup.exe", "run.BAT", r"C:\tmp\script.ps1", "shortcut.lnk", "page.hta"] {
+            assert!(is_executable(Path::new(path)), "{}", path);
+        }
+        for path in ["photo.png", "report.pdf", "notes.txt", "archive.exe.zip", "exe"] {
+            assert!(!is_executable(Path::new(path)), "{}", path);
+        }
+    }
+}
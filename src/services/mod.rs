@@ -1,5 +1,13 @@
 mod registry;
 mod clipboard;
 mod manager;
+pub mod quiet_hours;
+pub mod digest;
+pub mod hooks;
+pub mod mqtt;
+pub mod relay;
+pub mod smtp;
+pub mod syslog;
 
-pub use manager::NotificationManager;
+pub use manager::{NotificationManager, Delivery, ActivationEvent};
+pub use clipboard::ClipboardService;
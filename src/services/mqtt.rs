@@ -0,0 +1,495 @@
+use anyhow::{anyhow, Result};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::notifications::{DefaultAction, NotificationRequest, Priority};
+use crate::services::hooks::Hooks;
+use crate::services::{ActivationEvent, NotificationManager};
+use crate::utils::config::MqttConfig;
+
+/// Prefix of the `origin` of notifications received over MQTT
+const ORIGIN_PREFIX: &str = "mqtt:";
+/// Notifications remembered for reporting their clicks
+const TRACKED_NOTIFICATIONS: usize = 1000;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_CAPACITY: usize = 32;
+
+/// How payloads of a subscription are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// JSON objects as JSON, anything else as text
+    #[default]
+    Auto,
+    Json,
+    Text,
+}
+
+/// A topic filter as written in `[[mqtt.subscriptions]]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttSubscription {
+    /// Topic filter, may contain `+` and `#` wildcards
+    pub topic: String,
+    /// 0, 1 or 2
+    #[serde(default)]
+    pub qos: u8,
+    #[serde(default)]
+    pub format: PayloadFormat,
+    /// Title for messages without one, instead of the topic
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Priority for messages without one
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// Map JSON payloads with this `[[hooks]]` route instead of reading their fields
+    #[serde(default)]
+    pub hook: Option<String>,
+}
+
+impl MqttSubscription {
+    fn qos(&self) -> QoS {
+        match self.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        }
+    }
+}
+
+/// The fields read from JSON payloads, all optional
+#[derive(Debug, Default, Deserialize)]
+struct MqttMessage {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    dedup_key: Option<String>,
+    #[serde(default)]
+    replace_key: Option<String>,
+    /// Echoed in the click event so the publisher can tell which message was clicked
+    #[serde(default)]
+    id: Option<Value>,
+}
+
+/// Where a shown notification came from, for its click event
+struct Tracked {
+    topic: String,
+    title: String,
+    id: Option<Value>,
+}
+
+/// Whether `topic` matches the MQTT topic filter `filter`
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) if filter_level == topic_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Subscribes to the configured topics and shows their messages, publishing clicks to the reply topic
+pub struct MqttInput {
+    receiver: MqttReceiver,
+    manager: Arc<Mutex<NotificationManager>>,
+}
+
+impl MqttInput {
+    pub fn new(config: &MqttConfig, manager: Arc<Mutex<NotificationManager>>, hooks: Arc<Hooks>) -> Result<Self> {
+        Ok(Self { receiver: MqttReceiver::new(config, hooks)?, manager })
+    }
+
+    fn options(config: &MqttConfig, host: &str) -> Result<MqttOptions> {
+        let mut options = MqttOptions::new(&config.client_id, host, config.port);
+        options.set_keep_alive(Duration::from_secs(config.keep_alive_secs.max(5)));
+        if let Some(username) = &config.username {
+            let password = match (&config.password, &config.password_env) {
+                (_, Some(env)) => std::env::var(env)
+                    .map_err(|_| anyhow!("Environment variable {} for the MQTT password is not set", env))?,
+                (Some(password), None) => password.clone(),
+                (None, None) => String::new(),
+            };
+            options.set_credentials(username, password);
+        }
+        Ok(options)
+    }
+
+    /// Connects in the background, reconnecting after errors
+    pub fn start(self) -> Result<()> {
+        let config = &self.receiver.config;
+        let host = match &config.host {
+            Some(host) => host.clone(),
+            None => return Ok(()),
+        };
+        let (client, eventloop) = AsyncClient::new(Self::options(config, &host)?, REQUEST_CAPACITY);
+        let activations = self.manager.lock().unwrap().subscribe_activations();
+        log::info!("Connecting to MQTT broker {}:{}", host, config.port);
+
+        let receiver = Arc::new(Mutex::new(self.receiver));
+        actix_web::rt::spawn(Self::publish_activations(Arc::clone(&receiver), client.clone(), activations));
+        actix_web::rt::spawn(Self::run(receiver, self.manager, client, eventloop));
+        Ok(())
+    }
+
+    async fn run(receiver: Arc<Mutex<MqttReceiver>>, manager: Arc<Mutex<NotificationManager>>, client: AsyncClient, mut eventloop: EventLoop) {
+        let subscriptions = receiver.lock().unwrap().config.subscriptions.clone();
+        loop {
+            match eventloop.poll().await {
+                // Subscriptions do not survive a reconnect with a clean session. The requests are
+                // queued without waiting, as they are only sent while the event loop is polled.
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    log::info!("Connected to MQTT broker");
+                    for subscription in &subscriptions {
+                        if let Err(e) = client.try_subscribe(subscription.topic.as_str(), subscription.qos()) {
+                            log::error!("Failed to subscribe to MQTT topic {}: {}", subscription.topic, e);
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let request = receiver.lock().unwrap().handle_publish(&publish.topic, &publish.payload);
+                    if let Some(request) = request {
+                        let mut manager = manager.lock().unwrap();
+                        if let Err(e) = manager.send_notification(request).await {
+                            log::error!("Failed to send MQTT notification: {}", e);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("MQTT connection error, reconnecting in {:?}: {}", RECONNECT_DELAY, e);
+                    actix_web::rt::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    /// Publishes a JSON event to the reply topic for each click on a notification from MQTT
+    async fn publish_activations(receiver: Arc<Mutex<MqttReceiver>>, client: AsyncClient, mut activations: broadcast::Receiver<ActivationEvent>) {
+        loop {
+            let activation = match activations.recv().await {
+                Ok(activation) => activation,
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("Missed {} click events for MQTT", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let (reply_topic, event) = match receiver.lock().unwrap().click_event(&activation.origin) {
+                Some(click) => click,
+                None => continue,
+            };
+
+            if let Err(e) = client.publish(reply_topic.as_str(), QoS::AtLeastOnce, false, event.to_string()).await {
+                log::error!("Failed to publish click event to {}: {}", reply_topic, e);
+            }
+        }
+    }
+}
+
+/// Maps received messages to notifications and remembers them for their click events
+struct MqttReceiver {
+    config: MqttConfig,
+    hooks: Arc<Hooks>,
+    tracked: HashMap<String, Tracked>,
+    tracked_order: VecDeque<String>,
+}
+
+impl MqttReceiver {
+    fn new(config: &MqttConfig, hooks: Arc<Hooks>) -> Result<Self> {
+        for subscription in &config.subscriptions {
+            if subscription.qos > 2 {
+                return Err(anyhow!("Invalid QoS {} for MQTT topic {}", subscription.qos, subscription.topic));
+            }
+            if let Some(hook) = &subscription.hook {
+                hooks.get(hook).ok_or_else(|| anyhow!("MQTT topic {} uses unknown hook {}", subscription.topic, hook))?;
+            }
+        }
+        Ok(Self {
+            config: config.clone(),
+            hooks,
+            tracked: HashMap::new(),
+            tracked_order: VecDeque::new(),
+        })
+    }
+
+    /// Turns a received message into a notification, remembering it for its click event
+    fn handle_publish(&mut self, topic: &str, payload: &[u8]) -> Option<NotificationRequest> {
+        let subscription = match self.config.subscriptions.iter().find(|subscription| topic_matches(&subscription.topic, topic)) {
+            Some(subscription) => subscription,
+            None => {
+                log::debug!("Ignoring MQTT message on unsubscribed topic {}", topic);
+                return None;
+            }
+        };
+
+        let text = String::from_utf8_lossy(payload).trim().to_string();
+        let json = match subscription.format {
+            PayloadFormat::Text => None,
+            PayloadFormat::Auto => serde_json::from_str::<Value>(&text).ok().filter(Value::is_object),
+            PayloadFormat::Json => match serde_json::from_str::<Value>(&text) {
+                Ok(json) => Some(json),
+                Err(e) => {
+                    log::warn!("Ignoring MQTT message on {} that is not JSON: {}", topic, e);
+                    return None;
+                }
+            },
+        };
+
+        let default_title = subscription.title.clone().unwrap_or_else(|| topic.to_string());
+        let (mut request, id) = match (json, &subscription.hook) {
+            (Some(json), Some(hook)) => {
+                let route = self.hooks.get(hook)?;
+                (route.apply(&json, None).request, json.get("id").cloned())
+            }
+            (Some(json), None) => {
+                let message: MqttMessage = serde_json::from_value(json).unwrap_or_default();
+                let title = message.title.filter(|title| !title.trim().is_empty()).unwrap_or(default_title);
+                let body = message.message.unwrap_or_else(|| text.clone());
+                let mut request = NotificationRequest::new(&title, &body);
+                request.priority = message.priority.or(subscription.priority).unwrap_or_default();
+                if let Some(url) = message.url {
+                    request.url = Some(url);
+                    request.default_action = Some(vec![DefaultAction::OpenUrl]);
+                }
+                request.dedup_key = message.dedup_key;
+                request.replace_key = message.replace_key;
+                (request, message.id)
+            }
+            (None, _) => {
+                let mut request = NotificationRequest::new(&default_title, &text);
+                request.priority = subscription.priority.unwrap_or_default();
+                (request, None)
+            }
+        };
+        request.sender = Some(topic.to_string());
+        request.digest = subscription.digest.clone();
+
+        if self.config.reply_topic.is_some() {
+            let key = uuid::Uuid::new_v4().simple().to_string();
+            request.origin = Some(format!("{}{}", ORIGIN_PREFIX, key));
+            self.track(key, Tracked { topic: topic.to_string(), title: request.title.clone(), id });
+        }
+        log::info!("MQTT message on {}: {}", topic, request.title);
+        Some(request)
+    }
+
+    fn track(&mut self, key: String, tracked: Tracked) {
+        self.tracked.insert(key.clone(), tracked);
+        self.tracked_order.push_back(key);
+        while self.tracked_order.len() > TRACKED_NOTIFICATIONS {
+            if let Some(oldest) = self.tracked_order.pop_front() {
+                self.tracked.remove(&oldest);
+            }
+        }
+    }
+
+    /// The reply topic and JSON event for a click on the notification with `origin`, if it came from MQTT
+    fn click_event(&self, origin: &str) -> Option<(String, Value)> {
+        let key = origin.strip_prefix(ORIGIN_PREFIX)?;
+        let (reply_topic, tracked) = match (&self.config.reply_topic, self.tracked.get(key)) {
+            (Some(reply_topic), Some(tracked)) => (reply_topic.clone(), tracked),
+            _ => return None,
+        };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let event = json!({
+            "event": "clicked",
+            "topic": tracked.topic,
+            "title": tracked.title,
+            "id": tracked.id,
+            "timestamp": timestamp,
+        });
+        Some((reply_topic, event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::hooks::HookConfig;
+
+    fn subscription(topic: &str) -> MqttSubscription {
+        MqttSubscription {
+            topic: topic.to_string(),
+            qos: 0,
+            format: PayloadFormat::Auto,
+            title: None,
+            priority: None,
+            digest: None,
+            hook: None,
+        }
+    }
+
+    fn receiver(subscriptions: Vec<MqttSubscription>, reply_topic: Option<&str>) -> MqttReceiver {
+        let config = MqttConfig {
+            reply_topic: reply_topic.map(str::to_string),
+            subscriptions,
+            ..MqttConfig::default()
+        };
+        MqttReceiver::new(&config, Arc::new(Hooks::new(&[]).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn matches_topic_filters() {
+        assert!(topic_matches("home/door", "home/door"));
+        assert!(topic_matches("home/+/state", "home/door/state"));
+        assert!(topic_matches("home/#", "home/door/state"));
+        assert!(topic_matches("home/#", "home"));
+        assert!(topic_matches("#", "anything/at/all"));
+
+        assert!(!topic_matches("home/door", "home/door/state"));
+        assert!(!topic_matches("home/+/state", "home/door"));
+        assert!(!topic_matches("home/+", "home/door/state"));
+        assert!(!topic_matches("home/door/state", "home/door"));
+    }
+
+    #[test]
+    fn maps_json_payloads() {
+        let mut receiver = receiver(vec![subscription("alerts/#")], None);
+        let payload = br#"{"title": "Backup failed", "message": "Disk full", "priority": "high",
+            "url": "https://nas.lan", "dedup_key": "backup", "replace_key": "nightly"}"#;
+        let request = receiver.handle_publish("alerts/nas", payload).unwrap();
+
+        assert_eq!(request.title, "Backup failed");
+        assert_eq!(request.message, "Disk full");
+        assert_eq!(request.priority, Priority::High);
+        assert_eq!(request.url.as_deref(), Some("https://nas.lan"));
+        assert!(matches!(request.default_action.as_deref(), Some([DefaultAction::OpenUrl])));
+        assert_eq!(request.dedup_key.as_deref(), Some("backup"));
+        assert_eq!(request.replace_key.as_deref(), Some("nightly"));
+        assert_eq!(request.sender.as_deref(), Some("alerts/nas"));
+        assert_eq!(request.origin, None);
+    }
+
+    #[test]
+    fn falls_back_to_the_subscription_settings() {
+        let mut receiver = receiver(vec![MqttSubscription {
+            title: Some("Doorbell".to_string()),
+            priority: Some(Priority::Urgent),
+            digest: Some("home".to_string()),
+            ..subscription("home/+/ring")
+        }], None);
+
+        // JSON without a title or message keeps the payload as the message
+        let request = receiver.handle_publish("home/front/ring", br#"{"title": " ", "battery": 80}"#).unwrap();
+        assert_eq!(request.title, "Doorbell");
+        assert_eq!(request.message, r#"{"title": " ", "battery": 80}"#);
+        assert_eq!(request.priority, Priority::Urgent);
+        assert_eq!(request.digest.as_deref(), Some("home"));
+
+        let request = receiver.handle_publish("home/back/ring", b"  Someone is at the back door\n").unwrap();
+        assert_eq!(request.title, "Doorbell");
+        assert_eq!(request.message, "Someone is at the back door");
+        assert_eq!(request.priority, Priority::Urgent);
+    }
+
+    #[test]
+    fn reads_payloads_in_the_configured_format() {
+        let mut receiver = receiver(vec![
+            MqttSubscription { format: PayloadFormat::Text, ..subscription("text") },
+            MqttSubscription { format: PayloadFormat::Json, ..subscription("json") },
+            subscription("auto"),
+        ], None);
+
+        let request = receiver.handle_publish("text", br#"{"title": "Ignored"}"#).unwrap();
+        assert_eq!(request.title, "text");
+        assert_eq!(request.message, r#"{"title": "Ignored"}"#);
+
+        assert!(receiver.handle_publish("json", b"not json").is_none());
+        assert_eq!(receiver.handle_publish("json", br#"{"title": "Read"}"#).unwrap().title, "Read");
+
+        // Only objects are read as JSON in auto mode
+        assert_eq!(receiver.handle_publish("auto", b"42").unwrap().message, "42");
+        assert!(receiver.handle_publish("unsubscribed", b"hello").is_none());
+    }
+
+    #[test]
+    fn maps_payloads_with_a_hook() {
+        let hooks = Hooks::new(&[HookConfig {
+            name: "sensor".to_string(),
+            title: Some("{$.device} is {$.state}".to_string()),
+            message: None,
+            image_url: None,
+            priority: None,
+            url: None,
+            priorities: HashMap::new(),
+            sender: None,
+        }]).unwrap();
+        let config = MqttConfig {
+            reply_topic: Some("notifications/clicked".to_string()),
+            subscriptions: vec![MqttSubscription { hook: Some("sensor".to_string()), ..subscription("sensors/+") }],
+            ..MqttConfig::default()
+        };
+        let mut receiver = MqttReceiver::new(&config, Arc::new(hooks)).unwrap();
+
+        let request = receiver.handle_publish("sensors/garage", br#"{"device": "Garage", "state": "open", "id": 7}"#).unwrap();
+        assert_eq!(request.title, "Garage is open");
+        assert_eq!(request.sender.as_deref(), Some("sensors/garage"));
+
+        let (_, event) = receiver.click_event(request.origin.as_deref().unwrap()).unwrap();
+        assert_eq!(event["id"], 7);
+    }
+
+    #[test]
+    fn rejects_invalid_subscriptions() {
+        let hooks = Arc::new(Hooks::new(&[]).unwrap());
+        let invalid_qos = MqttConfig { subscriptions: vec![MqttSubscription { qos: 3, ..subscription("a") }], ..MqttConfig::default() };
+        assert!(MqttReceiver::new(&invalid_qos, Arc::clone(&hooks)).is_err());
+
+        let unknown_hook = MqttConfig { subscriptions: vec![MqttSubscription { hook: Some("missing".to_string()), ..subscription("a") }], ..MqttConfig::default() };
+        assert!(MqttReceiver::new(&unknown_hook, hooks).is_err());
+    }
+
+    #[test]
+    fn reports_clicks_to_the_reply_topic() {
+        let mut receiver = receiver(vec![subscription("alerts/#")], Some("notifications/clicked"));
+        let request = receiver.handle_publish("alerts/nas", br#"{"title": "Backup failed", "id": "job-12"}"#).unwrap();
+        let origin = request.origin.unwrap();
+        assert!(origin.starts_with(ORIGIN_PREFIX));
+
+        let (reply_topic, event) = receiver.click_event(&origin).unwrap();
+        assert_eq!(reply_topic, "notifications/clicked");
+        assert_eq!(event["event"], "clicked");
+        assert_eq!(event["topic"], "alerts/nas");
+        assert_eq!(event["title"], "Backup failed");
+        assert_eq!(event["id"], "job-12");
+
+        assert!(receiver.click_event("web:anything").is_none());
+        assert!(receiver.click_event(&format!("{}unknown", ORIGIN_PREFIX)).is_none());
+    }
+
+    #[test]
+    fn reports_nothing_without_a_reply_topic() {
+        let mut receiver = receiver(vec![subscription("alerts/#")], None);
+        assert_eq!(receiver.handle_publish("alerts/nas", b"hello").unwrap().origin, None);
+        assert!(receiver.tracked.is_empty());
+    }
+
+    #[test]
+    fn forgets_the_oldest_tracked_notifications() {
+        let mut receiver = receiver(vec![subscription("alerts/#")], Some("notifications/clicked"));
+        let first = receiver.handle_publish("alerts/first", b"hello").unwrap().origin.unwrap();
+        for _ in 0..TRACKED_NOTIFICATIONS {
+            receiver.handle_publish("alerts/more", b"hello").unwrap();
+        }
+
+        assert_eq!(receiver.tracked.len(), TRACKED_NOTIFICATIONS);
+        assert!(receiver.click_event(&first).is_none());
+    }
+}
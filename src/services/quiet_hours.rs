@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, Duration as ChronoDuration, Local, NaiveDateTime, NaiveTime, Weekday};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::notifications::{NotificationRequest, Priority};
+use crate::services::NotificationManager;
+use crate::services::digest::{DigestBatch, DigestItem};
+use crate::utils::config::QuietHoursConfig;
+
+/// How often queued notifications are checked for delivery
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Source of the current local time, replaceable so the rules can be evaluated at any moment
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// What happens to non-urgent notifications while quiet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietMode {
+    /// Hold them back and show a digest when the quiet period ends
+    Queue,
+    Drop,
+    /// Show them without sound
+    Silent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietDecision {
+    Deliver,
+    Silent,
+    Queue,
+    Drop,
+}
+
+/// A window as written in `[[quiet_hours.windows]]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuietWindowConfig {
+    /// Days the window starts on, such as `["mon", "tue"]`. Empty means every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Local time as `HH:MM`. A window ending before it starts runs past midnight.
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone)]
+struct QuietWindow {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietWindow {
+    fn from_config(config: &QuietWindowConfig) -> Result<Self> {
+        let parse_time = |value: &str| NaiveTime::parse_from_str(value.trim(), "%H:%M")
+            .with_context(|| format!("Invalid quiet hours time {}, expected HH:MM", value));
+        let days = config.days.iter()
+            .map(|day| day.trim().parse::<Weekday>().map_err(|_| anyhow!("Invalid quiet hours day: {}", day)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            days,
+            start: parse_time(&config.start)?,
+            end: parse_time(&config.end)?,
+        })
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+        let today = now.weekday();
+        if self.start < self.end {
+            self.starts_on(today) && time >= self.start && time < self.end
+        } else if self.start > self.end {
+            // Past midnight the window belongs to the day it started on
+            (self.starts_on(today) && time >= self.start) || (self.starts_on(today.pred()) && time < self.end)
+        } else {
+            self.starts_on(today)
+        }
+    }
+}
+
+/// Scheduled quiet hours plus the manual Do Not Disturb switch, and the notifications
+/// queued until they are over
+pub struct QuietHours {
+    windows: Vec<QuietWindow>,
+    mode: QuietMode,
+    max_queued: usize,
+    queue: Vec<DigestItem>,
+    /// Queued notifications that did not fit within `max_queued`
+    overflow: usize,
+    /// `Some` while Do Not Disturb is switched on, with the time it switches itself off
+    dnd: Option<Option<NaiveDateTime>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl QuietHours {
+    pub fn new(config: &QuietHoursConfig) -> Result<Self> {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: &QuietHoursConfig, clock: Arc<dyn Clock>) -> Result<Self> {
+        let windows = config.windows.iter()
+            .enumerate()
+            .map(|(index, window)| QuietWindow::from_config(window)
+                .with_context(|| format!("Invalid quiet hours window #{}", index + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            windows,
+            mode: config.mode,
+            max_queued: config.max_queued,
+            queue: Vec::new(),
+            overflow: 0,
+            dnd: None,
+            clock,
+        })
+    }
+
+    /// Switches Do Not Disturb on, optionally for a limited time, or off. Fails, leaving the
+    /// state unchanged, when the end of `duration` is out of range.
+    pub fn set_dnd(&mut self, enabled: bool, duration: Option<Duration>) -> Result<()> {
+        if !enabled {
+            self.dnd = None;
+            return Ok(());
+        }
+        let until = duration
+            .map(|duration| ChronoDuration::from_std(duration).ok()
+                .and_then(|duration| self.clock.now().checked_add_signed(duration))
+                .ok_or_else(|| anyhow!("Do Not Disturb duration out of range")))
+            .transpose()?;
+        self.dnd = Some(until);
+        Ok(())
+    }
+
+    pub fn is_dnd_active(&self) -> bool {
+        match self.dnd {
+            Some(Some(until)) => self.clock.now() < until,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// When a timed Do Not Disturb ends
+    pub fn dnd_until(&self) -> Option<NaiveDateTime> {
+        self.dnd.flatten().filter(|_| self.is_dnd_active())
+    }
+
+    pub fn is_scheduled_quiet(&self) -> bool {
+        let now = self.clock.now();
+        self.windows.iter().any(|window| window.contains(now))
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.is_dnd_active() || self.is_scheduled_quiet()
+    }
+
+    pub fn decide(&self, priority: Priority) -> QuietDecision {
+        if priority == Priority::Urgent || !self.is_quiet() {
+            return QuietDecision::Deliver;
+        }
+        match self.mode {
+            QuietMode::Queue => QuietDecision::Queue,
+            QuietMode::Drop => QuietDecision::Drop,
+            QuietMode::Silent => QuietDecision::Silent,
+        }
+    }
+
+    /// Holds `request` back for the digest shown when quiet hours end
+    pub fn queue(&mut self, request: &NotificationRequest) {
+        if self.queue.len() >= self.max_queued {
+            self.overflow += 1;
+            return;
+        }
+        let mut item = DigestItem::from(request);
+        item.received = self.clock.now();
+        self.queue.push(item);
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.queue.len() + self.overflow
+    }
+
+    /// Everything queued as one batch, once quiet hours are over
+    pub fn take_digest(&mut self) -> Option<DigestBatch> {
+        if self.queued_count() == 0 || self.is_quiet() {
+            return None;
+        }
+        let mut batch = DigestBatch::new("Quiet hours");
+        batch.items = std::mem::take(&mut self.queue);
+        batch.overflow = std::mem::take(&mut self.overflow);
+        Some(batch)
+    }
+}
+
+/// Periodically delivers the notifications queued during quiet hours once they are over
+pub fn start_flush_task(manager: Arc<Mutex<NotificationManager>>) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut manager = manager.lock().unwrap();
+            if let Err(e) = manager.flush_quiet_queue().await {
+                log::error!("Failed to deliver notifications queued during quiet hours: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// A clock that stays where the test puts it
+    struct TestClock(Mutex<NaiveDateTime>);
+
+    impl TestClock {
+        fn set(&self, now: NaiveDateTime) {
+            *self.0.lock().unwrap() = now;
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> NaiveDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// 2024-05-03 is a Friday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn quiet_hours(mode: QuietMode, days: &[&str], start: &str, end: &str) -> (QuietHours, Arc<TestClock>) {
+        let config = QuietHoursConfig {
+            mode,
+            max_queued: 3,
+            windows: vec![QuietWindowConfig {
+                days: days.iter().map(|day| day.to_string()).collect(),
+                start: start.to_string(),
+                end: end.to_string(),
+            }],
+        };
+        let clock = Arc::new(TestClock(Mutex::new(at(3, 12, 0))));
+        let quiet_hours = QuietHours::with_clock(&config, clock.clone()).unwrap();
+        (quiet_hours, clock)
+    }
+
+    #[test]
+    fn window_crossing_midnight_belongs_to_its_start_day() {
+        let (quiet_hours, clock) = quiet_hours(QuietMode::Queue, &["fri"], "22:00", "07:00");
+
+        let expected = [
+            (at(3, 21, 59), false),
+            (at(3, 22, 0), true),
+            (at(3, 23, 59), true),
+            (at(4, 0, 0), true),
+            (at(4, 6, 59), true),
+            (at(4, 7, 0), false),
+            // Saturday night is not covered, and neither is the early morning before Friday
+            (at(4, 23, 0), false),
+            (at(3, 6, 0), false),
+        ];
+        for (now, quiet) in expected {
+            clock.set(now);
+            assert_eq!(quiet_hours.is_scheduled_quiet(), quiet, "at {}", now);
+        }
+    }
+
+    #[test]
+    fn urgent_notifications_break_through() {
+        let (quiet_hours, clock) = quiet_hours(QuietMode::Drop, &[], "22:00", "07:00");
+        clock.set(at(3, 23, 0));
+
+        assert_eq!(quiet_hours.decide(Priority::Urgent), QuietDecision::Deliver);
+        assert_eq!(quiet_hours.decide(Priority::High), QuietDecision::Drop);
+    }
+
+    #[test]
+    fn mode_decides_outside_urgent() {
+        for (mode, decision) in [
+            (QuietMode::Queue, QuietDecision::Queue),
+            (QuietMode::Drop, QuietDecision::Drop),
+            (QuietMode::Silent, QuietDecision::Silent),
+        ] {
+            let (quiet_hours, clock) = quiet_hours(mode, &[], "22:00", "07:00");
+            clock.set(at(3, 23, 0));
+            assert_eq!(quiet_hours.decide(Priority::Normal), decision);
+
+            clock.set(at(3, 12, 0));
+            assert_eq!(quiet_hours.decide(Priority::Normal), QuietDecision::Deliver);
+        }
+    }
+
+    #[test]
+    fn dnd_applies_outside_the_windows() {
+        let (mut quiet_hours, clock) = quiet_hours(QuietMode::Silent, &[], "22:00", "07:00");
+        quiet_hours.set_dnd(true, Some(Duration::from_secs(3600))).unwrap();
+        assert_eq!(quiet_hours.decide(Priority::Low), QuietDecision::Silent);
+
+        clock.set(at(3, 13, 0));
+        assert_eq!(quiet_hours.decide(Priority::Low), QuietDecision::Deliver);
+    }
+
+    #[test]
+    fn dnd_rejects_durations_out_of_range() {
+        let (mut quiet_hours, _clock) = quiet_hours(QuietMode::Silent, &[], "22:00", "07:00");
+        assert!(quiet_hours.set_dnd(true, Some(Duration::from_secs(u64::MAX))).is_err());
+        assert!(quiet_hours.set_dnd(true, Some(Duration::from_secs(i64::MAX as u64 / 1000))).is_err());
+        assert!(!quiet_hours.is_dnd_active());
+    }
+
+    #[test]
+    fn queued_notifications_become_a_digest_after_the_window() {
+        let (mut quiet_hours, clock) = quiet_hours(QuietMode::Queue, &[], "22:00", "07:00");
+        clock.set(at(3, 23, 0));
+        quiet_hours.queue(&NotificationRequest::new("Backup done", "All good"));
+        clock.set(at(4, 2, 0));
+        quiet_hours.queue(&NotificationRequest::new("Disk space low", "10% left"));
+
+        assert_eq!(quiet_hours.queued_count(), 2);
+        assert!(quiet_hours.take_digest().is_none());
+
+        clock.set(at(4, 7, 0));
+        let batch = quiet_hours.take_digest().unwrap();
+        let titles: Vec<&str> = batch.items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, ["Backup done", "Disk space low"]);
+        assert_eq!(batch.items[1].received, at(4, 2, 0));
+        assert_eq!(batch.overflow, 0);
+
+        assert_eq!(quiet_hours.queued_count(), 0);
+        assert!(quiet_hours.take_digest().is_none());
+    }
+
+    #[test]
+    fn queue_overflow_is_counted_in_the_digest() {
+        let (mut quiet_hours, clock) = quiet_hours(QuietMode::Queue, &[], "22:00", "07:00");
+        clock.set(at(3, 23, 0));
+        for index in 0..5 {
+            quiet_hours.queue(&NotificationRequest::new(&format!("Alert {}", index), ""));
+        }
+        assert_eq!(quiet_hours.queued_count(), 5);
+
+        clock.set(at(4, 8, 0));
+        let batch = quiet_hours.take_digest().unwrap();
+        assert_eq!(batch.items.len(), 3);
+        assert_eq!(batch.overflow, 2);
+
+        let summary = batch.summary(2, None);
+        assert_eq!(summary.title, "Quiet hours: 5 notifications");
+        assert_eq!(summary.message, "Alert 0\nAlert 1\nand 3 more");
+    }
+}
@@ -0,0 +1,552 @@
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use reqwest::multipart::{Form, Part};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::notifications::{ImagePosition, NotificationRequest, Priority};
+use crate::services::{ActivationEvent, NotificationManager};
+use crate::utils::config::RelayConfig;
+
+/// Prefix of the `origin` of notifications received from a hub, followed by the hub's id
+const ORIGIN_PREFIX: &str = "relay:";
+/// Forwarded notifications remembered for propagating their clicks
+const TRACKED_NOTIFICATIONS: usize = 1000;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+const MAX_RELAY_ID_LENGTH: usize = 64;
+
+/// A downstream server as written in `[[relay.targets]]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RelayTarget {
+    pub name: String,
+    /// Base URL of the server, such as `https://alice-pc:3000`
+    pub url: String,
+    /// API token sent as a bearer token. Prefer `token_env` to keep it out of the config file.
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub token_env: Option<String>,
+    /// Only forward notifications at least this important
+    #[serde(default)]
+    pub min_priority: Option<Priority>,
+    /// Only forward notifications from these senders, any sender when empty
+    #[serde(default)]
+    pub senders: Vec<String>,
+    /// Regular expression the title or message has to match
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Leave out the image and attachments
+    #[serde(default)]
+    pub skip_files: bool,
+    /// Name of the token or client certificate the target reports clicks with. Clicks on the
+    /// notifications forwarded to it are only accepted from this identity.
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+/// The `origin` for a notification a hub forwarded with `relay_id`, `None` for an invalid id
+pub fn relay_origin(relay_id: &str) -> Option<String> {
+    let relay_id = relay_id.trim();
+    let is_valid = !relay_id.is_empty()
+        && relay_id.len() <= MAX_RELAY_ID_LENGTH
+        && relay_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_valid.then(|| format!("{}{}", ORIGIN_PREFIX, relay_id))
+}
+
+/// The name a value serializes to, such as `high` for a priority
+fn serde_name<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_value(value).ok()?.as_str().map(str::to_string)
+}
+
+/// A notification waiting to be forwarded, with the id clicks are reported back with
+struct Forwarded {
+    id: String,
+    request: NotificationRequest,
+}
+
+/// Why forwarding failed, and whether another attempt can help
+enum ForwardError {
+    Temporary(anyhow::Error),
+    Rejected(anyhow::Error),
+}
+
+/// A target with its rules parsed and its credentials resolved
+struct TargetClient {
+    name: String,
+    url: String,
+    token: Option<String>,
+    min_priority: Option<Priority>,
+    senders: Vec<String>,
+    pattern: Option<Regex>,
+    skip_files: bool,
+    identity: Option<String>,
+    client: reqwest::Client,
+}
+
+impl TargetClient {
+    fn new(target: &RelayTarget, client: reqwest::Client) -> Result<Self> {
+        let token = match (&target.token, &target.token_env) {
+            (_, Some(env)) => Some(std::env::var(env)
+                .map_err(|_| anyhow!("Environment variable {} for the token is not set", env))?),
+            (token, None) => token.clone(),
+        };
+        let pattern = target.pattern.as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid pattern")?;
+        reqwest::Url::parse(&target.url).context("Invalid url")?;
+        Ok(Self {
+            name: target.name.clone(),
+            url: target.url.trim_end_matches('/').to_string(),
+            token,
+            min_priority: target.min_priority,
+            senders: target.senders.clone(),
+            pattern,
+            skip_files: target.skip_files,
+            identity: target.identity.clone(),
+            client,
+        })
+    }
+
+    fn matches(&self, request: &NotificationRequest) -> bool {
+        if self.min_priority.is_some_and(|min_priority| request.priority < min_priority) {
+            return false;
+        }
+        if !self.senders.is_empty() {
+            let sender = request.sender.as_deref().unwrap_or_default();
+            if !self.senders.iter().any(|allowed| allowed.eq_ignore_ascii_case(sender)) {
+                return false;
+            }
+        }
+        match &self.pattern {
+            Some(pattern) => pattern.is_match(&request.title) || pattern.is_match(&request.message),
+            None => true,
+        }
+    }
+
+    /// Posts the notification to the target's `/notify` endpoint
+    async fn send(&self, forwarded: &Forwarded) -> Result<(), ForwardError> {
+        let request = &forwarded.request;
+        let mut form = Form::new()
+            .text("title", request.title.clone())
+            .text("message", request.message.clone())
+            .text("relay_id", forwarded.id.clone());
+        let fields = [
+            ("priority", serde_name(&request.priority)),
+            ("url", request.url.clone()),
+            ("sender", request.sender.clone()),
+            ("dedup_key", request.dedup_key.clone()),
+            ("replace_key", request.replace_key.clone()),
+            ("digest", request.digest.clone()),
+            ("image_position", request.image_position.as_ref().map(|position| match position {
+                ImagePosition::Hero => "hero".to_string(),
+                ImagePosition::AppLogoOverride => "logo".to_string(),
+            })),
+            ("default_action", request.default_action.as_ref().map(|actions| {
+                actions.iter().filter_map(serde_name).collect::<Vec<_>>().join(",")
+            })),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                form = form.text(name, value);
+            }
+        }
+        if !request.links.is_empty() {
+            if let Ok(links) = serde_json::to_string(&request.links) {
+                form = form.text("links", links);
+            }
+        }
+
+        if !self.skip_files {
+            if let Some(image_path) = &request.image_path {
+                if let Some(part) = file_part(image_path).await {
+                    form = form.part("image", part);
+                }
+            }
+            for file_path in request.file_paths.iter().flatten() {
+                if let Some(part) = file_part(file_path).await {
+                    form = form.part("files", part);
+                }
+            }
+        }
+
+        let mut http = self.client.post(format!("{}/notify", self.url)).multipart(form);
+        if let Some(token) = &self.token {
+            http = http.bearer_auth(token);
+        }
+        let response = http.send().await.map_err(|e| ForwardError::Temporary(e.into()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let error = anyhow!("{} {}", status, body.trim());
+        if is_retryable(status) {
+            Err(ForwardError::Temporary(error))
+        } else {
+            Err(ForwardError::Rejected(error))
+        }
+    }
+}
+
+/// Whether another attempt can help after a target answered with `status`
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// A file as a multipart part, `None` if it can no longer be read
+async fn file_part(path: &str) -> Option<Part> {
+    let filename = Path::new(path).file_name()?.to_string_lossy().into_owned();
+    match tokio::fs::read(path).await {
+        Ok(content) => Some(Part::bytes(content).file_name(filename)),
+        Err(e) => {
+            log::warn!("Not forwarding {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// A forwarded notification, for propagating clicks reported by a target
+#[derive(Debug, Clone)]
+pub struct TrackedForward {
+    pub title: String,
+    pub origin: Option<String>,
+    /// Identities of the targets it was forwarded to, the only ones that may report its clicks
+    pub reporters: Vec<String>,
+}
+
+impl TrackedForward {
+    pub fn may_report(&self, identity: &str) -> bool {
+        self.reporters.iter().any(|reporter| reporter == identity)
+    }
+}
+
+#[derive(Default)]
+struct Tracked {
+    forwards: HashMap<String, TrackedForward>,
+    order: VecDeque<String>,
+}
+
+/// Fans notifications out to the downstream targets, each through its own queue
+pub struct Relay {
+    targets: Vec<(Arc<TargetClient>, mpsc::Sender<Arc<Forwarded>>)>,
+    tracked: Mutex<Tracked>,
+}
+
+impl Relay {
+    /// Queues `request` for every target whose rules match it. Notifications that came from a
+    /// hub are not forwarded again, so relays configured in a cycle do not loop.
+    pub fn forward(&self, request: &NotificationRequest) {
+        if request.origin.as_deref().is_some_and(|origin| origin.starts_with(ORIGIN_PREFIX)) {
+            return;
+        }
+        let targets: Vec<_> = self.targets.iter().filter(|(target, _)| target.matches(request)).collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let reporters = targets.iter().filter_map(|(target, _)| target.identity.clone()).collect();
+        self.track(id.clone(), TrackedForward { title: request.title.clone(), origin: request.origin.clone(), reporters });
+        let forwarded = Arc::new(Forwarded { id, request: request.clone() });
+        for (target, queue) in targets {
+            match queue.try_send(Arc::clone(&forwarded)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => log::warn!("Relay queue for {} is full, not forwarding: {}", target.name, request.title),
+                Err(TrySendError::Closed(_)) => log::error!("Relay worker for {} has stopped", target.name),
+            }
+        }
+    }
+
+    fn track(&self, id: String, forward: TrackedForward) {
+        let mut tracked = self.tracked.lock().unwrap();
+        tracked.forwards.insert(id.clone(), forward);
+        tracked.order.push_back(id);
+        while tracked.order.len() > TRACKED_NOTIFICATIONS {
+            if let Some(oldest) = tracked.order.pop_front() {
+                tracked.forwards.remove(&oldest);
+            }
+        }
+    }
+
+    /// The forwarded notification a target reported a click on
+    pub fn activated(&self, id: &str) -> Option<TrackedForward> {
+        self.tracked.lock().unwrap().forwards.get(id).cloned()
+    }
+}
+
+/// Delivers one target's queue in order, retrying with a growing delay
+async fn run_target(target: Arc<TargetClient>, mut queue: mpsc::Receiver<Arc<Forwarded>>, max_attempts: u32, retry_delay: Duration) {
+    while let Some(forwarded) = queue.recv().await {
+        let title = &forwarded.request.title;
+        let mut delay = retry_delay;
+        for attempt in 1..=max_attempts.max(1) {
+            match target.send(&forwarded).await {
+                Ok(()) => {
+                    log::info!("Forwarded notification to {}: {}", target.name, title);
+                    break;
+                }
+                Err(ForwardError::Temporary(e)) if attempt < max_attempts => {
+                    log::warn!("Failed to forward notification to {} (attempt {}), retrying in {:?}: {}", target.name, attempt, delay, e);
+                    actix_web::rt::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+                Err(ForwardError::Temporary(e)) => {
+                    log::error!("Giving up forwarding notification to {} after {} attempts: {}", target.name, attempt, e);
+                    break;
+                }
+                Err(ForwardError::Rejected(e)) => {
+                    log::error!("{} rejected forwarded notification {}: {}", target.name, title, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Reports clicks on notifications forwarded by the upstream hub back to it
+async fn report_activations(client: reqwest::Client, upstream: String, token: Option<String>, mut activations: broadcast::Receiver<ActivationEvent>) {
+    let url = format!("{}/relay/activations", upstream.trim_end_matches('/'));
+    loop {
+        let activation = match activations.recv().await {
+            Ok(activation) => activation,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Missed {} click events for the upstream hub", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let id = match activation.origin.strip_prefix(ORIGIN_PREFIX) {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let mut http = client.post(&url).json(&json!({ "id": id }));
+        if let Some(token) = &token {
+            http = http.bearer_auth(token);
+        }
+        match http.send().await.and_then(reqwest::Response::error_for_status) {
+            Ok(_) => log::info!("Reported click on forwarded notification {} to {}", id, upstream),
+            Err(e) => log::error!("Failed to report click to {}: {}", upstream, e),
+        }
+    }
+}
+
+/// Starts a worker per target and hands the relay to the manager, and reports clicks to the
+/// upstream hub when one is configured
+pub fn start(config: &RelayConfig, manager: Arc<Mutex<NotificationManager>>) -> Result<()> {
+    if config.targets.is_empty() && config.upstream.is_none() {
+        return Ok(());
+    }
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs.max(1)))
+        .build()?;
+
+    if !config.targets.is_empty() {
+        let mut targets = Vec::with_capacity(config.targets.len());
+        for target in &config.targets {
+            let target = TargetClient::new(target, client.clone())
+                .with_context(|| format!("Invalid relay target {}", target.name))?;
+            let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+            let target = Arc::new(target);
+            actix_web::rt::spawn(run_target(
+                Arc::clone(&target),
+                receiver,
+                config.max_attempts,
+                Duration::from_secs(config.retry_delay_secs),
+            ));
+            targets.push((target, sender));
+        }
+        log::info!("Forwarding notifications to {} relay target(s)", targets.len());
+        manager.lock().unwrap().set_relay(Arc::new(Relay { targets, tracked: Mutex::new(Tracked::default()) }));
+    }
+
+    if let Some(upstream) = &config.upstream {
+        reqwest::Url::parse(upstream).context("Invalid relay upstream")?;
+        let token = config.upstream_token()?;
+        let activations = manager.lock().unwrap().subscribe_activations();
+        log::info!("Reporting clicks on forwarded notifications to {}", upstream);
+        actix_web::rt::spawn(report_activations(client, upstream.clone(), token, activations));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn target(name: &str, url: &str) -> RelayTarget {
+        RelayTarget {
+            name: name.to_string(),
+            url: url.to_string(),
+            token: None,
+            token_env: None,
+            min_priority: None,
+            senders: Vec::new(),
+            pattern: None,
+            skip_files: false,
+            identity: None,
+        }
+    }
+
+    fn client(target: &RelayTarget) -> TargetClient {
+        TargetClient::new(target, reqwest::Client::new()).unwrap()
+    }
+
+    fn request(title: &str, sender: Option<&str>, priority: Priority) -> NotificationRequest {
+        let mut request = NotificationRequest::new(title, "message");
+        request.sender = sender.map(str::to_string);
+        request.priority = priority;
+        request
+    }
+
+    #[test]
+    fn relay_origin_accepts_only_plain_ids() {
+        assert_eq!(relay_origin(" 3f2a9c_b-1 ").as_deref(), Some("relay:3f2a9c_b-1"));
+        assert_eq!(relay_origin(""), None);
+        assert_eq!(relay_origin("mqtt:topic"), None);
+        assert_eq!(relay_origin("a b"), None);
+        assert_eq!(relay_origin(&"a".repeat(MAX_RELAY_ID_LENGTH)).map(|origin| origin.len()), Some(MAX_RELAY_ID_LENGTH + ORIGIN_PREFIX.len()));
+        assert_eq!(relay_origin(&"a".repeat(MAX_RELAY_ID_LENGTH + 1)), None);
+    }
+
+    #[test]
+    fn targets_match_by_priority_sender_and_pattern() {
+        let any = client(&target("any", "http://localhost:3000"));
+        assert!(any.matches(&request("Backup done", None, Priority::Min)));
+
+        let strict = client(&RelayTarget {
+            min_priority: Some(Priority::High),
+            senders: vec!["Alertmanager".to_string()],
+            pattern: Some("(?i)prod".to_string()),
+            ..target("strict", "http://localhost:3000")
+        });
+        assert!(strict.matches(&request("PROD down", Some("alertmanager"), Priority::Urgent)));
+        assert!(!strict.matches(&request("PROD down", Some("alertmanager"), Priority::Normal)));
+        assert!(!strict.matches(&request("PROD down", Some("Grafana"), Priority::High)));
+        assert!(!strict.matches(&request("PROD down", None, Priority::High)));
+        assert!(!strict.matches(&request("Staging down", Some("Alertmanager"), Priority::High)));
+
+        let mut in_message = request("Down", Some("Alertmanager"), Priority::High);
+        in_message.message = "prod-db-1 is unreachable".to_string();
+        assert!(strict.matches(&in_message));
+    }
+
+    #[test]
+    fn invalid_targets_are_rejected() {
+        let client = reqwest::Client::new();
+        assert!(TargetClient::new(&target("bad", "not a url"), client.clone()).is_err());
+        assert!(TargetClient::new(&RelayTarget { pattern: Some("(".to_string()), ..target("bad", "http://localhost") }, client.clone()).is_err());
+        let missing_env = RelayTarget { token_env: Some("RELAY_TEST_TOKEN_THAT_IS_NOT_SET".to_string()), ..target("bad", "http://localhost") };
+        assert!(TargetClient::new(&missing_env, client).is_err());
+    }
+
+    #[test]
+    fn only_some_failures_are_retried() {
+        for status in [StatusCode::INTERNAL_SERVER_ERROR, StatusCode::SERVICE_UNAVAILABLE, StatusCode::REQUEST_TIMEOUT, StatusCode::TOO_MANY_REQUESTS] {
+            assert!(is_retryable(status), "{}", status);
+        }
+        for status in [StatusCode::BAD_REQUEST, StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::PAYLOAD_TOO_LARGE] {
+            assert!(!is_retryable(status), "{}", status);
+        }
+    }
+
+    #[test]
+    fn forwards_track_their_reporters_and_skip_relayed_notifications() {
+        let (sender, mut receiver) = mpsc::channel(4);
+        let alice = Arc::new(client(&RelayTarget { identity: Some("alice".to_string()), ..target("alice", "http://alice:3000") }));
+        let relay = Relay { targets: vec![(alice, sender)], tracked: Mutex::new(Tracked::default()) };
+
+        relay.forward(&request("Disk full", None, Priority::High));
+        let forwarded = receiver.try_recv().unwrap();
+        let tracked = relay.activated(&forwarded.id).unwrap();
+        assert!(tracked.may_report("alice"));
+        assert!(!tracked.may_report("mallory"));
+        assert!(relay.activated("unknown").is_none());
+
+        let mut relayed = request("From the hub", None, Priority::High);
+        relayed.origin = relay_origin("abc");
+        relay.forward(&relayed);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    /// Answers each request with the next status, repeating the last one, and counts requests
+    async fn fake_target(statuses: Vec<u16>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        actix_web::rt::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                let index = counter.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[index.min(statuses.len() - 1)];
+                // Read the whole request so the client does not see a reset
+                let mut received = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let read = stream.read(&mut buffer).await.unwrap_or(0);
+                    received.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&received);
+                    let complete = text.split_once("\r\n\r\n").is_some_and(|(headers, body)| {
+                        let length = headers.lines()
+                            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse::<usize>().unwrap_or(0)));
+                        match length {
+                            Some(length) => body.len() >= length,
+                            None => body.ends_with("0\r\n\r\n"),
+                        }
+                    });
+                    if read == 0 || complete {
+                        break;
+                    }
+                }
+                let response = format!("HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    async fn deliver(url: &str, max_attempts: u32) {
+        let target = Arc::new(client(&target("test", url)));
+        let (sender, receiver) = mpsc::channel(1);
+        sender.send(Arc::new(Forwarded { id: "1".to_string(), request: request("Disk full", None, Priority::High) })).await.unwrap();
+        drop(sender);
+        run_target(target, receiver, max_attempts, Duration::ZERO).await;
+    }
+
+    #[actix_web::test]
+    async fn retries_temporary_failures() {
+        let (url, requests) = fake_target(vec![503, 429, 200]).await;
+        deliver(&url, 5).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn gives_up_after_max_attempts() {
+        let (url, requests) = fake_target(vec![500]).await;
+        deliver(&url, 3).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[actix_web::test]
+    async fn does_not_retry_rejections() {
+        let (url, requests) = fake_target(vec![400, 200]).await;
+        deliver(&url, 5).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,462 @@
+use anyhow::{anyhow, Context, Result};
+use ipnet::IpNet;
+use mail_parser::{Message, MessageParser, MimeHeaders};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+use crate::notifications::{NotificationRequest, Priority};
+use crate::services::NotificationManager;
+use crate::utils::config::SmtpConfig;
+use crate::utils::constants::NOTIFICATION_ASSETS_DIR;
+use crate::utils::network::{normalize_ip, parse_cidr};
+use crate::utils::rate_limit::RateLimiter;
+
+/// Longest command line, the limit RFC 5321 sets for lines of text
+const MAX_LINE_BYTES: u64 = 1000;
+const MAX_RECIPIENTS: usize = 100;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A mailbox accepted by the receiver, as written in `[[smtp.recipients]]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpRecipient {
+    /// `alerts@desktop.lan`, `@desktop.lan` for every mailbox of a domain or `*` for any address
+    pub address: String,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// Shown as attribution instead of the email's sender
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+
+impl SmtpRecipient {
+    fn matches(&self, address: &str) -> bool {
+        let pattern = self.address.trim();
+        if pattern == "*" {
+            return true;
+        }
+        match pattern.strip_prefix('@') {
+            Some(domain) => address.rsplit_once('@').is_some_and(|(_, address_domain)| address_domain.eq_ignore_ascii_case(domain)),
+            None => pattern.eq_ignore_ascii_case(address),
+        }
+    }
+}
+
+/// The sender and recipients given for the message being received
+#[derive(Debug, Default)]
+struct Envelope {
+    from: Option<String>,
+    /// Indexes into the configured recipients
+    recipients: Vec<usize>,
+}
+
+/// The address in `FROM:<address>` or `TO:<address>` and the parameters after it
+fn parse_path<'a>(argument: &'a str, prefix: &str) -> Option<(String, &'a str)> {
+    let rest = argument.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| argument[prefix.len()..].trim_start())?;
+    let rest = rest.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    // Source routes such as `<@relay:user@host>` are obsolete, only the mailbox matters
+    let path = &rest[..end];
+    let address = if path.starts_with('@') { path.rsplit_once(':').map_or(path, |(_, address)| address) } else { path };
+    Some((address.to_string(), rest[end + 1..].trim()))
+}
+
+/// Reads a line of at most `limit` bytes including its line ending, `None` at the end of the stream
+async fn read_line(reader: &mut BufReader<OwnedReadHalf>, limit: u64) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let read = actix_web::rt::time::timeout(IDLE_TIMEOUT, (&mut *reader).take(limit).read_until(b'\n', &mut line))
+        .await
+        .map_err(|_| anyhow!("Timed out"))??;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read as u64 == limit && !line.ends_with(b"\n") {
+        return Err(anyhow!("Line longer than {} bytes", limit));
+    }
+    Ok(Some(line))
+}
+
+/// Reads the message after `DATA` up to the line holding a single `.`, undoing dot-stuffing.
+/// `None` when the message is larger than `max_bytes`, which is still read to its end.
+async fn read_data(reader: &mut BufReader<OwnedReadHalf>, max_bytes: usize) -> Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut too_large = false;
+    loop {
+        let line = read_line(reader, max_bytes as u64 + 3).await?
+            .ok_or_else(|| anyhow!("Connection closed during DATA"))?;
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content == b"." {
+            break;
+        }
+        let content = content.strip_prefix(b".").unwrap_or(content);
+        if too_large || data.len() + content.len() + 2 > max_bytes {
+            too_large = true;
+            data = Vec::new();
+            continue;
+        }
+        data.extend_from_slice(content);
+        data.extend_from_slice(b"\r\n");
+    }
+    Ok((!too_large).then_some(data))
+}
+
+async fn reply(writer: &mut OwnedWriteHalf, response: &str) -> Result<()> {
+    writer.write_all(format!("{}\r\n", response).as_bytes()).await?;
+    Ok(())
+}
+
+/// A minimal SMTP server that shows emails to the configured recipients as notifications
+pub struct SmtpReceiver {
+    config: SmtpConfig,
+    allowed_networks: Vec<IpNet>,
+    limiter: RateLimiter,
+    manager: Arc<Mutex<NotificationManager>>,
+}
+
+impl SmtpReceiver {
+    pub fn new(config: &SmtpConfig, manager: Arc<Mutex<NotificationManager>>) -> Result<Self> {
+        let allowed_networks = config.allowed_networks.iter()
+            .map(|network| parse_cidr(network))
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid smtp.allowed_networks")?;
+        if config.listen.is_some() && config.recipients.is_empty() {
+            return Err(anyhow!("The SMTP receiver needs at least one [[smtp.recipients]] entry"));
+        }
+        Ok(Self {
+            config: config.clone(),
+            allowed_networks,
+            limiter: RateLimiter::new(&config.rate_limit),
+            manager,
+        })
+    }
+
+    /// Binds the listener and accepts connections in the background
+    pub fn start(self) -> Result<()> {
+        let listen = match &self.config.listen {
+            Some(listen) => listen.clone(),
+            None => return Ok(()),
+        };
+        let listener = std::net::TcpListener::bind(&listen)
+            .with_context(|| format!("Failed to bind the SMTP receiver to {}", listen))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        log::info!("SMTP receiver listening on {}", listen);
+
+        actix_web::rt::spawn(Self::accept(Arc::new(self), listener));
+        Ok(())
+    }
+
+    async fn accept(receiver: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warn!("Failed to accept SMTP connection: {}", e);
+                    actix_web::rt::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            if !receiver.is_allowed(&peer) {
+                log::warn!("Rejected SMTP connection from {}", peer.ip());
+                continue;
+            }
+
+            let receiver = Arc::clone(&receiver);
+            actix_web::rt::spawn(async move {
+                if let Err(e) = receiver.handle_connection(stream, peer).await {
+                    log::debug!("SMTP connection from {} ended: {}", peer.ip(), e);
+                }
+            });
+        }
+    }
+
+    fn is_allowed(&self, peer: &SocketAddr) -> bool {
+        let ip = normalize_ip(peer.ip());
+        self.allowed_networks.is_empty() || self.allowed_networks.iter().any(|network| network.contains(&ip))
+    }
+
+    async fn handle_connection(&self, stream: TcpStream, peer: SocketAddr) -> Result<()> {
+        let hostname = &self.config.hostname;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        reply(&mut writer, &format!("220 {} ESMTP ready", hostname)).await?;
+
+        let mut envelope = Envelope::default();
+        while let Some(line) = read_line(&mut reader, MAX_LINE_BYTES).await? {
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            let (verb, argument) = line.split_once(' ').unwrap_or((line, ""));
+
+            let response = match verb.to_ascii_uppercase().as_str() {
+                "EHLO" => format!("250-{}\r\n250-SIZE {}\r\n250-8BITMIME\r\n250 SMTPUTF8", hostname, self.config.max_message_bytes),
+                "HELO" => format!("250 {}", hostname),
+                "MAIL" => self.mail(&mut envelope, argument.trim()),
+                "RCPT" => self.rcpt(&mut envelope, argument.trim()),
+                "DATA" if envelope.recipients.is_empty() => "503 Need RCPT before DATA".to_string(),
+                "DATA" => {
+                    reply(&mut writer, "354 End data with <CR><LF>.<CR><LF>").await?;
+                    let response = match read_data(&mut reader, self.config.max_message_bytes).await? {
+                        Some(data) => self.deliver(&envelope, &data, peer).await,
+                        None => format!("552 Message exceeds {} bytes", self.config.max_message_bytes),
+                    };
+                    envelope = Envelope::default();
+                    response
+                }
+                "RSET" => {
+                    envelope = Envelope::default();
+                    "250 OK".to_string()
+                }
+                "NOOP" => "250 OK".to_string(),
+                "VRFY" => "252 Cannot verify user".to_string(),
+                "QUIT" => {
+                    reply(&mut writer, &format!("221 {} closing connection", hostname)).await?;
+                    return Ok(());
+                }
+                _ => "502 Command not implemented".to_string(),
+            };
+            reply(&mut writer, &response).await?;
+        }
+        Ok(())
+    }
+
+    fn mail(&self, envelope: &mut Envelope, argument: &str) -> String {
+        let (address, parameters) = match parse_path(argument, "FROM:") {
+            Some(path) => path,
+            None => return "501 Syntax: MAIL FROM:<address>".to_string(),
+        };
+        let size = parameters.split_whitespace().find_map(|parameter| {
+            let (key, value) = parameter.split_once('=')?;
+            key.eq_ignore_ascii_case("SIZE").then(|| value.parse::<usize>().ok()).flatten()
+        });
+        if size.is_some_and(|size| size > self.config.max_message_bytes) {
+            return format!("552 Message exceeds {} bytes", self.config.max_message_bytes);
+        }
+        *envelope = Envelope { from: Some(address), recipients: Vec::new() };
+        "250 OK".to_string()
+    }
+
+    fn rcpt(&self, envelope: &mut Envelope, argument: &str) -> String {
+        if envelope.from.is_none() {
+            return "503 Need MAIL before RCPT".to_string();
+        }
+        let (address, _) = match parse_path(argument, "TO:") {
+            Some(path) => path,
+            None => return "501 Syntax: RCPT TO:<address>".to_string(),
+        };
+        if envelope.recipients.len() >= MAX_RECIPIENTS {
+            return "452 Too many recipients".to_string();
+        }
+        match self.config.recipients.iter().position(|recipient| recipient.matches(&address)) {
+            Some(index) => {
+                envelope.recipients.push(index);
+                "250 OK".to_string()
+            }
+            None => {
+                log::info!("Rejected SMTP mail for unknown recipient {}", address);
+                "550 No such recipient".to_string()
+            }
+        }
+    }
+
+    /// Shows a received message once, with the settings of its first accepted recipient
+    async fn deliver(&self, envelope: &Envelope, data: &[u8], peer: SocketAddr) -> String {
+        let recipient = &self.config.recipients[envelope.recipients[0]];
+        if let Err(retry_after) = self.limiter.check(&recipient.address, Some(normalize_ip(peer.ip()))) {
+            let label = format!("SMTP recipient {}", recipient.address);
+            self.limiter.record_rejection(recipient.address.clone(), label, retry_after, Arc::clone(&self.manager));
+            return format!("450 Rate limit exceeded, retry in {} seconds", retry_after.as_secs().max(1));
+        }
+        let request = match to_request(data, recipient, self.config.save_attachments) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!("Rejected email from {}: {:#}", peer.ip(), e);
+                return "554 Message could not be processed".to_string();
+            }
+        };
+        log::info!("Email from {}: {}", envelope.from.as_deref().unwrap_or_default(), request.title);
+
+        let mut manager = self.manager.lock().unwrap();
+        match manager.send_notification(request).await {
+            Ok(_) => "250 OK".to_string(),
+            Err(e) => {
+                log::error!("Failed to send email notification: {}", e);
+                "451 Failed to show notification".to_string()
+            }
+        }
+    }
+}
+
+/// The notification for a received message, stored attachments included with `save_attachments`
+fn to_request(data: &[u8], recipient: &SmtpRecipient, save_attachments: bool) -> Result<NotificationRequest> {
+    let message = MessageParser::default().parse(data).ok_or_else(|| anyhow!("Message could not be parsed"))?;
+    let from = message.from()
+        .and_then(|from| from.first())
+        .and_then(|from| from.name().or(from.address()))
+        .map(str::to_string);
+
+    let title = message.subject()
+        .map(str::trim)
+        .filter(|subject| !subject.is_empty())
+        .map(str::to_string)
+        .or_else(|| from.as_ref().map(|from| format!("Email from {}", from)))
+        .unwrap_or_else(|| "Email".to_string());
+    let body = message.body_text(0).map(|body| body.trim().to_string()).unwrap_or_default();
+
+    let mut request = NotificationRequest::new(&title, &body);
+    request.sender = recipient.sender.clone().or(from);
+    request.priority = recipient.priority.unwrap_or_default();
+    request.digest = recipient.digest.clone();
+    if save_attachments {
+        save_parts(&message, &mut request)?;
+    }
+    Ok(request)
+}
+
+/// Stores the message's attachments, showing the first inline image as the toast's image
+fn save_parts(message: &Message, request: &mut NotificationRequest) -> Result<()> {
+    let mut dir: Option<PathBuf> = None;
+    let mut file_paths = Vec::new();
+
+    for (index, part) in message.attachments().enumerate() {
+        let content = part.contents();
+        if content.is_empty() {
+            continue;
+        }
+        let image_format = image::guess_format(content).ok();
+        // Images referenced from the HTML body have a Content-ID but often no disposition
+        let is_inline = part.content_disposition().map_or(part.content_id().is_some(), |disposition| disposition.is_inline());
+        let extension = image_format.and_then(|format| format.extensions_str().first().copied()).unwrap_or("bin");
+        let filename = part.attachment_name()
+            .and_then(|name| Path::new(name).file_name())
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("attachment-{}.{}", index + 1, extension));
+
+        let dir = match &mut dir {
+            Some(dir) => dir,
+            None => {
+                let path = env::temp_dir().join(NOTIFICATION_ASSETS_DIR).join(format!("smtp_{}", Uuid::new_v4()));
+                fs::create_dir_all(&path).context("Failed to create temp directory")?;
+                dir.insert(path)
+            }
+        };
+        let mut path = dir.join(&filename);
+        if path.exists() {
+            path = dir.join(format!("{}-{}", index + 1, filename));
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to save attachment {}", filename))?;
+
+        let path = path.to_string_lossy().into_owned();
+        if image_format.is_some() && is_inline && request.image_path.is_none() {
+            request.image_path = Some(path);
+        } else {
+            file_paths.push(path);
+        }
+    }
+
+    if !file_paths.is_empty() {
+        request.file_paths = Some(file_paths);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipient(address: &str) -> SmtpRecipient {
+        SmtpRecipient { address: address.to_string(), priority: None, digest: None, sender: None }
+    }
+
+    #[test]
+    fn prefers_the_plain_text_part() {
+        let request = to_request(include_bytes!("../../tests/fixtures/smtp/alternative.eml"), &recipient("*"), false).unwrap();
+        assert_eq!(request.title, "[NAS] Volume 1 degraded");
+        assert_eq!(request.message, "Volume 1 on nas is degraded.\r\nReplace drive 2 soon.");
+        assert_eq!(request.sender.as_deref(), Some("Synology NAS"));
+        assert_eq!(request.priority, Priority::Normal);
+    }
+
+    #[test]
+    fn converts_html_only_messages() {
+        let request = to_request(include_bytes!("../../tests/fixtures/smtp/html_only.eml"), &recipient("*"), false).unwrap();
+        // Without a subject the title names the sender, which has no display name
+        assert_eq!(request.title, "Email from ups@home.lan");
+        assert!(request.message.contains("Power failure"), "{}", request.message);
+        assert!(request.message.contains("Running on battery, 35 minutes left."), "{}", request.message);
+        assert!(!request.message.contains('<'), "{}", request.message);
+    }
+
+    #[test]
+    fn applies_the_recipient_settings() {
+        let ups = SmtpRecipient {
+            priority: Some(Priority::Urgent),
+            digest: Some("ups".to_string()),
+            sender: Some("UPS".to_string()),
+            ..recipient("ups@desktop.lan")
+        };
+        let request = to_request(include_bytes!("../../tests/fixtures/smtp/html_only.eml"), &ups, false).unwrap();
+        assert_eq!(request.sender.as_deref(), Some("UPS"));
+        assert_eq!(request.priority, Priority::Urgent);
+        assert_eq!(request.digest.as_deref(), Some("ups"));
+    }
+
+    #[test]
+    fn ignores_attachments_by_default() {
+        let request = to_request(include_bytes!("../../tests/fixtures/smtp/attachments.eml"), &recipient("*"), false).unwrap();
+        assert_eq!(request.title, "Scan ready");
+        assert_eq!(request.message, "Your scan is attached.");
+        assert_eq!(request.image_path, None);
+        assert_eq!(request.file_paths, None);
+    }
+
+    #[test]
+    fn saves_attachments_when_enabled() {
+        let request = to_request(include_bytes!("../../tests/fixtures/smtp/attachments.eml"), &recipient("*"), true).unwrap();
+
+        let image_path = request.image_path.unwrap();
+        assert!(image_path.ends_with(".png"), "{}", image_path);
+        let file_paths = request.file_paths.unwrap();
+        assert_eq!(file_paths.len(), 1);
+        // The attachment name is reduced to its last component
+        let file = Path::new(&file_paths[0]);
+        assert_eq!(file.file_name().and_then(|name| name.to_str()), Some("scan.pdf"));
+        assert_eq!(file.parent(), Path::new(&image_path).parent());
+        assert!(fs::read(file).unwrap().starts_with(b"%PDF-1.4"));
+
+        fs::remove_dir_all(file.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn matches_recipients() {
+        assert!(recipient("alerts@desktop.lan").matches("Alerts@Desktop.lan"));
+        assert!(!recipient("alerts@desktop.lan").matches("other@desktop.lan"));
+        assert!(recipient("@desktop.lan").matches("anyone@DESKTOP.lan"));
+        assert!(!recipient("@desktop.lan").matches("anyone@evil-desktop.lan"));
+        assert!(recipient("*").matches("anything@anywhere"));
+    }
+
+    #[test]
+    fn parses_paths_and_parameters() {
+        assert_eq!(parse_path("FROM:<nas@home.lan> SIZE=1024", "FROM:"), Some(("nas@home.lan".to_string(), "SIZE=1024")));
+        assert_eq!(parse_path("TO:<@relay.lan:alerts@desktop.lan>", "TO:").map(|(address, _)| address), Some("alerts@desktop.lan".to_string()));
+        assert_eq!(parse_path("to: <alerts@desktop.lan>", "TO:").map(|(address, _)| address), Some("alerts@desktop.lan".to_string()));
+        assert_eq!(parse_path("FROM:<>", "FROM:").map(|(address, _)| address), Some(String::new()));
+        assert_eq!(parse_path("FROM:nas@home.lan", "FROM:"), None);
+    }
+}
@@ -0,0 +1,626 @@
+use anyhow::{anyhow, Context, Result};
+use ipnet::IpNet;
+use regex::Regex;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::notifications::{NotificationRequest, Priority};
+use crate::services::NotificationManager;
+use crate::utils::config::SyslogConfig;
+use crate::utils::network::{normalize_ip, parse_cidr};
+use crate::utils::rate_limit::RateLimiter;
+
+/// Facility names by code, as used by `facilities` in rules
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news",
+    "uucp", "cron", "authpriv", "ftp", "ntp", "audit", "alert", "clock",
+    "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+/// Longest RFC 3164 tag (program name) that is recognized
+const MAX_TAG_LENGTH: usize = 48;
+/// Priority assumed for messages without one, `user.notice` as RFC 3164 suggests
+const DEFAULT_PRI: u8 = 13;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Syslog severities, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[serde(alias = "emerg")]
+    Emergency,
+    Alert,
+    #[serde(alias = "crit")]
+    Critical,
+    #[serde(alias = "err")]
+    Error,
+    #[serde(alias = "warn")]
+    Warning,
+    Notice,
+    #[serde(alias = "informational")]
+    Info,
+    Debug,
+}
+
+impl Severity {
+    const ALL: [Severity; 8] = [
+        Severity::Emergency,
+        Severity::Alert,
+        Severity::Critical,
+        Severity::Error,
+        Severity::Warning,
+        Severity::Notice,
+        Severity::Info,
+        Severity::Debug,
+    ];
+
+    fn from_code(code: u8) -> Self {
+        Self::ALL[(code & 7) as usize]
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Emergency => "emergency",
+            Severity::Alert => "alert",
+            Severity::Critical => "critical",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Notice => "notice",
+            Severity::Info => "info",
+            Severity::Debug => "debug",
+        }
+    }
+
+    /// The priority of toasts for rules that do not set one
+    fn priority(self) -> Priority {
+        match self {
+            Severity::Emergency | Severity::Alert | Severity::Critical => Priority::Urgent,
+            Severity::Error => Priority::High,
+            Severity::Warning => Priority::Normal,
+            Severity::Notice | Severity::Info => Priority::Low,
+            Severity::Debug => Priority::Min,
+        }
+    }
+}
+
+/// A rule as written in `[[syslog.rules]]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyslogRule {
+    /// Shown in logs and used as the rule's rate limit key
+    pub name: String,
+    /// Facility names such as `auth` or `local0`, any facility when empty
+    #[serde(default)]
+    pub facilities: Vec<String>,
+    /// Least severe level that matches, so `warning` also matches `error` and `critical`
+    #[serde(default)]
+    pub severity: Option<Severity>,
+    /// Regular expression the message text has to match
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Text with `${1}` or `${name}` capture groups and `{host}`, `{app}`, `{severity}` and
+    /// `{facility}` placeholders
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Like `title`, the message text when not set
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Derived from the severity when not set
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+/// A received message, with nil and missing fields as `None`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SyslogMessage {
+    facility: u8,
+    severity: Severity,
+    hostname: Option<String>,
+    app_name: Option<String>,
+    text: String,
+}
+
+fn non_nil(value: &str) -> Option<String> {
+    (!value.is_empty() && value != "-").then(|| value.to_string())
+}
+
+/// Parses an RFC 5424 message, or an RFC 3164 one when the version after the priority is missing
+fn parse_message(raw: &str) -> Option<SyslogMessage> {
+    let raw = raw.trim_end_matches(['\r', '\n', '\0']);
+    let (pri, rest) = match raw.strip_prefix('<') {
+        Some(rest) => {
+            let end = rest.find('>').filter(|end| *end <= 3)?;
+            let pri = rest[..end].parse::<u8>().ok().filter(|pri| *pri <= 191)?;
+            (pri, &rest[end + 1..])
+        }
+        None => (DEFAULT_PRI, raw),
+    };
+
+    let (hostname, app_name, text) = match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(rest)?,
+        None => parse_rfc3164(rest),
+    };
+    Some(SyslogMessage {
+        facility: pri / 8,
+        severity: Severity::from_code(pri % 8),
+        hostname,
+        app_name,
+        text: text.trim().to_string(),
+    })
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]` after `<PRI>1 `
+fn parse_rfc5424(rest: &str) -> Option<(Option<String>, Option<String>, &str)> {
+    let mut fields = rest.splitn(6, ' ');
+    let _timestamp = fields.next()?;
+    let hostname = non_nil(fields.next()?);
+    let app_name = non_nil(fields.next()?);
+    let _proc_id = fields.next()?;
+    let _msg_id = fields.next()?;
+    let text = skip_structured_data(fields.next().unwrap_or(""));
+    Some((hostname, app_name, text.strip_prefix('\u{feff}').unwrap_or(text)))
+}
+
+/// The message after the structured data, which is `-` or a run of `[id param="value"]` elements
+fn skip_structured_data(rest: &str) -> &str {
+    if let Some(text) = rest.strip_prefix('-') {
+        return text.strip_prefix(' ').unwrap_or(text);
+    }
+    if !rest.starts_with('[') {
+        return rest;
+    }
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, c) in rest.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ']' if !in_quotes && !rest[index + 1..].starts_with('[') => {
+                let text = &rest[index + 1..];
+                return text.strip_prefix(' ').unwrap_or(text);
+            }
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG: MSG` after `<PRI>`. Devices often leave out the timestamp,
+/// the hostname or the tag, so each is only taken when it looks like one.
+fn parse_rfc3164(rest: &str) -> (Option<String>, Option<String>, &str) {
+    let rest = rest.trim_start();
+    let after_timestamp = match rest.get(..16) {
+        Some(timestamp) if is_bsd_timestamp(timestamp) => Some(&rest[16..]),
+        // Some senders use an RFC 3339 timestamp instead
+        _ => rest.split_once(' ')
+            .filter(|(timestamp, _)| timestamp.len() >= 19 && timestamp.as_bytes()[4] == b'-' && timestamp.contains('T'))
+            .map(|(_, rest)| rest),
+    };
+
+    // Without a timestamp there is no telling a hostname from the first word of the message
+    let (hostname, rest) = match after_timestamp {
+        Some(rest) => {
+            let rest = rest.trim_start();
+            match rest.split_once(' ') {
+                Some((first, after)) if !first.ends_with(':') && !first.contains('[') => (Some(first.to_string()), after),
+                _ => (None, rest),
+            }
+        }
+        None => (None, rest),
+    };
+
+    let (app_name, text) = split_tag(rest);
+    (hostname, app_name, text)
+}
+
+fn is_bsd_timestamp(timestamp: &str) -> bool {
+    let bytes = timestamp.as_bytes();
+    timestamp.is_ascii()
+        && MONTHS.contains(&&timestamp[..3])
+        && bytes[3] == b' '
+        && timestamp[4..6].trim_start().parse::<u8>().is_ok()
+        && bytes[6] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+        && bytes[15] == b' '
+}
+
+/// Splits `sshd[123]: message` or `sshd: message` into the program name and the message
+fn split_tag(rest: &str) -> (Option<String>, &str) {
+    let end = match rest.find([':', '[', ' ']) {
+        Some(end) if end > 0 && end <= MAX_TAG_LENGTH => end,
+        _ => return (None, rest),
+    };
+    let tag = Some(rest[..end].to_string());
+    let after = &rest[end..];
+    if let Some((_, text)) = after.strip_prefix('[').and_then(|after| after.split_once(']')) {
+        return (tag, text.strip_prefix(':').unwrap_or(text).trim_start());
+    }
+    match after.strip_prefix(':') {
+        Some(text) => (tag, text.trim_start()),
+        None => (None, rest),
+    }
+}
+
+/// A rule with its facilities and pattern parsed
+#[derive(Debug)]
+struct SyslogRoute {
+    rule: SyslogRule,
+    facilities: Vec<u8>,
+    pattern: Option<Regex>,
+}
+
+impl SyslogRoute {
+    fn new(rule: &SyslogRule) -> Result<Self> {
+        let facilities = rule.facilities.iter()
+            .map(|name| FACILITIES.iter()
+                .position(|facility| facility.eq_ignore_ascii_case(name))
+                .map(|code| code as u8)
+                .ok_or_else(|| anyhow!("Unknown facility {}", name)))
+            .collect::<Result<Vec<_>>>()?;
+        let pattern = rule.pattern.as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid pattern")?;
+        Ok(Self { rule: rule.clone(), facilities, pattern })
+    }
+
+    /// The notification for a message, `None` when the rule does not match it
+    fn apply(&self, message: &SyslogMessage, host: &str) -> Option<NotificationRequest> {
+        if !self.facilities.is_empty() && !self.facilities.contains(&message.facility) {
+            return None;
+        }
+        if self.rule.severity.is_some_and(|threshold| message.severity > threshold) {
+            return None;
+        }
+        let captures = match &self.pattern {
+            Some(pattern) => Some(pattern.captures(&message.text)?),
+            None => None,
+        };
+
+        let app = message.app_name.as_deref().unwrap_or_default();
+        let render = |template: &String| {
+            let mut text = String::new();
+            match &captures {
+                Some(captures) => captures.expand(template, &mut text),
+                None => text.push_str(template),
+            }
+            let text = text
+                .replace("{host}", host)
+                .replace("{app}", app)
+                .replace("{severity}", message.severity.name())
+                .replace("{facility}", FACILITIES[message.facility as usize]);
+            Some(text.trim().to_string()).filter(|text| !text.is_empty())
+        };
+
+        let title = self.rule.title.as_ref().and_then(render).unwrap_or_else(|| match app {
+            "" => host.to_string(),
+            app => format!("{} on {}", app, host),
+        });
+        let body = self.rule.message.as_ref().and_then(render).unwrap_or_else(|| message.text.clone());
+        let mut request = NotificationRequest::new(&title, &body);
+        request.sender = Some(host.to_string());
+        request.priority = self.rule.priority.unwrap_or_else(|| message.severity.priority());
+        request.digest = self.rule.digest.clone();
+        Some(request)
+    }
+}
+
+/// Receives syslog messages over UDP and TCP and shows those matching a rule
+pub struct SyslogListener {
+    config: SyslogConfig,
+    routes: Vec<SyslogRoute>,
+    allowed_networks: Vec<IpNet>,
+    limiter: RateLimiter,
+    manager: Arc<Mutex<NotificationManager>>,
+}
+
+impl SyslogListener {
+    pub fn new(config: &SyslogConfig, manager: Arc<Mutex<NotificationManager>>) -> Result<Self> {
+        let routes = config.rules.iter()
+            .map(|rule| SyslogRoute::new(rule).with_context(|| format!("Invalid syslog rule {}", rule.name)))
+            .collect::<Result<Vec<_>>>()?;
+        let allowed_networks = config.allowed_networks.iter()
+            .map(|network| parse_cidr(network))
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid syslog.allowed_networks")?;
+        if (config.udp.is_some() || config.tcp.is_some()) && routes.is_empty() {
+            return Err(anyhow!("The syslog listener needs at least one [[syslog.rules]] entry"));
+        }
+        Ok(Self {
+            config: config.clone(),
+            routes,
+            allowed_networks,
+            limiter: RateLimiter::new(&config.rate_limit),
+            manager,
+        })
+    }
+
+    /// Binds the configured sockets and receives messages in the background
+    pub fn start(self) -> Result<()> {
+        let udp = match &self.config.udp {
+            Some(address) => {
+                let socket = std::net::UdpSocket::bind(address)
+                    .with_context(|| format!("Failed to bind the syslog UDP listener to {}", address))?;
+                socket.set_nonblocking(true)?;
+                log::info!("Syslog UDP listener on {}", address);
+                Some(UdpSocket::from_std(socket)?)
+            }
+            None => None,
+        };
+        let tcp = match &self.config.tcp {
+            Some(address) => {
+                let listener = std::net::TcpListener::bind(address)
+                    .with_context(|| format!("Failed to bind the syslog TCP listener to {}", address))?;
+                listener.set_nonblocking(true)?;
+                log::info!("Syslog TCP listener on {}", address);
+                Some(TcpListener::from_std(listener)?)
+            }
+            None => None,
+        };
+
+        let listener = Arc::new(self);
+        if let Some(socket) = udp {
+            actix_web::rt::spawn(Self::receive_udp(Arc::clone(&listener), socket));
+        }
+        if let Some(tcp) = tcp {
+            actix_web::rt::spawn(Self::accept_tcp(listener, tcp));
+        }
+        Ok(())
+    }
+
+    fn is_allowed(&self, peer: &SocketAddr) -> bool {
+        let ip = normalize_ip(peer.ip());
+        self.allowed_networks.is_empty() || self.allowed_networks.iter().any(|network| network.contains(&ip))
+    }
+
+    async fn receive_udp(listener: Arc<Self>, socket: UdpSocket) {
+        // One byte more than allowed, to tell a datagram that was cut off from one that fits
+        let mut buffer = vec![0; listener.config.max_message_bytes + 1];
+        loop {
+            let (length, peer) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    log::warn!("Failed to receive syslog datagram: {}", e);
+                    continue;
+                }
+            };
+            if !listener.is_allowed(&peer) {
+                log::debug!("Ignoring syslog datagram from {}", peer.ip());
+                continue;
+            }
+            if length > listener.config.max_message_bytes {
+                log::warn!("Dropping syslog message from {} longer than {} bytes", peer.ip(), listener.config.max_message_bytes);
+                continue;
+            }
+            listener.handle(&buffer[..length], peer).await;
+        }
+    }
+
+    async fn accept_tcp(listener: Arc<Self>, tcp: TcpListener) {
+        loop {
+            let (stream, peer) = match tcp.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warn!("Failed to accept syslog connection: {}", e);
+                    actix_web::rt::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            if !listener.is_allowed(&peer) {
+                log::warn!("Rejected syslog connection from {}", peer.ip());
+                continue;
+            }
+
+            let listener = Arc::clone(&listener);
+            actix_web::rt::spawn(async move {
+                if let Err(e) = listener.receive_tcp(stream, peer).await {
+                    log::debug!("Syslog connection from {} ended: {}", peer.ip(), e);
+                }
+            });
+        }
+    }
+
+    /// Reads messages framed by octet counting (`<length> <message>`) or ended by a newline,
+    /// as described in RFC 6587
+    async fn receive_tcp(&self, stream: TcpStream, peer: SocketAddr) -> Result<()> {
+        let max_bytes = self.config.max_message_bytes;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let first = match reader.fill_buf().await?.first() {
+                Some(first) => *first,
+                None => return Ok(()),
+            };
+
+            let mut message = Vec::new();
+            if first.is_ascii_digit() {
+                let mut length = Vec::new();
+                (&mut reader).take(8).read_until(b' ', &mut length).await?;
+                let length = std::str::from_utf8(&length).ok()
+                    .and_then(|length| length.trim_end().parse::<usize>().ok())
+                    .ok_or_else(|| anyhow!("Invalid message length"))?;
+                if length > max_bytes {
+                    return Err(anyhow!("Message of {} bytes is longer than {} bytes", length, max_bytes));
+                }
+                message.resize(length, 0);
+                reader.read_exact(&mut message).await?;
+            } else {
+                (&mut reader).take(max_bytes as u64 + 1).read_until(b'\n', &mut message).await?;
+                if message.len() > max_bytes {
+                    return Err(anyhow!("Message longer than {} bytes", max_bytes));
+                }
+            }
+            self.handle(&message, peer).await;
+        }
+    }
+
+    async fn handle(&self, raw: &[u8], peer: SocketAddr) {
+        let raw = String::from_utf8_lossy(raw);
+        if raw.trim().is_empty() {
+            return;
+        }
+        let message = match parse_message(&raw) {
+            Some(message) => message,
+            None => {
+                log::debug!("Ignoring malformed syslog message from {}", peer.ip());
+                return;
+            }
+        };
+
+        let ip = normalize_ip(peer.ip());
+        let host = message.hostname.clone().unwrap_or_else(|| ip.to_string());
+        let matched = self.routes.iter()
+            .find_map(|route| route.apply(&message, &host).map(|request| (route, request)));
+        let (route, request) = match matched {
+            Some(matched) => matched,
+            None => return,
+        };
+
+        if let Err(retry_after) = self.limiter.check(&route.rule.name, Some(ip)) {
+            let label = format!("syslog rule {}", route.rule.name);
+            self.limiter.record_rejection(route.rule.name.clone(), label, retry_after, Arc::clone(&self.manager));
+            return;
+        }
+        log::info!("Syslog rule {} matched a message from {}: {}", route.rule.name, host, request.title);
+
+        let mut manager = self.manager.lock().unwrap();
+        if let Err(e) = manager.send_notification(request).await {
+            log::error!("Failed to send syslog notification: {}", e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str) -> SyslogRule {
+        SyslogRule {
+            name: name.to_string(),
+            facilities: Vec::new(),
+            severity: None,
+            pattern: None,
+            title: None,
+            message: None,
+            priority: None,
+            digest: None,
+        }
+    }
+
+    #[test]
+    fn parses_rfc5424_messages() {
+        // The examples from RFC 5424 section 6.5
+        let message = parse_message("<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - \u{feff}'su root' failed for lonvick on /dev/pts/8").unwrap();
+        assert_eq!(message, SyslogMessage {
+            facility: 4,
+            severity: Severity::Critical,
+            hostname: Some("mymachine.example.com".to_string()),
+            app_name: Some("su".to_string()),
+            text: "'su root' failed for lonvick on /dev/pts/8".to_string(),
+        });
+
+        let message = parse_message(r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="App]lication" eventID="1011"][examplePriority@32473 class="high"] An application event log entry..."#).unwrap();
+        assert_eq!(message.facility, 20);
+        assert_eq!(message.severity, Severity::Notice);
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.text, "An application event log entry...");
+    }
+
+    #[test]
+    fn treats_nil_rfc5424_fields_as_missing() {
+        let message = parse_message("<11>1 - - - - - [exampleSDID@32473 iut=\"3\"]").unwrap();
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.text, "");
+        assert!(parse_message("<11>1 2003-10-11T22:14:15.003Z host").is_none());
+    }
+
+    #[test]
+    fn parses_rfc3164_messages() {
+        // The example from RFC 3164 section 5.4
+        let message = parse_message("<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8\n").unwrap();
+        assert_eq!(message, SyslogMessage {
+            facility: 4,
+            severity: Severity::Critical,
+            hostname: Some("mymachine".to_string()),
+            app_name: Some("su".to_string()),
+            text: "'su root' failed for lonvick on /dev/pts/8".to_string(),
+        });
+
+        let message = parse_message("<38>Feb  5 09:01:02 router sshd[1234]: Failed password for root from 10.0.0.5 port 22").unwrap();
+        assert_eq!(message.hostname.as_deref(), Some("router"));
+        assert_eq!(message.app_name.as_deref(), Some("sshd"));
+        assert_eq!(message.text, "Failed password for root from 10.0.0.5 port 22");
+
+        let message = parse_message("<30>2024-03-01T12:00:00+01:00 nas smartd: Device /dev/sda failing").unwrap();
+        assert_eq!(message.hostname.as_deref(), Some("nas"));
+        assert_eq!(message.app_name.as_deref(), Some("smartd"));
+    }
+
+    #[test]
+    fn parses_rfc3164_messages_missing_fields() {
+        let message = parse_message("<27>dnsmasq[77]: no servers found").unwrap();
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name.as_deref(), Some("dnsmasq"));
+        assert_eq!(message.text, "no servers found");
+
+        let message = parse_message("link down on port 3").unwrap();
+        assert_eq!(message.facility, 1);
+        assert_eq!(message.severity, Severity::Notice);
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.text, "link down on port 3");
+    }
+
+    #[test]
+    fn rejects_invalid_priorities() {
+        assert!(parse_message("<192>Oct 11 22:14:15 host app: text").is_none());
+        assert!(parse_message("<1234>text").is_none());
+        assert!(parse_message("<abc>text").is_none());
+    }
+
+    #[test]
+    fn routes_render_captures_and_placeholders() {
+        let route = SyslogRoute::new(&SyslogRule {
+            facilities: vec!["auth".to_string()],
+            severity: Some(Severity::Warning),
+            pattern: Some(r"Failed password for (?P<user>\S+) from (?P<ip>\S+)".to_string()),
+            title: Some("SSH login failed for ${user}".to_string()),
+            message: Some("${ip} on {host} ({facility}.{severity})".to_string()),
+            ..rule("ssh")
+        }).unwrap();
+
+        let message = parse_message("<36>Feb  5 09:01:02 router sshd[1234]: Failed password for root from 10.0.0.5 port 22").unwrap();
+        let request = route.apply(&message, "router").unwrap();
+        assert_eq!(request.title, "SSH login failed for root");
+        assert_eq!(request.message, "10.0.0.5 on router (auth.warning)");
+        assert_eq!(request.sender.as_deref(), Some("router"));
+        assert_eq!(request.priority, Priority::Normal);
+
+        let info = parse_message("<38>Feb  5 09:01:02 router sshd[1234]: Failed password for root from 10.0.0.5 port 22").unwrap();
+        assert!(route.apply(&info, "router").is_none());
+        let daemon = parse_message("<28>Feb  5 09:01:02 router sshd[1234]: Failed password for root from 10.0.0.5 port 22").unwrap();
+        assert!(route.apply(&daemon, "router").is_none());
+    }
+
+    #[test]
+    fn routes_default_to_the_app_and_message_text() {
+        let route = SyslogRoute::new(&rule("all")).unwrap();
+        let message = parse_message("<11>Oct 11 22:14:15 nas kernel: disk error").unwrap();
+        let request = route.apply(&message, "nas").unwrap();
+        assert_eq!(request.title, "kernel on nas");
+        assert_eq!(request.message, "disk error");
+        assert_eq!(request.priority, Priority::High);
+
+        assert!(SyslogRoute::new(&SyslogRule { facilities: vec!["nope".to_string()], ..rule("bad") }).is_err());
+    }
+}
@@ -1,80 +1,624 @@
 use actix_web::{
-    dev::{ServiceRequest, Service, ServiceResponse, Transform},
-    error::ErrorUnauthorized,
+    dev::{Payload, ServiceRequest, Service, ServiceResponse, Transform},
+    error::{ErrorForbidden, ErrorPayloadTooLarge, ErrorUnauthorized, InternalError},
     http::header,
-    Error,
+    web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use anyhow::anyhow;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
 };
 use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::BytesMut;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::future::{ready, Ready, Future};
+use std::net::IpAddr;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+use crate::utils::config::{LockoutConfig, ServerConfig};
+use crate::utils::ip_rules::{IpDecision, IpRules};
+use crate::utils::network::{ClientIp, ClientIpResolver};
+use crate::utils::signature::{SignatureVerifier, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::utils::tls::PeerCertificate;
+
+const TOKEN_HASH_PREFIX: &str = "sha256:";
+const LOCALHOST_IDENTITY: &str = "localhost";
+const ANONYMOUS_IDENTITY: &str = "anonymous";
+/// Endpoints of compatible APIs whose clients send their token in a header or query parameter
+/// instead of `Authorization`. Entries ending in `/` match every path below them.
+const ALTERNATE_TOKEN_PATHS: &[&str] = &["/message", "/integrations/slack/", "/hooks/"];
+const GOTIFY_KEY_HEADER: &str = "x-gotify-key";
+/// Webhook endpoints whose senders cannot send credentials and that verify the webhook's own
+/// signature instead, with the identity they are let through as
+const WEBHOOK_PATHS: &[(&str, &str)] = &[
+    ("/integrations/github", "github"),
+    ("/integrations/gitlab", "gitlab"),
+];
+const TOKEN_QUERY_PARAMETER: &str = "token";
+/// Pages opened by the local browser from a toast button, which cannot send credentials.
+/// Their handlers only serve loopback clients and address pages by an unguessable id.
+const TOAST_LINK_PATHS: &[(&str, &str)] = &[("/digests/", "digest-viewer")];
+
+/// Permissions that can be granted to an API token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Send notifications
+    Notify,
+    /// Attach an image to a notification
+    Images,
+    /// Attach files to a notification
+    Files,
+    /// Set a callback command that runs on click
+    Callbacks,
+    /// Read and write the desktop clipboard
+    Clipboard,
+    /// Everything, including server administration
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [
+        Scope::Notify,
+        Scope::Images,
+        Scope::Files,
+        Scope::Callbacks,
+        Scope::Clipboard,
+        Scope::Admin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Notify => "notify",
+            Scope::Images => "images",
+            Scope::Files => "files",
+            Scope::Callbacks => "callbacks",
+            Scope::Clipboard => "clipboard",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// A named bearer token as configured in the `[[tokens]]` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    /// `sha256:<hex>` digest of the token, as printed by `--generate-token`
+    pub hash: String,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+    /// Shown as attribution on toasts sent with this token
+    #[serde(default)]
+    pub sender: Option<String>,
+    /// Maximum number of requests per minute, enforced by the rate limiter in place of
+    /// `rate_limit.sender_per_minute`. Unset or 0 falls back to that limit.
+    #[serde(default)]
+    pub rate_limit_per_minute: Option<u32>,
+}
+
+/// Maps a verified client certificate to an identity, configured in `[[mtls.clients]]`.
+/// A certificate matches if its common name equals `subject` or any of its SANs equals `san`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientCertMapping {
+    pub name: String,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub san: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+    /// Shown as attribution on toasts sent with this certificate
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+
+impl ClientCertMapping {
+    fn matches(&self, cert: &PeerCertificate) -> bool {
+        let subject_matches = match (&self.subject, &cert.common_name) {
+            (Some(subject), Some(common_name)) => subject == common_name,
+            _ => false,
+        };
+        let san_matches = match &self.san {
+            Some(san) => cert.subject_alt_names.iter().any(|name| name.eq_ignore_ascii_case(san)),
+            None => false,
+        };
+        subject_matches || san_matches
+    }
+}
+
+/// Who a request was authenticated as, available to handlers as an extractor
+#[derive(Debug, Clone)]
+pub struct AuthIdentity {
+    pub name: String,
+    pub sender: Option<String>,
+    scopes: Option<Vec<Scope>>,
+    /// Scopes refused to the client address by scope-specific IP rules, with the rule's reason
+    ip_denied_scopes: Vec<(Scope, String)>,
+}
+
+impl AuthIdentity {
+    /// An identity with every scope, used for localhost, basic auth and unauthenticated servers
+    fn unrestricted(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            sender: None,
+            scopes: None,
+            ip_denied_scopes: Vec::new(),
+        }
+    }
+
+    /// An identity limited to `scopes`, used for tokens, client certificates and signed requests
+    fn restricted(name: &str, sender: Option<String>, scopes: &[Scope]) -> Self {
+        Self {
+            name: name.to_string(),
+            sender,
+            scopes: Some(scopes.to_vec()),
+            ip_denied_scopes: Vec::new(),
+        }
+    }
+
+    /// True for remote requests on a server without any authentication configured
+    pub fn is_anonymous(&self) -> bool {
+        self.name == ANONYMOUS_IDENTITY
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match &self.scopes {
+            None => true,
+            Some(scopes) => scopes.contains(&Scope::Admin) || scopes.contains(&scope),
+        }
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), Error> {
+        if let Some((_, reason)) = self.ip_denied_scopes.iter().find(|(denied, _)| *denied == scope) {
+            log::warn!("Identity {} denied the {} scope by IP rule: {}", self.name, scope.as_str(), reason);
+            return Err(ErrorForbidden(format!("Address is not allowed to use scope: {}", scope.as_str())));
+        }
+
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            log::warn!("Identity {} is missing the {} scope", self.name, scope.as_str());
+            Err(ErrorForbidden(format!("Missing required scope: {}", scope.as_str())))
+        }
+    }
+}
+
+impl FromRequest for AuthIdentity {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthIdentity>()
+                .cloned()
+                .ok_or_else(|| ErrorUnauthorized("Request was not authenticated")),
+        )
+    }
+}
+
+/// Returns the `sha256:<hex>` digest stored in the config file for a token
+pub fn hash_token(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}{}", TOKEN_HASH_PREFIX, hex)
+}
+
+/// Creates a new random token
+pub fn generate_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+/// Hashes a password into an argon2id PHC string suitable for `--password-hash`
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+/// Checks that a password hash is an argon2 PHC string or a bcrypt hash
+pub fn validate_password_hash(hash: &str) -> anyhow::Result<()> {
+    if is_bcrypt_hash(hash) {
+        return Ok(());
+    }
+    PasswordHash::new(hash)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Unsupported password hash, expected argon2 or bcrypt: {}", e))
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+/// Both argon2 and bcrypt verification compare their digests in constant time
+fn verify_password(password: &str, hash: &str) -> bool {
+    if is_bcrypt_hash(hash) {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Compares two strings without leaking where they differ. Both sides are hashed
+/// first so the comparison does not leak the expected length either.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    Sha256::digest(a.as_bytes()).ct_eq(&Sha256::digest(b.as_bytes())).into()
+}
+
+#[derive(Debug, Default)]
+struct FailureState {
+    failures: u32,
+    last_failure: Option<Instant>,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+}
 
 #[derive(Clone, Debug)]
 pub struct AuthConfig {
     pub username: Option<String>,
-    pub password: Option<String>,
+    password_hash: Option<String>,
+    pub tokens: Vec<ApiToken>,
+    pub client_certs: Vec<ClientCertMapping>,
+    lockout: LockoutConfig,
+    client_ip_resolver: ClientIpResolver,
+    localhost_bypass: bool,
+    ip_rules: IpRules,
+    signatures: SignatureVerifier,
+    max_signed_body_bytes: usize,
+    failures: Arc<Mutex<HashMap<IpAddr, FailureState>>>,
 }
 
 impl AuthConfig {
-    pub fn new(username: Option<String>, password: Option<String>) -> Self {
-        Self { username, password }
+    /// `password_hash` must be an argon2 or bcrypt hash, see [`hash_password`]
+    pub fn new(username: Option<String>, password_hash: Option<String>, config: &ServerConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            username,
+            password_hash,
+            tokens: config.tokens.clone(),
+            client_certs: config.mtls.clients.clone(),
+            lockout: config.lockout.clone(),
+            client_ip_resolver: ClientIpResolver::new(&config.network.trusted_proxies)?,
+            localhost_bypass: config.network.localhost_bypass,
+            ip_rules: IpRules::new(&config.ip_rules)?,
+            signatures: SignatureVerifier::new(&config.hmac.senders, config.hmac.replay_window_secs)?,
+            max_signed_body_bytes: config.hmac.max_body_bytes,
+            failures: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn is_basic_auth_enabled(&self) -> bool {
+        self.username.is_some() && self.password_hash.is_some()
     }
 
     pub fn is_auth_required(&self) -> bool {
-        self.username.is_some() && self.password.is_some()
+        self.is_basic_auth_enabled()
+            || !self.tokens.is_empty()
+            || !self.client_certs.is_empty()
+            || self.signatures.is_enabled()
+    }
+
+    pub fn is_signed_request(&self, req: &ServiceRequest) -> bool {
+        self.signatures.is_enabled() && req.headers().contains_key(SIGNATURE_HEADER)
+    }
+
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        self.client_ip_resolver.resolve_request(req)
+    }
+
+    pub async fn validate_auth_header(&self, req: &ServiceRequest, ip: Option<IpAddr>) -> Result<AuthIdentity, Error> {
+        // Address rules are checked before any credentials are looked at
+        let ip_denied_scopes = self.check_ip_rules(ip)?;
+
+        let mut identity = self.authenticate(req, ip).await?;
+        identity.ip_denied_scopes = ip_denied_scopes;
+        Ok(identity)
+    }
+
+    /// Authenticates a request by its HMAC signature. The body is buffered for verification
+    /// and then put back so handlers can still read it.
+    pub async fn validate_signed_request(&self, req: &mut ServiceRequest, ip: Option<IpAddr>) -> Result<AuthIdentity, Error> {
+        let ip_denied_scopes = self.check_ip_rules(ip)?;
+        if let Some(ip) = ip {
+            self.check_lockout(ip)?;
+        }
+
+        let header_value = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let signature = header_value(SIGNATURE_HEADER)
+            .ok_or_else(|| ErrorUnauthorized("Invalid signature header"))?;
+        let timestamp = header_value(TIMESTAMP_HEADER)
+            .ok_or_else(|| ErrorUnauthorized("Missing signature timestamp header"))?;
+        if let Err(e) = self.signatures.check_headers(&signature, &timestamp) {
+            log::warn!("Rejected signed request: {}", e);
+            if let Some(ip) = ip {
+                self.record_failure(ip);
+            }
+            return Err(ErrorUnauthorized("Invalid signature"));
+        }
+
+        let mut payload = req.take_payload();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk?;
+            if body.len() + chunk.len() > self.max_signed_body_bytes {
+                return Err(ErrorPayloadTooLarge("Signed request body is too large"));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let body = body.freeze();
+        req.set_payload(Payload::from(body.clone()));
+
+        match self.signatures.verify(&signature, &timestamp, &body) {
+            Ok(sender) => {
+                if let Some(ip) = ip {
+                    self.failures.lock().unwrap().remove(&ip);
+                }
+                log::debug!("Authenticated signed request from {}", sender.name);
+                let mut identity = AuthIdentity::restricted(&sender.name, sender.sender.clone(), &sender.scopes);
+                identity.ip_denied_scopes = ip_denied_scopes;
+                Ok(identity)
+            }
+            Err(e) => {
+                log::warn!("Rejected signed request: {}", e);
+                if let Some(ip) = ip {
+                    self.record_failure(ip);
+                }
+                Err(ErrorUnauthorized("Invalid signature"))
+            }
+        }
+    }
+
+    /// Requests whose address cannot be determined are denied wherever an allow rule applies
+    fn check_ip_rules(&self, ip: Option<IpAddr>) -> Result<Vec<(Scope, String)>, Error> {
+        if self.ip_rules.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        match self.ip_rules.evaluate(ip, None) {
+            IpDecision::Allowed => Ok(self.ip_rules.denied_scopes(ip)),
+            IpDecision::Denied(reason) => {
+                let address = ip.map_or_else(|| "an unknown address".to_string(), |ip| ip.to_string());
+                log::warn!("Denied request from {} by IP rule: {}", address, reason);
+                Err(ErrorForbidden("Address is not allowed"))
+            }
+        }
     }
 
-    pub fn validate_auth_header(&self, req: &ServiceRequest) -> Result<bool, Error> {
+    async fn authenticate(&self, req: &ServiceRequest, ip: Option<IpAddr>) -> Result<AuthIdentity, Error> {
+        let is_localhost = ip.is_some_and(|ip| ip.is_loopback());
+
         // Allow requests from localhost without authentication
-        if is_localhost(req) {
-            return Ok(true);
+        if is_localhost && self.localhost_bypass {
+            return Ok(AuthIdentity::unrestricted(LOCALHOST_IDENTITY));
+        }
+
+        // Clients with a mapped certificate need no further credentials
+        if let Some(identity) = req.conn_data::<PeerCertificate>().and_then(|cert| self.identity_for_certificate(cert)) {
+            return Ok(identity);
         }
 
         // If no auth is configured, allow all requests
         if !self.is_auth_required() {
-            return Ok(true);
+            let name = if is_localhost { LOCALHOST_IDENTITY } else { ANONYMOUS_IDENTITY };
+            return Ok(AuthIdentity::unrestricted(name));
+        }
+
+        if let Some(ip) = ip {
+            self.check_lockout(ip)?;
+        }
+
+        // Webhooks without credentials get an identity without scopes and are verified by their handler
+        if !req.headers().contains_key(header::AUTHORIZATION) {
+            if let Some((_, name)) = WEBHOOK_PATHS.iter().find(|(path, _)| *path == req.path()) {
+                return Ok(AuthIdentity::restricted(name, None, &[]));
+            }
+            if let Some((_, name)) = TOAST_LINK_PATHS.iter().find(|(prefix, _)| req.path().starts_with(prefix)) {
+                return Ok(AuthIdentity::restricted(name, None, &[]));
+            }
+        }
+
+        match self.validate_credentials(req).await {
+            Ok(identity) => {
+                if let Some(ip) = ip {
+                    self.failures.lock().unwrap().remove(&ip);
+                }
+                Ok(identity)
+            }
+            Err(AuthFailure::Uncounted(e)) => Err(e),
+            Err(AuthFailure::Rejected(e)) => {
+                if let Some(ip) = ip {
+                    self.record_failure(ip);
+                }
+                Err(e)
+            }
         }
+    }
 
+    async fn validate_credentials(&self, req: &ServiceRequest) -> Result<AuthIdentity, AuthFailure> {
         let auth_header = match req.headers().get(header::AUTHORIZATION) {
             Some(header) => header,
-            None => return Err(ErrorUnauthorized("Missing authorization header")),
+            None => match alternate_token(req) {
+                Some(token) => return self.validate_token(token.trim()),
+                None => return Err(AuthFailure::Uncounted(ErrorUnauthorized("Missing authorization header"))),
+            },
         };
 
         let auth_str = match auth_header.to_str() {
             Ok(str) => str,
-            Err(_) => return Err(ErrorUnauthorized("Invalid authorization header")),
+            Err(_) => return Err(AuthFailure::rejected("Invalid authorization header")),
         };
 
-        if !auth_str.starts_with("Basic ") {
-            return Err(ErrorUnauthorized("Invalid authorization type"));
+        if let Some(token) = auth_str.strip_prefix("Bearer ") {
+            return self.validate_token(token.trim());
+        }
+
+        if !auth_str.starts_with("Basic ") || !self.is_basic_auth_enabled() {
+            return Err(AuthFailure::rejected("Invalid authorization type"));
         }
 
         let credentials = match STANDARD.decode(&auth_str[6..]) {
             Ok(decoded) => match String::from_utf8(decoded) {
                 Ok(str) => str,
-                Err(_) => return Err(ErrorUnauthorized("Invalid authorization header")),
+                Err(_) => return Err(AuthFailure::rejected("Invalid authorization header")),
             },
-            Err(_) => return Err(ErrorUnauthorized("Invalid authorization header")),
+            Err(_) => return Err(AuthFailure::rejected("Invalid authorization header")),
         };
 
         let parts: Vec<&str> = credentials.splitn(2, ':').collect();
         if parts.len() != 2 {
-            return Err(ErrorUnauthorized("Invalid credentials format"));
+            return Err(AuthFailure::rejected("Invalid credentials format"));
         }
 
-        if parts[0] == self.username.as_ref().unwrap() && parts[1] == self.password.as_ref().unwrap() {
-            Ok(true)
+        // Always verify the password so a wrong username takes as long as a wrong password.
+        // Hashing is deliberately slow, so it runs on the blocking pool instead of a worker thread.
+        let username_matches = constant_time_eq(parts[0], self.username.as_ref().unwrap());
+        let password = parts[1].to_string();
+        let hash = self.password_hash.clone().unwrap();
+        let password_matches = web::block(move || verify_password(&password, &hash)).await.unwrap_or(false);
+
+        if username_matches && password_matches {
+            Ok(AuthIdentity::unrestricted(parts[0]))
         } else {
-            Err(ErrorUnauthorized("Invalid credentials"))
+            Err(AuthFailure::rejected("Invalid credentials"))
         }
     }
+
+    fn identity_for_certificate(&self, cert: &PeerCertificate) -> Option<AuthIdentity> {
+        match self.client_certs.iter().find(|mapping| mapping.matches(cert)) {
+            Some(mapping) => {
+                log::debug!("Authenticated request with client certificate {}", mapping.name);
+                Some(AuthIdentity::restricted(&mapping.name, mapping.sender.clone(), &mapping.scopes))
+            }
+            None => {
+                log::warn!("Client certificate {:?} is not mapped to an identity", cert.common_name);
+                None
+            }
+        }
+    }
+
+    fn validate_token(&self, token: &str) -> Result<AuthIdentity, AuthFailure> {
+        let hash = hash_token(token);
+
+        // Compare against every token so the time taken does not reveal which one matched
+        let mut matched = None;
+        for api_token in &self.tokens {
+            if constant_time_eq(&api_token.hash.to_ascii_lowercase(), &hash) && matched.is_none() {
+                matched = Some(api_token);
+            }
+        }
+        let api_token = match matched {
+            Some(api_token) => api_token,
+            None => return Err(AuthFailure::rejected("Invalid token")),
+        };
+
+        log::debug!("Authenticated request with token {}", api_token.name);
+        Ok(AuthIdentity::restricted(&api_token.name, api_token.sender.clone(), &api_token.scopes))
+    }
+
+    fn check_lockout(&self, ip: IpAddr) -> Result<(), Error> {
+        let failures = self.failures.lock().unwrap();
+        let locked_until = match failures.get(&ip).and_then(|state| state.locked_until) {
+            Some(locked_until) => locked_until,
+            None => return Ok(()),
+        };
+
+        let now = Instant::now();
+        if locked_until <= now {
+            return Ok(());
+        }
+
+        let retry_after = (locked_until - now).as_secs().max(1);
+        log::warn!("Rejecting request from locked out address {} for another {}s", ip, retry_after);
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .body("Too many failed login attempts");
+        Err(InternalError::from_response("Too many failed login attempts", response).into())
+    }
+
+    /// Counts a failed attempt and locks the address out once `max_failures` is reached.
+    /// Each consecutive lockout doubles in length up to `max_secs`.
+    fn record_failure(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.lockout.window_secs);
+
+        // Forget addresses that are neither locked out nor failed recently, so the map stays bounded.
+        // Their backoff is kept for `max_secs` so a slow attacker does not reset it.
+        let retention = window.max(Duration::from_secs(self.lockout.max_secs));
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, state| {
+            let locked = state.locked_until.is_some_and(|until| until > now);
+            let recent = state.last_failure.is_some_and(|last| now.duration_since(last) <= retention)
+                || state.locked_until.is_some_and(|until| now.duration_since(until) <= retention);
+            locked || recent
+        });
+        let state = failures.entry(ip).or_default();
+        if state.last_failure.is_some_and(|last| now.duration_since(last) > window) {
+            state.failures = 0;
+        }
+        state.failures += 1;
+        state.last_failure = Some(now);
+
+        log::warn!("Failed authentication attempt {} from {}", state.failures, ip);
+        if self.lockout.max_failures == 0 || state.failures < self.lockout.max_failures {
+            return;
+        }
+
+        let multiplier = 1u64.checked_shl(state.lockouts).unwrap_or(u64::MAX);
+        let lockout_secs = self.lockout.base_secs.saturating_mul(multiplier).min(self.lockout.max_secs);
+        state.lockouts = state.lockouts.saturating_add(1);
+        state.failures = 0;
+        state.locked_until = Some(now + Duration::from_secs(lockout_secs));
+        log::warn!("Locking out {} for {}s after repeated failures", ip, lockout_secs);
+    }
+}
+
+/// A token from `X-Gotify-Key` or `?token=`, accepted only on `ALTERNATE_TOKEN_PATHS`
+fn alternate_token(req: &ServiceRequest) -> Option<String> {
+    let path = req.path();
+    let accepted = ALTERNATE_TOKEN_PATHS.iter()
+        .any(|accepted| path == *accepted || (accepted.ends_with('/') && path.starts_with(accepted)));
+    if !accepted {
+        return None;
+    }
+    if let Some(token) = req.headers().get(GOTIFY_KEY_HEADER).and_then(|value| value.to_str().ok()) {
+        return Some(token.to_string());
+    }
+    serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+        .ok()?
+        .into_iter()
+        .find(|(name, _)| name == TOKEN_QUERY_PARAMETER)
+        .map(|(_, token)| token)
+}
+
+/// Why a request failed authentication. Only rejected credentials count towards a lockout;
+/// a missing header is what clients send before they are challenged.
+enum AuthFailure {
+    Uncounted(Error),
+    Rejected(Error),
 }
 
-fn is_localhost(req: &ServiceRequest) -> bool {
-    if let Some(addr) = req.connection_info().realip_remote_addr() {
-        return addr.starts_with("127.0.0.1") || addr.starts_with("::1") || addr.starts_with("localhost");
+impl AuthFailure {
+    fn rejected(message: &'static str) -> Self {
+        AuthFailure::Rejected(ErrorUnauthorized(message))
     }
-    false
 }
 
 pub struct AuthMiddleware {
@@ -89,7 +633,7 @@ impl AuthMiddleware {
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -101,20 +645,20 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareService {
-            service,
-            auth_config: self.auth_config.clone(),
+            service: Rc::new(service),
+            auth_config: Rc::new(self.auth_config.clone()),
         }))
     }
 }
 
 pub struct AuthMiddlewareService<S> {
-    service: S,
-    auth_config: AuthConfig,
+    service: Rc<S>,
+    auth_config: Rc<AuthConfig>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -126,13 +670,166 @@ where
         self.service.poll_ready(cx)
     }
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        let auth_result = self.auth_config.validate_auth_header(&req);
-        let fut = self.service.call(req);
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let ip = self.auth_config.client_ip(req.request());
+        if let Some(ip) = ip {
+            req.extensions_mut().insert(ClientIp(ip));
+        }
 
+        let auth_config = Rc::clone(&self.auth_config);
+        let service = Rc::clone(&self.service);
         Box::pin(async move {
-            auth_result?;
-            fut.await
+            // Signed requests need the whole body before they can be authenticated
+            let identity = if auth_config.is_signed_request(&req) {
+                auth_config.validate_signed_request(&mut req, ip).await?
+            } else {
+                auth_config.validate_auth_header(&req, ip).await?
+            };
+            req.extensions_mut().insert(identity);
+            service.call(req).await
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const REMOTE: &str = "192.0.2.10";
+
+    fn config_with_password(password: &str) -> AuthConfig {
+        let config = ServerConfig {
+            lockout: LockoutConfig { max_failures: 3, window_secs: 300, base_secs: 30, max_secs: 900 },
+            ..Default::default()
+        };
+        // Lowest bcrypt cost keeps the tests fast
+        let hash = bcrypt::hash(password, 4).unwrap();
+        AuthConfig::new(Some("admin".to_string()), Some(hash), &config).unwrap()
+    }
+
+    fn basic_request(username: &str, password: &str) -> ServiceRequest {
+        let credentials = STANDARD.encode(format!("{}:{}", username, password));
+        TestRequest::default()
+            .insert_header((header::AUTHORIZATION, format!("Basic {}", credentials)))
+            .to_srv_request()
+    }
+
+    fn remote_ip() -> IpAddr {
+        REMOTE.parse().unwrap()
+    }
+
+    fn lockout_remaining(auth: &AuthConfig) -> Duration {
+        let failures = auth.failures.lock().unwrap();
+        let locked_until = failures[&remote_ip()].locked_until.unwrap();
+        locked_until.saturating_duration_since(Instant::now())
+    }
+
+    #[test]
+    fn verify_password_accepts_only_the_right_password() {
+        let argon2_hash = hash_password("hunter2").unwrap();
+        assert!(verify_password("hunter2", &argon2_hash));
+        assert!(!verify_password("hunter3", &argon2_hash));
+
+        let bcrypt_hash = bcrypt::hash("hunter2", 4).unwrap();
+        assert!(verify_password("hunter2", &bcrypt_hash));
+        assert!(!verify_password("hunter3", &bcrypt_hash));
+    }
+
+    #[test]
+    fn verify_password_rejects_malformed_hashes() {
+        assert!(!verify_password("hunter2", "not a hash"));
+        assert!(!verify_password("hunter2", "$argon2id$v=19$broken"));
+        assert!(!verify_password("hunter2", "$2b$04$truncated"));
+        assert!(validate_password_hash("not a hash").is_err());
+    }
+
+    #[actix_web::test]
+    async fn basic_auth_checks_username_and_password() {
+        let auth = config_with_password("hunter2");
+
+        assert!(auth.validate_credentials(&basic_request("admin", "hunter2")).await.is_ok());
+        assert!(matches!(
+            auth.validate_credentials(&basic_request("admin", "wrong")).await,
+            Err(AuthFailure::Rejected(_))
+        ));
+        assert!(matches!(
+            auth.validate_credentials(&basic_request("eve", "hunter2")).await,
+            Err(AuthFailure::Rejected(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn locks_out_after_max_failures() {
+        let auth = config_with_password("hunter2");
+        let ip = remote_ip();
+
+        for _ in 0..2 {
+            assert!(auth.authenticate(&basic_request("admin", "wrong"), Some(ip)).await.is_err());
+            assert!(auth.check_lockout(ip).is_ok());
+        }
+        assert!(auth.authenticate(&basic_request("admin", "wrong"), Some(ip)).await.is_err());
+
+        let error = auth.check_lockout(ip).unwrap_err();
+        assert_eq!(error.error_response().status(), actix_web::http::StatusCode::TOO_MANY_REQUESTS);
+        // Even the right password is refused while locked out
+        assert!(auth.authenticate(&basic_request("admin", "hunter2"), Some(ip)).await.is_err());
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let auth = config_with_password("hunter2");
+        let ip = remote_ip();
+
+        let mut expected = Vec::new();
+        for _ in 0..7 {
+            for _ in 0..3 {
+                auth.record_failure(ip);
+            }
+            expected.push(lockout_remaining(&auth).as_secs_f64().round() as u64);
+            // Let the lockout expire without forgetting how many there were
+            auth.failures.lock().unwrap().get_mut(&ip).unwrap().locked_until = Some(Instant::now());
+        }
+
+        assert_eq!(expected, vec![30, 60, 120, 240, 480, 900, 900]);
+    }
+
+    #[actix_web::test]
+    async fn successful_login_resets_failures() {
+        let auth = config_with_password("hunter2");
+        let ip = remote_ip();
+
+        for _ in 0..2 {
+            auth.record_failure(ip);
+        }
+        assert!(auth.authenticate(&basic_request("admin", "hunter2"), Some(ip)).await.is_ok());
+        assert!(!auth.failures.lock().unwrap().contains_key(&ip));
+
+        // The count starts over, so two more failures do not lock the address out
+        for _ in 0..2 {
+            auth.record_failure(ip);
+        }
+        assert!(auth.check_lockout(ip).is_ok());
+    }
+
+    #[test]
+    fn stale_failures_are_pruned() {
+        let mut auth = config_with_password("hunter2");
+        auth.lockout.window_secs = 1;
+        auth.lockout.max_secs = 1;
+        let stale: IpAddr = "192.0.2.99".parse().unwrap();
+        let long_ago = Instant::now() - Duration::from_secs(5);
+        auth.failures.lock().unwrap().insert(stale, FailureState {
+            failures: 1,
+            last_failure: Some(long_ago),
+            lockouts: 1,
+            locked_until: Some(long_ago),
+        });
+
+        auth.record_failure(remote_ip());
+
+        let failures = auth.failures.lock().unwrap();
+        assert!(!failures.contains_key(&stale));
+        assert!(failures.contains_key(&remote_ip()));
+    }
+}
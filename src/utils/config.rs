@@ -0,0 +1,567 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::notifications::{DefaultAction, Priority};
+use crate::services::hooks::HookConfig;
+use crate::services::mqtt::MqttSubscription;
+use crate::services::quiet_hours::{QuietMode, QuietWindowConfig};
+use crate::services::relay::RelayTarget;
+use crate::services::smtp::SmtpRecipient;
+use crate::services::syslog::SyslogRule;
+use crate::utils::auth::{ApiToken, ClientCertMapping};
+use crate::utils::ip_rules::IpRuleConfig;
+use crate::utils::signature::HmacSender;
+
+/// Server-wide settings loaded from the optional `--config` TOML file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Actions run on click when a request does not specify its own `default_action`
+    pub default_action: Vec<DefaultAction>,
+    pub clipboard: ClipboardConfig,
+    /// Bearer tokens accepted in addition to basic authentication
+    pub tokens: Vec<ApiToken>,
+    pub lockout: LockoutConfig,
+    pub network: NetworkConfig,
+    /// Allow / deny rules by client address, evaluated in order before credentials
+    pub ip_rules: Vec<IpRuleConfig>,
+    pub mtls: MtlsConfig,
+    pub hmac: HmacConfig,
+    pub rate_limit: RateLimitConfig,
+    pub dedup: DedupConfig,
+    pub quiet_hours: QuietHoursConfig,
+    pub digest: DigestConfig,
+    pub ntfy: NtfyConfig,
+    pub alertmanager: AlertmanagerConfig,
+    pub github: ForgeWebhookConfig,
+    pub gitlab: ForgeWebhookConfig,
+    pub slack: SlackConfig,
+    /// Generic webhook routes served at `/hooks/{name}`
+    pub hooks: Vec<HookConfig>,
+    pub mqtt: MqttConfig,
+    pub smtp: SmtpConfig,
+    pub syslog: SyslogConfig,
+    pub relay: RelayConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            default_action: vec![DefaultAction::CopyText, DefaultAction::OpenFolder],
+            clipboard: ClipboardConfig::default(),
+            tokens: Vec::new(),
+            lockout: LockoutConfig::default(),
+            network: NetworkConfig::default(),
+            ip_rules: Vec::new(),
+            mtls: MtlsConfig::default(),
+            hmac: HmacConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            dedup: DedupConfig::default(),
+            quiet_hours: QuietHoursConfig::default(),
+            digest: DigestConfig::default(),
+            ntfy: NtfyConfig::default(),
+            alertmanager: AlertmanagerConfig::default(),
+            github: ForgeWebhookConfig::default(),
+            gitlab: ForgeWebhookConfig::default(),
+            slack: SlackConfig::default(),
+            hooks: Vec::new(),
+            mqtt: MqttConfig::default(),
+            smtp: SmtpConfig::default(),
+            syslog: SyslogConfig::default(),
+            relay: RelayConfig::default(),
+        }
+    }
+}
+
+/// Settings for the remote `/clipboard` endpoints
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// Allow `POST /clipboard` to replace the desktop clipboard, off by default
+    pub allow_write: bool,
+    /// Allow `GET /clipboard` to read the desktop clipboard, off by default
+    pub allow_read: bool,
+    /// Ask for confirmation with a toast before a remote read is answered
+    pub confirm_read: bool,
+    /// How long to wait for the confirmation toast before denying the read
+    pub confirm_timeout_secs: u64,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            allow_write: false,
+            allow_read: false,
+            confirm_read: false,
+            confirm_timeout_secs: 30,
+        }
+    }
+}
+
+/// Per-address backoff after failed authentication attempts
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LockoutConfig {
+    /// Failures within `window_secs` before an address is locked out, 0 disables lockouts
+    pub max_failures: u32,
+    pub window_secs: u64,
+    /// Length of the first lockout, doubled for every following one
+    pub base_secs: u64,
+    pub max_secs: u64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            window_secs: 300,
+            base_secs: 30,
+            max_secs: 900,
+        }
+    }
+}
+
+/// How client addresses are determined
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Proxies (addresses or CIDRs) whose `X-Forwarded-For` header is trusted
+    pub trusted_proxies: Vec<String>,
+    /// Let loopback clients skip authentication
+    pub localhost_bypass: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            trusted_proxies: Vec::new(),
+            localhost_bypass: true,
+        }
+    }
+}
+
+/// Client certificate authentication, only used when the server runs with TLS
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MtlsConfig {
+    /// PEM bundle of the CAs that client certificates must chain to
+    pub ca_bundle: Option<PathBuf>,
+    pub clients: Vec<ClientCertMapping>,
+}
+
+/// Webhook-style senders that sign request bodies with a shared secret
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HmacConfig {
+    /// How far the signature timestamp may be from the server clock
+    pub replay_window_secs: u64,
+    /// Largest body that is buffered for signature verification
+    pub max_body_bytes: usize,
+    pub senders: Vec<HmacSender>,
+}
+
+impl Default for HmacConfig {
+    fn default() -> Self {
+        Self {
+            replay_window_secs: 300,
+            max_body_bytes: 1024 * 1024,
+            senders: Vec::new(),
+        }
+    }
+}
+
+/// Token-bucket limits on `POST /notify`. A `*_per_minute` of 0 disables that limit,
+/// which is the default for all of them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Sustained rate per authenticated sender (token, certificate, user or localhost)
+    pub sender_per_minute: u32,
+    /// Requests a sender may make at once before the sustained rate applies
+    pub sender_burst: u32,
+    pub ip_per_minute: u32,
+    pub ip_burst: u32,
+    /// Cap across all senders
+    pub global_per_minute: u32,
+    pub global_burst: u32,
+    /// Show one "N more notifications suppressed" toast after a sender was limited
+    pub summarize_suppressed: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            sender_per_minute: 0,
+            sender_burst: 10,
+            ip_per_minute: 0,
+            ip_burst: 20,
+            global_per_minute: 0,
+            global_burst: 30,
+            summarize_suppressed: true,
+        }
+    }
+}
+
+/// Coalescing of repeated notifications into one toast with a counter
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DedupConfig {
+    /// How long after the last repeat a notification still counts as a duplicate, 0 disables
+    pub window_secs: u64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self { window_secs: 60 }
+    }
+}
+
+/// Scheduled quiet hours. `POST /dnd` switches the same behaviour on manually.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QuietHoursConfig {
+    /// What happens to notifications below `urgent` priority while quiet
+    pub mode: QuietMode,
+    /// Most notifications kept for the digest in `queue` mode, later ones are only counted
+    pub max_queued: usize,
+    pub windows: Vec<QuietWindowConfig>,
+}
+
+impl Default for QuietHoursConfig {
+    fn default() -> Self {
+        Self {
+            mode: QuietMode::Queue,
+            max_queued: 500,
+            windows: Vec::new(),
+        }
+    }
+}
+
+/// Batching of notifications sent with `digest=<name>`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DigestConfig {
+    /// How long a batch collects before its summary is shown
+    pub interval_secs: u64,
+    /// Show the summary early once a batch has this many notifications
+    pub max_items: usize,
+    /// Titles listed in the summary toast
+    pub top_items: usize,
+    /// Most notifications kept across all pending digests, later ones are only counted
+    pub max_queued: usize,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 3600,
+            max_items: 50,
+            top_items: 5,
+            max_queued: 500,
+        }
+    }
+}
+
+/// Topics of the ntfy-compatible `/{topic}` endpoint
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NtfyConfig {
+    /// Accept topics without a `[[ntfy.topics]]` entry, using the defaults
+    pub allow_unlisted_topics: bool,
+    /// Largest body accepted by `/{topic}`, which includes attachments
+    pub max_body_bytes: usize,
+    pub topics: Vec<NtfyTopicConfig>,
+}
+
+impl Default for NtfyConfig {
+    fn default() -> Self {
+        Self {
+            allow_unlisted_topics: true,
+            max_body_bytes: 15 * 1024 * 1024,
+            topics: Vec::new(),
+        }
+    }
+}
+
+/// How messages published to one ntfy topic are shown
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfyTopicConfig {
+    pub name: String,
+    /// Title for messages that do not set one, instead of the topic name
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Priority for messages that do not set one
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    /// Collect the topic's messages into this digest
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// Accept messages without showing them
+    #[serde(default)]
+    pub mute: bool,
+}
+
+/// How Alertmanager webhooks are shown
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertmanagerConfig {
+    /// Label whose value selects the priority of a firing group
+    pub severity_label: String,
+    /// Priority per severity, lowercase. Unknown severities are `normal`.
+    pub severities: HashMap<String, Priority>,
+    pub resolved_priority: Priority,
+}
+
+impl Default for AlertmanagerConfig {
+    fn default() -> Self {
+        let severities = [
+            ("critical", Priority::Urgent),
+            ("page", Priority::Urgent),
+            ("error", Priority::High),
+            ("high", Priority::High),
+            ("warning", Priority::Normal),
+            ("info", Priority::Low),
+            ("none", Priority::Min),
+        ];
+        Self {
+            severity_label: "severity".to_string(),
+            severities: severities.iter().map(|(name, priority)| (name.to_string(), *priority)).collect(),
+            resolved_priority: Priority::Normal,
+        }
+    }
+}
+
+/// A GitHub or GitLab webhook receiver
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ForgeWebhookConfig {
+    /// GitHub's webhook secret or GitLab's secret token. Prefer `secret_env` to keep it out of the config file.
+    pub secret: Option<String>,
+    /// Name of an environment variable holding the secret
+    pub secret_env: Option<String>,
+    /// Events shown as toasts, `*` for all of them. Empty shows the receiver's default events.
+    pub events: Vec<String>,
+    /// Also show successful CI runs, not just failed ones
+    pub ci_success: bool,
+    /// Username whose mentions and review requests are shown with high priority
+    pub mention: Option<String>,
+}
+
+impl ForgeWebhookConfig {
+    pub fn secret(&self) -> Result<Option<String>> {
+        match (&self.secret, &self.secret_env) {
+            (_, Some(env)) => std::env::var(env)
+                .map(Some)
+                .map_err(|_| anyhow!("Environment variable {} for the webhook secret is not set", env)),
+            (secret, None) => Ok(secret.clone()),
+        }
+    }
+}
+
+/// The Slack-compatible `/integrations/slack/{channel}` endpoint
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SlackConfig {
+    /// Download a message's first image to show it in the toast, instead of an "Open image" button
+    pub download_images: bool,
+    pub max_image_bytes: usize,
+}
+
+impl Default for SlackConfig {
+    fn default() -> Self {
+        Self {
+            download_images: false,
+            max_image_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+/// The optional MQTT input, connected when `host` is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub host: Option<String>,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    /// Prefer `password_env` to keep the password out of the config file
+    pub password: Option<String>,
+    /// Name of an environment variable holding the password
+    pub password_env: Option<String>,
+    pub keep_alive_secs: u64,
+    /// Topic a JSON event is published to when a notification from MQTT is clicked
+    pub reply_topic: Option<String>,
+    pub subscriptions: Vec<MqttSubscription>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: 1883,
+            client_id: "notification-server".to_string(),
+            username: None,
+            password: None,
+            password_env: None,
+            keep_alive_secs: 30,
+            reply_topic: None,
+            subscriptions: Vec::new(),
+        }
+    }
+}
+
+/// The optional SMTP receiver, listening when `listen` is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SmtpConfig {
+    /// Address such as `0.0.0.0:2525`
+    pub listen: Option<String>,
+    /// Name announced in the greeting and `EHLO` reply
+    pub hostname: String,
+    /// Largest accepted message, including attachments
+    pub max_message_bytes: usize,
+    /// Addresses or CIDRs allowed to connect, only this machine by default.
+    /// Any address may connect when empty.
+    pub allowed_networks: Vec<String>,
+    /// Store attachments and show them in the toast
+    pub save_attachments: bool,
+    /// Limits per recipient (`sender_*`), per sending address (`ip_*`) and overall (`global_*`)
+    pub rate_limit: RateLimitConfig,
+    pub recipients: Vec<SmtpRecipient>,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            hostname: "notification-server".to_string(),
+            max_message_bytes: 10 * 1024 * 1024,
+            allowed_networks: vec!["127.0.0.1".to_string(), "::1".to_string()],
+            save_attachments: false,
+            rate_limit: RateLimitConfig {
+                sender_per_minute: 10,
+                sender_burst: 5,
+                ip_per_minute: 30,
+                ip_burst: 10,
+                global_per_minute: 60,
+                global_burst: 20,
+                summarize_suppressed: true,
+            },
+            recipients: Vec::new(),
+        }
+    }
+}
+
+/// The optional syslog listeners, started for each of `udp` and `tcp` that is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SyslogConfig {
+    /// Address such as `0.0.0.0:514`
+    pub udp: Option<String>,
+    /// Address such as `0.0.0.0:601`, for newline or octet-counted framing
+    pub tcp: Option<String>,
+    /// Longer messages are dropped
+    pub max_message_bytes: usize,
+    /// Addresses or CIDRs allowed to send, only this machine by default.
+    /// Any address may send when empty.
+    pub allowed_networks: Vec<String>,
+    /// Limits per rule (`sender_*`), per sending address (`ip_*`) and overall (`global_*`)
+    pub rate_limit: RateLimitConfig,
+    /// Checked in order, the first matching rule decides how a message is shown.
+    /// Messages matching no rule are ignored.
+    pub rules: Vec<SyslogRule>,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            udp: None,
+            tcp: None,
+            max_message_bytes: 8192,
+            allowed_networks: vec!["127.0.0.1".to_string(), "::1".to_string()],
+            rate_limit: RateLimitConfig {
+                sender_per_minute: 10,
+                sender_burst: 5,
+                ip_per_minute: 30,
+                ip_burst: 10,
+                global_per_minute: 60,
+                global_burst: 20,
+                summarize_suppressed: true,
+            },
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Forwarding of every notification to downstream servers, and reporting clicks back to a hub
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    pub targets: Vec<RelayTarget>,
+    /// Notifications waiting per target; new ones are dropped while it is full
+    pub queue_size: usize,
+    /// Attempts per notification and target before it is given up
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub retry_delay_secs: u64,
+    pub timeout_secs: u64,
+    /// Hub whose forwarded notifications report their clicks back to it, such as `https://hub:3000`
+    pub upstream: Option<String>,
+    /// Prefer `upstream_token_env` to keep the token out of the config file
+    pub upstream_token: Option<String>,
+    pub upstream_token_env: Option<String>,
+    /// Names of the tokens or client certificates hubs forward with. Only requests from these
+    /// identities may set the `sender` and `relay_id` of a notification.
+    pub hubs: Vec<String>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            queue_size: 100,
+            max_attempts: 5,
+            retry_delay_secs: 5,
+            timeout_secs: 30,
+            upstream: None,
+            upstream_token: None,
+            upstream_token_env: None,
+            hubs: Vec::new(),
+        }
+    }
+}
+
+impl RelayConfig {
+    pub fn is_hub(&self, identity: &str) -> bool {
+        self.hubs.iter().any(|hub| hub == identity)
+    }
+
+    pub fn upstream_token(&self) -> Result<Option<String>> {
+        match (&self.upstream_token, &self.upstream_token_env) {
+            (_, Some(env)) => std::env::var(env)
+                .map(Some)
+                .map_err(|_| anyhow!("Environment variable {} for the upstream token is not set", env)),
+            (token, None) => Ok(token.clone()),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: ServerConfig = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+
+        log::info!("Loaded configuration from {}", path.display());
+        Ok(config)
+    }
+}
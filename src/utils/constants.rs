@@ -1,2 +1,4 @@
 pub const APP_ID: &str = "TyroneCheung.NotificationServer";
 pub const APP_DISPLAY_NAME: &str = "Notification Server";
+/// Directory under the system temp dir where received images and attachments are stored
+pub const NOTIFICATION_ASSETS_DIR: &str = "notification_server_assets";
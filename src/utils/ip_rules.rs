@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+use crate::utils::auth::Scope;
+use crate::utils::network::{normalize_ip, parse_cidr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpRuleAction {
+    Allow,
+    Deny,
+}
+
+/// A rule as written in the `[[ip_rules]]` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct IpRuleConfig {
+    pub action: IpRuleAction,
+    pub cidrs: Vec<String>,
+    /// Logged whenever the rule decides a request
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Restrict the rule to these scopes. Rules without scopes apply to every request.
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone)]
+struct IpRule {
+    action: IpRuleAction,
+    nets: Vec<IpNet>,
+    reason: String,
+    scopes: Vec<Scope>,
+}
+
+impl IpRule {
+    fn applies_to(&self, scope: Option<Scope>) -> bool {
+        match scope {
+            None => self.scopes.is_empty(),
+            Some(scope) => self.scopes.contains(&scope),
+        }
+    }
+
+    fn matches(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpDecision {
+    Allowed,
+    Denied(String),
+}
+
+/// Ordered allow / deny rules. The first matching rule wins; when nothing matches,
+/// the address is denied if any allow rule applies and allowed otherwise.
+/// An unknown address matches no rule, so it is only allowed when no allow rule applies.
+#[derive(Debug, Clone, Default)]
+pub struct IpRules {
+    rules: Vec<IpRule>,
+}
+
+impl IpRules {
+    pub fn new(configs: &[IpRuleConfig]) -> Result<Self> {
+        let mut rules = Vec::with_capacity(configs.len());
+        for (index, config) in configs.iter().enumerate() {
+            let nets = config.cidrs.iter()
+                .map(|cidr| parse_cidr(cidr))
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("Invalid IP rule #{}", index + 1))?;
+            let reason = config.reason.clone()
+                .unwrap_or_else(|| format!("IP rule #{}", index + 1));
+            rules.push(IpRule {
+                action: config.action,
+                nets,
+                reason,
+                scopes: config.scopes.clone(),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluates the rules for `scope`, or the rules without scopes if `None`
+    pub fn evaluate(&self, ip: Option<IpAddr>, scope: Option<Scope>) -> IpDecision {
+        let ip = ip.map(normalize_ip);
+        let mut has_allow_rule = false;
+
+        for rule in self.rules.iter().filter(|rule| rule.applies_to(scope)) {
+            if ip.is_some_and(|ip| rule.matches(ip)) {
+                return match rule.action {
+                    IpRuleAction::Allow => IpDecision::Allowed,
+                    IpRuleAction::Deny => IpDecision::Denied(rule.reason.clone()),
+                };
+            }
+            has_allow_rule |= rule.action == IpRuleAction::Allow;
+        }
+
+        if has_allow_rule {
+            IpDecision::Denied("not matched by any allow rule".to_string())
+        } else {
+            IpDecision::Allowed
+        }
+    }
+
+    /// The scopes that scope-specific rules deny to `ip`, with the reason of the deciding rule
+    pub fn denied_scopes(&self, ip: Option<IpAddr>) -> Vec<(Scope, String)> {
+        Scope::ALL.iter()
+            .filter_map(|scope| match self.evaluate(ip, Some(*scope)) {
+                IpDecision::Allowed => None,
+                IpDecision::Denied(reason) => Some((*scope, reason)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(action: IpRuleAction, cidrs: &[&str], scopes: &[Scope]) -> IpRuleConfig {
+        IpRuleConfig {
+            action,
+            cidrs: cidrs.iter().map(|cidr| cidr.to_string()).collect(),
+            reason: None,
+            scopes: scopes.to_vec(),
+        }
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = IpRules::new(&[
+            rule(IpRuleAction::Deny, &["192.168.1.13"], &[]),
+            rule(IpRuleAction::Allow, &["192.168.1.0/24", "fd00::/8"], &[]),
+        ]).unwrap();
+
+        assert_eq!(rules.evaluate(ip("192.168.1.13"), None), IpDecision::Denied("IP rule #1".to_string()));
+        assert_eq!(rules.evaluate(ip("192.168.1.20"), None), IpDecision::Allowed);
+        assert_eq!(rules.evaluate(ip("fd12:3456::1"), None), IpDecision::Allowed);
+        assert!(matches!(rules.evaluate(ip("10.0.0.1"), None), IpDecision::Denied(_)));
+        assert!(matches!(rules.evaluate(ip("2001:db8::1"), None), IpDecision::Denied(_)));
+    }
+
+    #[test]
+    fn deny_only_rules_allow_everything_else() {
+        let rules = IpRules::new(&[rule(IpRuleAction::Deny, &["2001:db8::/32"], &[])]).unwrap();
+
+        assert!(matches!(rules.evaluate(ip("2001:db8::1"), None), IpDecision::Denied(_)));
+        assert_eq!(rules.evaluate(ip("2001:db9::1"), None), IpDecision::Allowed);
+        assert_eq!(rules.evaluate(ip("10.0.0.1"), None), IpDecision::Allowed);
+        assert_eq!(rules.evaluate(None, None), IpDecision::Allowed);
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_rules() {
+        let rules = IpRules::new(&[rule(IpRuleAction::Allow, &["10.0.0.0/8"], &[])]).unwrap();
+
+        assert_eq!(rules.evaluate(ip("::ffff:10.1.2.3"), None), IpDecision::Allowed);
+        assert!(matches!(rules.evaluate(ip("::ffff:11.1.2.3"), None), IpDecision::Denied(_)));
+    }
+
+    #[test]
+    fn ipv4_mapped_rules_match_ipv4_addresses() {
+        let rules = IpRules::new(&[rule(IpRuleAction::Allow, &["::ffff:10.0.0.0/104", "::ffff:192.168.1.5"], &[])]).unwrap();
+
+        assert_eq!(rules.evaluate(ip("10.1.2.3"), None), IpDecision::Allowed);
+        assert_eq!(rules.evaluate(ip("::ffff:10.1.2.3"), None), IpDecision::Allowed);
+        assert_eq!(rules.evaluate(ip("192.168.1.5"), None), IpDecision::Allowed);
+        assert!(matches!(rules.evaluate(ip("11.1.2.3"), None), IpDecision::Denied(_)));
+    }
+
+    #[test]
+    fn unknown_addresses_are_denied_when_an_allow_rule_applies() {
+        let rules = IpRules::new(&[
+            rule(IpRuleAction::Allow, &["127.0.0.1"], &[]),
+            rule(IpRuleAction::Allow, &["192.168.0.0/16"], &[Scope::Files]),
+        ]).unwrap();
+
+        assert!(matches!(rules.evaluate(None, None), IpDecision::Denied(_)));
+        let denied: Vec<Scope> = rules.denied_scopes(None).into_iter().map(|(scope, _)| scope).collect();
+        assert_eq!(denied, vec![Scope::Files]);
+    }
+
+    #[test]
+    fn scoped_rules_only_apply_to_their_scopes() {
+        let rules = IpRules::new(&[
+            rule(IpRuleAction::Allow, &["192.168.0.0/16"], &[Scope::Clipboard]),
+            rule(IpRuleAction::Deny, &["0.0.0.0/0", "::/0"], &[Scope::Callbacks]),
+        ]).unwrap();
+
+        assert_eq!(rules.evaluate(ip("10.0.0.1"), None), IpDecision::Allowed);
+        let denied: Vec<Scope> = rules.denied_scopes(ip("10.0.0.1")).into_iter().map(|(scope, _)| scope).collect();
+        assert_eq!(denied, vec![Scope::Callbacks, Scope::Clipboard]);
+        assert_eq!(rules.denied_scopes(ip("192.168.1.1")).len(), 1);
+    }
+}
@@ -1,2 +1,8 @@
 pub mod constants;
 pub mod auth;
+pub mod config;
+pub mod network;
+pub mod ip_rules;
+pub mod tls;
+pub mod signature;
+pub mod rate_limit;
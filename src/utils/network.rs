@@ -0,0 +1,191 @@
+use actix_web::{http::header::HeaderMap, HttpMessage, HttpRequest};
+use anyhow::{Context, Result};
+use ipnet::{IpNet, Ipv4Net};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The resolved address of the client, stored in the request extensions by `AuthMiddleware`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    pub fn from_request(req: &HttpRequest) -> Option<IpAddr> {
+        req.extensions().get::<ClientIp>().map(|client_ip| client_ip.0)
+    }
+}
+
+/// Parses a CIDR such as `100.64.0.0/10`. A bare address is treated as a single host.
+/// IPv4-mapped networks (`::ffff:a.b.c.d/n`) become IPv4 networks, like [`normalize_ip`] does for addresses.
+pub fn parse_cidr(value: &str) -> Result<IpNet> {
+    let value = value.trim();
+    if value.contains('/') {
+        let net = value.parse::<IpNet>()
+            .with_context(|| format!("Invalid CIDR: {}", value))?;
+        return Ok(match net {
+            IpNet::V6(v6) if v6.prefix_len() >= 96 => match v6.addr().to_ipv4_mapped() {
+                Some(v4) => IpNet::V4(Ipv4Net::new(v4, v6.prefix_len() - 96)?),
+                None => net,
+            },
+            _ => net,
+        });
+    }
+    let ip = value.parse::<IpAddr>()
+        .with_context(|| format!("Invalid IP address: {}", value))?;
+    Ok(IpNet::from(normalize_ip(ip)))
+}
+
+/// Unwraps IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) so they match IPv4 rules
+pub fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// True for addresses reachable on the internet. Loopback, private, link-local, shared (CGNAT),
+/// multicast and reserved ranges are not, so requests made on behalf of a payload cannot reach
+/// this machine or its network. IPv6 addresses embedding an IPv4 one (NAT64, 6to4 and
+/// IPv4-compatible) are judged by that address.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match normalize_ip(ip) {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b == 18 || b == 19)))
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            let octets = v6.octets();
+            let embedded = |at: usize| IpAddr::V4(Ipv4Addr::new(octets[at], octets[at + 1], octets[at + 2], octets[at + 3]));
+            match segments {
+                // NAT64 well-known prefix
+                [0x64, 0xff9b, 0, 0, 0, 0, _, _] => return is_public_ip(embedded(12)),
+                // 6to4
+                [0x2002, ..] => return is_public_ip(embedded(2)),
+                // IPv4-compatible, deprecated but still routed to the IPv4 address by some stacks
+                [0, 0, 0, 0, 0, 0, high, _] if high != 0 => return is_public_ip(embedded(12)),
+                _ => {}
+            }
+            let first = segments[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                // Link-local and the deprecated site-local fec0::/10
+                || (first & 0xffc0) == 0xfe80
+                || (first & 0xffc0) == 0xfec0
+                // NAT64 local-use prefix 64:ff9b:1::/48
+                || (first == 0x64 && segments[1] == 0xff9b)
+                // Teredo, whose embedded address is obfuscated, and documentation
+                || (first == 0x2001 && (segments[1] == 0 || segments[1] == 0xdb8)))
+        }
+    }
+}
+
+/// Determines the real client address, only trusting `X-Forwarded-For` when the
+/// connection comes from one of the configured proxies
+#[derive(Debug, Clone, Default)]
+pub struct ClientIpResolver {
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl ClientIpResolver {
+    pub fn new(trusted_proxies: &[String]) -> Result<Self> {
+        let trusted_proxies = trusted_proxies.iter()
+            .map(|proxy| parse_cidr(proxy))
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid trusted proxy")?;
+        Ok(Self { trusted_proxies })
+    }
+
+    pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        let ip = normalize_ip(ip);
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    pub fn resolve(&self, peer_addr: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer_ip = normalize_ip(peer_addr?.ip());
+        if !self.is_trusted_proxy(peer_ip) {
+            return Some(peer_ip);
+        }
+
+        // Walk the chain from the nearest hop and stop at the first address we do not trust.
+        // Anything left of that could have been supplied by the client itself.
+        let mut client_ip = peer_ip;
+        let forwarded: Vec<IpAddr> = headers.get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .map(normalize_ip)
+            .collect();
+
+        for hop in forwarded.into_iter().rev() {
+            client_ip = hop;
+            if !self.is_trusted_proxy(hop) {
+                break;
+            }
+        }
+        Some(client_ip)
+    }
+
+    pub fn resolve_request(&self, req: &HttpRequest) -> Option<IpAddr> {
+        self.resolve(req.peer_addr(), req.headers())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cidr_accepts_networks_and_hosts() {
+        assert_eq!(parse_cidr(" 100.64.0.0/10 ").unwrap(), "100.64.0.0/10".parse::<IpNet>().unwrap());
+        assert_eq!(parse_cidr("fd00::/8").unwrap(), "fd00::/8".parse::<IpNet>().unwrap());
+        assert_eq!(parse_cidr("192.168.1.5").unwrap(), "192.168.1.5/32".parse::<IpNet>().unwrap());
+        assert!(parse_cidr("192.168.1.0/33").is_err());
+        assert!(parse_cidr("not an address").is_err());
+    }
+
+    #[test]
+    fn public_addresses() {
+        let public = [
+            "8.8.8.8", "1.1.1.1", "2606:4700:4700::1111", "::ffff:8.8.4.4",
+            "64:ff9b::808:808", "2002:808:808::1", "::8.8.8.8",
+        ];
+        for ip in public {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} should be public", ip);
+        }
+
+        let internal = [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0",
+            "100.64.0.1", "198.18.0.1", "224.0.0.1", "255.255.255.255", "192.0.2.1",
+            "::1", "::", "fd00::1", "fe80::1", "ff02::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1",
+            "fec0::1", "64:ff9b::7f00:1", "64:ff9b::a9fe:a9fe", "64:ff9b:1::808:808", "2002:7f00:1::1",
+            "2002:c0a8:101::1", "::127.0.0.1", "::10.0.0.1", "2001:0:4136:e378::1", "2001:db8::1",
+        ];
+        for ip in internal {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+    }
+
+    #[test]
+    fn parse_cidr_converts_ipv4_mapped_networks() {
+        assert_eq!(parse_cidr("::ffff:10.0.0.0/104").unwrap(), "10.0.0.0/8".parse::<IpNet>().unwrap());
+        assert_eq!(parse_cidr("::ffff:192.168.1.5").unwrap(), "192.168.1.5/32".parse::<IpNet>().unwrap());
+        // Wider than the mapped range, so it stays an IPv6 network
+        assert_eq!(parse_cidr("::ffff:0:0/95").unwrap(), "::ffff:0:0/95".parse::<IpNet>().unwrap());
+    }
+}
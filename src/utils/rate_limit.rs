@@ -0,0 +1,364 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header,
+    web, Error, HttpMessage, HttpResponse,
+};
+use std::collections::HashMap;
+use std::future::{ready, Ready, Future};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::notifications::{NotificationRequest, Priority};
+use crate::services::NotificationManager;
+use crate::utils::auth::{ApiToken, AuthIdentity};
+use crate::utils::config::RateLimitConfig;
+use crate::utils::network::ClientIp;
+
+/// Shortest wait before the "suppressed" summary is shown, so a burst is collected into one toast
+const MIN_SUMMARY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+struct BucketLimit {
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl BucketLimit {
+    /// `None` when `per_minute` is 0, which disables the limit
+    fn new(per_minute: u32, burst: u32) -> Option<Self> {
+        if per_minute == 0 {
+            return None;
+        }
+        Some(Self {
+            capacity: burst.max(1) as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &BucketLimit, now: Instant) -> Self {
+        Self { tokens: limit.capacity, updated: now }
+    }
+
+    fn refill(&mut self, limit: &BucketLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_sec).min(limit.capacity);
+        self.updated = now;
+    }
+
+    /// How long until a token is available, `None` if one is available now
+    fn wait_time(&self, limit: &BucketLimit) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / limit.refill_per_sec))
+        }
+    }
+
+    fn is_full(&self, limit: &BucketLimit) -> bool {
+        self.tokens >= limit.capacity
+    }
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    senders: HashMap<String, TokenBucket>,
+    ips: HashMap<IpAddr, TokenBucket>,
+    global: Option<TokenBucket>,
+    /// Rejected requests per sender since the last summary toast
+    suppressed: HashMap<String, u32>,
+}
+
+/// Token buckets per authenticated sender, per client address and for the whole server
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    sender_limit: Option<BucketLimit>,
+    /// Per-token `rate_limit_per_minute`, replacing `sender_limit` for those senders
+    token_limits: HashMap<String, BucketLimit>,
+    ip_limit: Option<BucketLimit>,
+    global_limit: Option<BucketLimit>,
+    summarize_suppressed: bool,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            sender_limit: BucketLimit::new(config.sender_per_minute, config.sender_burst),
+            token_limits: HashMap::new(),
+            ip_limit: BucketLimit::new(config.ip_per_minute, config.ip_burst),
+            global_limit: BucketLimit::new(config.global_per_minute, config.global_burst),
+            summarize_suppressed: config.summarize_suppressed,
+            state: Arc::new(Mutex::new(LimiterState::default())),
+        }
+    }
+
+    /// Limits each token with a `rate_limit_per_minute` to that many requests a minute,
+    /// all of which may be made at once
+    pub fn with_token_limits(mut self, tokens: &[ApiToken]) -> Self {
+        self.token_limits = tokens.iter()
+            .filter_map(|token| {
+                let per_minute = token.rate_limit_per_minute?;
+                BucketLimit::new(per_minute, per_minute).map(|limit| (token.name.clone(), limit))
+            })
+            .collect();
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender_limit.is_some()
+            || !self.token_limits.is_empty()
+            || self.ip_limit.is_some()
+            || self.global_limit.is_some()
+    }
+
+    fn sender_limit(&self, sender: &str) -> Option<&BucketLimit> {
+        self.token_limits.get(sender).or(self.sender_limit.as_ref())
+    }
+
+    /// Takes a token from every applicable bucket, or none of them if any is empty.
+    /// On rejection returns how long the client should wait.
+    pub fn check(&self, sender: &str, ip: Option<IpAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        // Idle buckets are full again and can be forgotten
+        state.senders.retain(|sender, bucket| match self.sender_limit(sender) {
+            Some(limit) => {
+                bucket.refill(limit, now);
+                !bucket.is_full(limit)
+            }
+            None => false,
+        });
+        if let Some(limit) = &self.ip_limit {
+            state.ips.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                !bucket.is_full(limit)
+            });
+        }
+
+        let mut buckets: Vec<(&'static str, &BucketLimit, &mut TokenBucket)> = Vec::with_capacity(3);
+        if let Some(limit) = self.sender_limit(sender) {
+            let bucket = state.senders.entry(sender.to_string())
+                .or_insert_with(|| TokenBucket::full(limit, now));
+            buckets.push(("sender", limit, bucket));
+        }
+        if let (Some(limit), Some(ip)) = (&self.ip_limit, ip) {
+            let bucket = state.ips.entry(ip)
+                .or_insert_with(|| TokenBucket::full(limit, now));
+            buckets.push(("address", limit, bucket));
+        }
+        if let Some(limit) = &self.global_limit {
+            let bucket = state.global.get_or_insert_with(|| TokenBucket::full(limit, now));
+            bucket.refill(limit, now);
+            buckets.push(("global", limit, bucket));
+        }
+
+        let mut retry_after = None;
+        for (kind, limit, bucket) in &buckets {
+            if let Some(wait) = bucket.wait_time(limit) {
+                log::warn!("Rate limit exceeded for {} ({} limit, address {:?})", sender, kind, ip);
+                retry_after = retry_after.max(Some(wait));
+            }
+        }
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+
+        for (_, _, bucket) in buckets {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Counts a rejected request from `sender`, scheduling a summary toast for the first one
+    /// since the last summary
+    pub fn record_rejection(&self, sender: String, label: String, retry_after: Duration, manager: Arc<Mutex<NotificationManager>>) {
+        if self.summarize_suppressed && self.record_suppressed(&sender) {
+            self.schedule_summary(sender, label, retry_after, manager);
+        }
+    }
+
+    /// Counts a rejected request. Returns true for the first one since the last summary,
+    /// when the caller should schedule a new summary.
+    fn record_suppressed(&self, sender: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let count = state.suppressed.entry(sender.to_string()).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    fn take_suppressed(&self, sender: &str) -> u32 {
+        self.state.lock().unwrap().suppressed.remove(sender).unwrap_or(0)
+    }
+
+    /// Shows a single toast for everything suppressed from `sender` once its limit has refilled
+    fn schedule_summary(&self, sender: String, label: String, delay: Duration, manager: Arc<Mutex<NotificationManager>>) {
+        let limiter = self.clone();
+        actix_web::rt::spawn(async move {
+            actix_web::rt::time::sleep(delay.max(MIN_SUMMARY_DELAY)).await;
+            let count = limiter.take_suppressed(&sender);
+            if count == 0 {
+                return;
+            }
+
+            let plural = if count == 1 { "" } else { "s" };
+            let mut request = NotificationRequest::new(
+                "Notifications suppressed",
+                &format!("{} more notification{} from {} suppressed by the rate limit", count, plural, label),
+            );
+            request.sender = Some(label);
+            request.priority = Priority::Low;
+
+            let mut manager = manager.lock().unwrap();
+            if let Err(e) = manager.send_notification(request).await {
+                log::error!("Failed to send rate limit summary: {}", e);
+            }
+        });
+    }
+}
+
+/// Rejects requests over the configured rates with `429`. Wrap it inside `AuthMiddleware`
+/// so the authenticated identity is known.
+pub struct RateLimitMiddleware {
+    limiter: RateLimiter,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimitMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddlewareService {
+            service,
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddlewareService<S> {
+    service: S,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !self.limiter.is_enabled() {
+            return Box::pin(self.service.call(req));
+        }
+
+        let identity = req.extensions().get::<AuthIdentity>()
+            .map(|identity| (identity.name.clone(), identity.sender.clone().unwrap_or_else(|| identity.name.clone())));
+        let (sender, label) = match identity {
+            Some(identity) => identity,
+            None => return Box::pin(self.service.call(req)),
+        };
+        let ip = req.extensions().get::<ClientIp>().map(|client_ip| client_ip.0);
+
+        let retry_after = match self.limiter.check(&sender, ip) {
+            Ok(()) => return Box::pin(self.service.call(req)),
+            Err(retry_after) => retry_after,
+        };
+
+        if let Some(manager) = req.app_data::<web::Data<Arc<Mutex<NotificationManager>>>>() {
+            self.limiter.record_rejection(sender, label, retry_after, Arc::clone(manager));
+        }
+
+        let retry_after = retry_after.as_secs().max(1);
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .body("Rate limit exceeded");
+        Box::pin(async move { Err(InternalError::from_response("Rate limit exceeded", response).into()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(name: &str, rate_limit_per_minute: Option<u32>) -> ApiToken {
+        ApiToken {
+            name: name.to_string(),
+            hash: String::new(),
+            scopes: Vec::new(),
+            sender: None,
+            rate_limit_per_minute,
+        }
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let limiter = RateLimiter::new(&RateLimitConfig::default());
+        assert!(!limiter.is_enabled());
+        for _ in 0..1000 {
+            assert!(limiter.check("ci", None).is_ok());
+        }
+    }
+
+    #[test]
+    fn token_limits_replace_the_sender_limit() {
+        let config = RateLimitConfig { sender_per_minute: 60, sender_burst: 5, ..Default::default() };
+        let limiter = RateLimiter::new(&config)
+            .with_token_limits(&[token("ci", Some(2)), token("unlimited", None)]);
+
+        assert!(limiter.check("ci", None).is_ok());
+        assert!(limiter.check("ci", None).is_ok());
+        let retry_after = limiter.check("ci", None).unwrap_err();
+        assert!(retry_after > Duration::from_secs(25) && retry_after <= Duration::from_secs(30));
+
+        // Tokens without their own limit share the configured sender limit
+        for _ in 0..5 {
+            assert!(limiter.check("unlimited", None).is_ok());
+        }
+        assert!(limiter.check("unlimited", None).is_err());
+    }
+
+    #[test]
+    fn token_limits_work_without_a_sender_limit() {
+        let limiter = RateLimiter::new(&RateLimitConfig::default()).with_token_limits(&[token("ci", Some(1))]);
+        assert!(limiter.is_enabled());
+        assert!(limiter.check("ci", None).is_ok());
+        assert!(limiter.check("ci", None).is_err());
+        assert!(limiter.check("other", None).is_ok());
+        assert!(limiter.check("other", None).is_ok());
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::utils::auth::Scope;
+
+pub const SIGNATURE_HEADER: &str = "x-signature-256";
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A sender that signs its requests with a shared secret, configured in `[[hmac.senders]]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HmacSender {
+    pub name: String,
+    /// The shared secret. Prefer `secret_env` to keep it out of the config file.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Name of an environment variable holding the shared secret
+    #[serde(default)]
+    pub secret_env: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+    /// Shown as attribution on toasts sent by this sender
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ResolvedSender {
+    config: HmacSender,
+    secret: Vec<u8>,
+}
+
+/// Verifies `X-Signature-256: sha256=<hex>` headers, computed as
+/// HMAC-SHA256(secret, "<timestamp>.<raw body>") with the timestamp taken from
+/// `X-Signature-Timestamp` in unix seconds
+#[derive(Debug, Clone, Default)]
+pub struct SignatureVerifier {
+    senders: Vec<ResolvedSender>,
+    replay_window: Duration,
+    /// Decoded MACs, so a signature cannot be replayed by changing the case of its hex digits
+    seen_signatures: Arc<Mutex<HashMap<Vec<u8>, Instant>>>,
+}
+
+impl SignatureVerifier {
+    pub fn new(senders: &[HmacSender], replay_window_secs: u64) -> Result<Self> {
+        let senders = senders.iter()
+            .map(|sender| {
+                let secret = match (&sender.secret, &sender.secret_env) {
+                    (_, Some(env)) => std::env::var(env)
+                        .map_err(|_| anyhow!("Environment variable {} for HMAC sender {} is not set", env, sender.name))?,
+                    (Some(secret), None) => secret.clone(),
+                    (None, None) => return Err(anyhow!("HMAC sender {} has no secret", sender.name)),
+                };
+                Ok(ResolvedSender { config: sender.clone(), secret: secret.into_bytes() })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            senders,
+            replay_window: Duration::from_secs(replay_window_secs),
+            seen_signatures: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.senders.is_empty()
+    }
+
+    /// Rejects malformed signatures and stale timestamps, so the body only has to be read
+    /// for requests that can still verify
+    pub fn check_headers(&self, signature: &str, timestamp: &str) -> Result<()> {
+        self.parse_headers(signature, timestamp).map(|_| ())
+    }
+
+    fn parse_headers(&self, signature: &str, timestamp: &str) -> Result<(Vec<u8>, u64)> {
+        let expected = signature.trim().strip_prefix(SIGNATURE_PREFIX)
+            .and_then(|hex| hex::decode(hex).ok())
+            .ok_or_else(|| anyhow!("Malformed signature"))?;
+
+        let timestamp: u64 = timestamp.trim().parse()
+            .map_err(|_| anyhow!("Malformed signature timestamp"))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if now.abs_diff(timestamp) > self.replay_window.as_secs() {
+            return Err(anyhow!("Signature timestamp is outside the replay window"));
+        }
+        Ok((expected, timestamp))
+    }
+
+    /// Returns the sender whose secret produced `signature`, rejecting stale timestamps and replays
+    pub fn verify(&self, signature: &str, timestamp: &str, body: &[u8]) -> Result<&HmacSender> {
+        let (expected, timestamp) = self.parse_headers(signature, timestamp)?;
+
+        // Check every sender so timing does not reveal which secret matched
+        let mut matched = None;
+        for sender in &self.senders {
+            if verify_signature(&sender.secret, timestamp, body, &expected) && matched.is_none() {
+                matched = Some(&sender.config);
+            }
+        }
+        let sender = matched.ok_or_else(|| anyhow!("Invalid signature"))?;
+
+        let mut seen_signatures = self.seen_signatures.lock().unwrap();
+        let now = Instant::now();
+        seen_signatures.retain(|_, seen| now.duration_since(*seen) <= self.replay_window * 2);
+        if seen_signatures.insert(expected, now).is_some() {
+            return Err(anyhow!("Signature has already been used"));
+        }
+
+        Ok(sender)
+    }
+}
+
+/// Constant-time check of a signature over `<timestamp>.<body>`
+pub fn verify_signature(secret: &[u8], timestamp: u64, body: &[u8], signature: &[u8]) -> bool {
+    let mut mac = match HmacSha256::new_from_slice(secret) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// Constant-time check of a `sha256=<hex>` signature over the raw body, the scheme GitHub
+/// uses for `X-Hub-Signature-256`
+pub fn verify_body_signature(secret: &[u8], signature: &str, body: &[u8]) -> bool {
+    let expected = match signature.trim().strip_prefix(SIGNATURE_PREFIX).and_then(|hex| hex::decode(hex).ok()) {
+        Some(expected) => expected,
+        None => return false,
+    };
+    let mut mac = match HmacSha256::new_from_slice(secret) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from GitHub's webhook documentation
+    const SECRET: &[u8] = b"It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn body_signature_accepts_a_valid_signature() {
+        assert!(verify_body_signature(SECRET, SIGNATURE, BODY));
+        assert!(verify_body_signature(SECRET, &format!(" {} ", SIGNATURE), BODY));
+    }
+
+    #[test]
+    fn body_signature_rejects_invalid_signatures() {
+        assert!(!verify_body_signature(b"another secret", SIGNATURE, BODY));
+        assert!(!verify_body_signature(SECRET, SIGNATURE, b"Hello, World?"));
+        assert!(!verify_body_signature(SECRET, &SIGNATURE.replace("sha256=", "sha1="), BODY));
+        assert!(!verify_body_signature(SECRET, "sha256=not-hex", BODY));
+        assert!(!verify_body_signature(SECRET, &SIGNATURE[..SIGNATURE.len() - 2], BODY));
+    }
+
+    #[test]
+    fn body_signature_rejects_a_missing_signature() {
+        assert!(!verify_body_signature(SECRET, "", BODY));
+        assert!(!verify_body_signature(SECRET, "sha256=", BODY));
+    }
+
+    fn verifier() -> SignatureVerifier {
+        let sender = HmacSender {
+            name: "ci".to_string(),
+            secret: Some("shared secret".to_string()),
+            secret_env: None,
+            scopes: vec![Scope::Notify],
+            sender: None,
+        };
+        SignatureVerifier::new(&[sender], 300).unwrap()
+    }
+
+    fn sign(timestamp: u64, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(b"shared secret").unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn verify_accepts_a_fresh_signature() {
+        let verifier = verifier();
+        let timestamp = now();
+        let sender = verifier.verify(&sign(timestamp, BODY), &timestamp.to_string(), BODY).unwrap();
+        assert_eq!(sender.name, "ci");
+
+        let other = now() - 10;
+        assert!(verifier.verify(&sign(other, BODY), &other.to_string(), b"tampered").is_err());
+        assert!(verifier.verify(&sign(other, BODY), &(other + 1).to_string(), BODY).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_timestamps_outside_the_window() {
+        let verifier = verifier();
+        for timestamp in [now() - 301, now() + 301] {
+            let signature = sign(timestamp, BODY);
+            assert!(verifier.check_headers(&signature, &timestamp.to_string()).is_err());
+            assert!(verifier.verify(&signature, &timestamp.to_string(), BODY).is_err());
+        }
+        let timestamp = now() - 290;
+        assert!(verifier.verify(&sign(timestamp, BODY), &timestamp.to_string(), BODY).is_ok());
+        assert!(verifier.check_headers(&sign(timestamp, BODY), "yesterday").is_err());
+    }
+
+    #[test]
+    fn verify_rejects_replays() {
+        let verifier = verifier();
+        let timestamp = now();
+        let signature = sign(timestamp, BODY);
+        assert!(verifier.verify(&signature, &timestamp.to_string(), BODY).is_ok());
+        assert!(verifier.verify(&signature, &timestamp.to_string(), BODY).is_err());
+
+        // Uppercase hex decodes to the same MAC
+        let uppercase = format!("sha256={}", signature["sha256=".len()..].to_uppercase());
+        assert!(verifier.verify(&uppercase, &timestamp.to_string(), BODY).is_err());
+        assert!(verifier.verify(&format!(" {}", signature), &timestamp.to_string(), BODY).is_err());
+    }
+}
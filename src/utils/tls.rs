@@ -0,0 +1,268 @@
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream};
+use anyhow::{anyhow, Context, Result};
+use rcgen::{CertificateParams, DnType, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::fs;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use x509_parser::extensions::GeneralName;
+
+use crate::utils::constants::APP_DISPLAY_NAME;
+
+const SELF_SIGNED_CERT_FILE: &str = "cert.pem";
+const SELF_SIGNED_KEY_FILE: &str = "key.pem";
+
+/// Where `--tls-self-signed` keeps its certificate when `--tls-dir` is not given
+pub fn default_tls_dir() -> PathBuf {
+    std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("NotificationServer")
+        .join("tls")
+}
+
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open certificate {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificate {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open private key {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse private key {}", path.display()))?
+        .ok_or_else(|| anyhow!("No private key found in {}", path.display()))
+}
+
+/// Builds the TLS configuration. With `client_ca`, client certificates signed by that bundle
+/// are requested and verified, but clients without one may still connect and use other auth.
+pub fn load_server_config(cert_path: &Path, key_path: &Path, client_ca: Option<&Path>) -> Result<rustls::ServerConfig> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?;
+
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots.add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", client_ca.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    builder.with_single_cert(certs, key)
+        .context("Invalid TLS certificate or key")
+}
+
+/// Names from a verified client certificate, stored as connection data for `AuthMiddleware`
+#[derive(Debug, Clone)]
+pub struct PeerCertificate {
+    pub common_name: Option<String>,
+    /// DNS names, e-mail addresses, URIs and IP addresses from the subject alternative name extension
+    pub subject_alt_names: Vec<String>,
+}
+
+impl PeerCertificate {
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| anyhow!("Failed to parse client certificate: {}", e))?;
+
+        let common_name = cert.subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+
+        let mut subject_alt_names = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => {
+                        subject_alt_names.push(name.to_string());
+                    }
+                    GeneralName::IPAddress(bytes) => {
+                        let ip = match bytes.len() {
+                            4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                            16 => <[u8; 16]>::try_from(*bytes).ok().map(IpAddr::from),
+                            _ => None,
+                        };
+                        if let Some(ip) = ip {
+                            subject_alt_names.push(ip.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self { common_name, subject_alt_names })
+    }
+}
+
+/// `HttpServer::on_connect` callback recording the client certificate of TLS connections.
+/// The certificate has already been verified against the client CA bundle by rustls.
+pub fn extract_peer_certificate(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(tls_stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        let (_, session) = tls_stream.get_ref();
+        if let Some(cert) = session.peer_certificates().and_then(|certs| certs.first()) {
+            match PeerCertificate::from_der(cert.as_ref()) {
+                Ok(peer_certificate) => {
+                    log::debug!("Client presented certificate {:?}", peer_certificate);
+                    data.insert(peer_certificate);
+                }
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+    }
+}
+
+/// Whether two subject alternative names are the same, comparing addresses by value
+fn same_name(a: &str, b: &str) -> bool {
+    match (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.eq_ignore_ascii_case(b),
+    }
+}
+
+/// Names of `subject_alt_names` the stored certificate at `cert_path` is not valid for
+fn missing_names(cert_path: &Path, subject_alt_names: &[String]) -> Result<Vec<String>> {
+    let certs = load_certs(cert_path)?;
+    let stored = PeerCertificate::from_der(certs[0].as_ref())?.subject_alt_names;
+    Ok(subject_alt_names.iter()
+        .filter(|name| !stored.iter().any(|stored| same_name(stored, name)))
+        .cloned()
+        .collect())
+}
+
+/// Returns the certificate and key in `dir`, generating a self-signed pair for
+/// `subject_alt_names` the first time so clients can keep pinning the same fingerprint.
+/// A stored certificate missing any of the names is replaced, which changes the fingerprint.
+pub fn ensure_self_signed(dir: &Path, subject_alt_names: Vec<String>) -> Result<(PathBuf, PathBuf)> {
+    let cert_path = dir.join(SELF_SIGNED_CERT_FILE);
+    let key_path = dir.join(SELF_SIGNED_KEY_FILE);
+    if cert_path.exists() && key_path.exists() {
+        let missing = missing_names(&cert_path, &subject_alt_names)?;
+        if missing.is_empty() {
+            log::info!("Using existing self-signed certificate in {}", dir.display());
+            return Ok((cert_path, key_path));
+        }
+        log::warn!("The self-signed certificate in {} does not cover {:?}, generating a new one with a new fingerprint", dir.display(), missing);
+    }
+
+    log::info!("Generating self-signed certificate for {:?}", subject_alt_names);
+    let mut params = CertificateParams::new(subject_alt_names)
+        .context("Invalid subject alternative name")?;
+    params.distinguished_name.push(DnType::CommonName, APP_DISPLAY_NAME);
+    params.not_after = rcgen::date_time_ymd(2049, 12, 31);
+
+    let key_pair = KeyPair::generate().context("Failed to generate key pair")?;
+    let cert = params.self_signed(&key_pair).context("Failed to generate certificate")?;
+
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(&cert_path, cert.pem())
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    fs::write(&key_path, key_pair.serialize_pem())
+        .with_context(|| format!("Failed to write {}", key_path.display()))?;
+
+    Ok((cert_path, key_path))
+}
+
+/// SHA-256 fingerprint of the first certificate in a PEM file, as colon separated hex
+pub fn fingerprint(cert_path: &Path) -> Result<String> {
+    let certs = load_certs(cert_path)?;
+    let digest = Sha256::digest(certs[0].as_ref());
+    Ok(digest.iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{SanType, Ia5String};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("tls_test_{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn reads_names_from_client_certificates() {
+        let mut params = CertificateParams::new(names(&["laptop.lan", "10.0.0.7", "fe80::1"])).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "laptop");
+        params.subject_alt_names.push(SanType::Rfc822Name(Ia5String::try_from("me@home.lan").unwrap()));
+        params.subject_alt_names.push(SanType::URI(Ia5String::try_from("spiffe://home/laptop").unwrap()));
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let peer = PeerCertificate::from_der(cert.der()).unwrap();
+        assert_eq!(peer.common_name.as_deref(), Some("laptop"));
+        assert_eq!(peer.subject_alt_names, names(&["laptop.lan", "10.0.0.7", "fe80::1", "me@home.lan", "spiffe://home/laptop"]));
+    }
+
+    #[test]
+    fn reads_certificates_without_names() {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+        let peer = PeerCertificate::from_der(cert.der()).unwrap();
+        assert_eq!(peer.common_name, None);
+        assert!(peer.subject_alt_names.is_empty());
+
+        assert!(PeerCertificate::from_der(b"not a certificate").is_err());
+    }
+
+    #[test]
+    fn compares_names() {
+        assert!(same_name("Desktop.LAN", "desktop.lan"));
+        assert!(same_name("::1", "0:0:0:0:0:0:0:1"));
+        assert!(!same_name("127.0.0.1", "localhost"));
+    }
+
+    #[test]
+    fn reuses_the_certificate_until_names_are_added() {
+        let dir = temp_dir();
+        let (cert_path, _) = ensure_self_signed(&dir, names(&["localhost", "127.0.0.1"])).unwrap();
+        let original = fingerprint(&cert_path).unwrap();
+
+        // The same names, or fewer, keep the pinned fingerprint
+        ensure_self_signed(&dir, names(&["LOCALHOST", "127.0.0.1"])).unwrap();
+        ensure_self_signed(&dir, names(&["localhost"])).unwrap();
+        assert_eq!(fingerprint(&cert_path).unwrap(), original);
+
+        ensure_self_signed(&dir, names(&["localhost", "127.0.0.1", "192.168.1.20"])).unwrap();
+        assert_ne!(fingerprint(&cert_path).unwrap(), original);
+        assert!(missing_names(&cert_path, &names(&["localhost", "127.0.0.1", "192.168.1.20"])).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}